@@ -0,0 +1,98 @@
+use std::sync::atomic::Ordering;
+
+use axum::{extract::State, http::StatusCode, response::IntoResponse, routing::post, Router};
+use serde::Serialize;
+
+use crate::{response::success_response, state::AppState};
+
+#[derive(Serialize)]
+struct LoggingResBody {
+    paused: bool,
+}
+
+// responses are still served while paused, only recording them to the history is skipped
+pub fn route_admin_to(app: Router<AppState>) -> Router<AppState> {
+    app.route("/admin/logging/pause", post(pause_logging))
+        .route("/admin/logging/resume", post(resume_logging))
+}
+
+async fn pause_logging(State(state): State<AppState>) -> impl IntoResponse {
+    set_logging_paused(&state, true)
+}
+
+async fn resume_logging(State(state): State<AppState>) -> impl IntoResponse {
+    set_logging_paused(&state, false)
+}
+
+fn set_logging_paused(state: &AppState, paused: bool) -> impl IntoResponse {
+    state.logging_paused.store(paused, Ordering::Relaxed);
+    success_response(StatusCode::OK, LoggingResBody { paused })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{
+        method::Method,
+        mock_endpoint::testutil::endpoint,
+        request_logger::testutil::new_logger,
+        response_handler::{ResponseHandler, StaticResponse},
+    };
+    use axum_test::TestServer;
+    use pretty_assertions::assert_eq;
+    use serde_json::json;
+
+    #[tokio::test]
+    async fn requests_are_not_logged_while_paused() {
+        let logger = new_logger().await;
+        logger.create_session("mysession").await.unwrap();
+        let state = AppState::new(logger);
+        let endpoint = endpoint(
+            Method::Get,
+            "/hello",
+            ResponseHandler::Static(StaticResponse {
+                status: 200,
+                ..Default::default()
+            }),
+        );
+        let app = endpoint
+            .route_to(route_admin_to(Router::new()))
+            .with_state(state.clone());
+        let server = TestServer::new(app).unwrap();
+
+        server
+            .get("/mock/mysession/hello")
+            .add_query_param("n", "1")
+            .await;
+        let paused = server.post("/admin/logging/pause").await;
+        let served = server
+            .get("/mock/mysession/hello")
+            .add_query_param("n", "2")
+            .await;
+        let resumed = server.post("/admin/logging/resume").await;
+        server
+            .get("/mock/mysession/hello")
+            .add_query_param("n", "3")
+            .await;
+
+        assert_eq!(
+            (
+                (StatusCode::OK, json!({ "paused": true })),
+                StatusCode::OK,
+                (StatusCode::OK, json!({ "paused": false })),
+            ),
+            (
+                (paused.status_code(), paused.json()),
+                served.status_code(),
+                (resumed.status_code(), resumed.json()),
+            )
+        );
+        let logs = state.logger.get_session_history("mysession").await.unwrap();
+        assert_eq!(
+            vec!["1", "3"],
+            logs.iter()
+                .map(|log| log.query["n"].as_str())
+                .collect::<Vec<_>>()
+        );
+    }
+}
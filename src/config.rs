@@ -1,50 +1,1193 @@
+use std::{fmt, fs, io::Read, path::Path};
+
+use axum::{body::Bytes, http::header};
+use base64::{prelude::BASE64_STANDARD, Engine};
+use chrono::{DateTime, Utc};
 use indexmap::IndexMap;
-use serde::Deserialize;
+use once_cell::sync::Lazy;
+use regex::Regex;
+use serde::{
+    de::{
+        value::{MapAccessDeserializer, SeqAccessDeserializer},
+        MapAccess, SeqAccess, Visitor,
+    },
+    Deserialize, Deserializer, Serialize,
+};
+use serde_yaml::value::{Tag, TaggedValue};
 
-use crate::{method::Method, mock_endpoint::MockEndpoint};
+use crate::{
+    method::Method,
+    mock_endpoint::{route_paths, MockEndpoint},
+    response_handler::{
+        BodySize, Cookie, EmptyBody, ExpectContinue, Fill, LatencyDistribution, ResponseHandler,
+        SameSite, StaticResponse, Strategy, TimeWindow,
+    },
+};
 
 #[derive(Deserialize)]
 struct Config {
-    pub paths: IndexMap<String, IndexMap<Method, EndpointConfig>>,
+    pub paths: IndexMap<String, IndexMap<Method, EndpointConfigs>>,
+    pub default_content_type: Option<String>,
+    #[serde(default)]
+    pub sessions: Vec<String>,
+    #[serde(default)]
+    pub default_status: IndexMap<Method, u16>,
+    pub correlation_header: Option<String>,
+}
+
+#[derive(PartialEq, Debug)]
+pub struct ServerConfig {
+    pub endpoints: Vec<MockEndpoint>,
+    pub default_content_type: Option<String>,
+    /// Sessions created at startup.
+    pub sessions: Vec<String>,
+    /// Header whose value is logged as the correlation id of each request.
+    pub correlation_header: Option<String>,
+}
+
+impl Config {
+    // the unknown keys in responses, which are hidden from the deserializer of the whole config
+    fn response_unknown_keys(&self) -> Vec<String> {
+        let mut unknown_keys = vec![];
+        for (path, methods) in &self.paths {
+            for (method, EndpointConfigs { endpoints, listed }) in methods {
+                for (i, endpoint) in endpoints.iter().enumerate() {
+                    let prefix = if *listed {
+                        format!("paths.{}.{}.{}.response", path, method, i)
+                    } else {
+                        format!("paths.{}.{}.response", path, method)
+                    };
+                    let ResponseVariant(_, keys) = &endpoint.response;
+                    unknown_keys.extend(keys.iter().map(|key| format!("{}.{}", prefix, key)));
+                }
+            }
+        }
+        unknown_keys
+    }
 }
 
 #[derive(Deserialize)]
 struct EndpointConfig {
-    pub response: ResponseConfig,
+    pub host: Option<String>,
+    pub name: Option<String>,
+    pub response: ResponseVariant,
+}
+
+// a method is given an endpoint, or a list of endpoints told apart by `host`
+struct EndpointConfigs {
+    endpoints: Vec<EndpointConfig>,
+    // given as a list, in which case the keys of the endpoints are prefixed with their index
+    listed: bool,
+}
+
+impl<'de> Deserialize<'de> for EndpointConfigs {
+    fn deserialize<D>(deserializer: D) -> Result<Self, D::Error>
+    where
+        D: Deserializer<'de>,
+    {
+        struct EndpointConfigsVisitor;
+
+        impl<'de> Visitor<'de> for EndpointConfigsVisitor {
+            type Value = EndpointConfigs;
+
+            fn expecting(&self, formatter: &mut fmt::Formatter) -> fmt::Result {
+                formatter.write_str("an endpoint or a sequence of endpoints")
+            }
+
+            fn visit_map<A: MapAccess<'de>>(self, map: A) -> Result<Self::Value, A::Error> {
+                EndpointConfig::deserialize(MapAccessDeserializer::new(map)).map(|endpoint| {
+                    EndpointConfigs {
+                        endpoints: vec![endpoint],
+                        listed: false,
+                    }
+                })
+            }
+
+            fn visit_seq<A: SeqAccess<'de>>(self, seq: A) -> Result<Self::Value, A::Error> {
+                Vec::deserialize(SeqAccessDeserializer::new(seq)).map(|endpoints| EndpointConfigs {
+                    endpoints,
+                    listed: true,
+                })
+            }
+        }
+
+        deserializer.deserialize_any(EndpointConfigsVisitor)
+    }
 }
 
+// read only once per endpoint at startup, so the size of the static variant does not matter
+#[allow(clippy::large_enum_variant)]
 #[derive(Deserialize)]
-struct ResponseConfig {
-    pub status: u16,
-    pub headers: Option<IndexMap<String, String>>,
-    pub body: String,
+#[serde(rename_all = "snake_case")]
+enum ResponseConfig {
+    Static {
+        status: Option<u16>,
+        headers: Option<IndexMap<String, String>>,
+        body: Option<String>,
+        #[serde(default, deserialize_with = "deserialize_optional_base64")]
+        body_base64: Option<Vec<u8>>,
+        last_modified: Option<DateTime<Utc>>,
+        chunk_size: Option<usize>,
+        #[serde(default)]
+        connection_close: bool,
+        delay_ms: Option<u64>,
+        #[serde(default)]
+        expect_continue: ExpectContinue,
+        #[serde(default)]
+        drain_body: bool,
+        latency: Option<LatencyDistribution>,
+        reset_after: Option<usize>,
+        #[serde(default)]
+        empty_body: EmptyBody,
+        #[serde(default)]
+        status_bodies: IndexMap<u16, String>,
+        #[serde(default, deserialize_with = "deserialize_base64_values")]
+        status_bodies_base64: IndexMap<u16, Vec<u8>>,
+        www_authenticate: Option<String>,
+        #[serde(default)]
+        cookies: Vec<Cookie>,
+    },
+    File {
+        path: String,
+    },
+    Dir {
+        root: String,
+    },
+    Hangup {
+        delay_ms: u64,
+    },
+    Truncated {
+        status: u16,
+        content_length: usize,
+        body: String,
+    },
+    StatusReflect {
+        status_param: String,
+        default: u16,
+        #[serde(default)]
+        statuses: IndexMap<String, u16>,
+    },
+    QueryEcho {
+        param: String,
+    },
+    Sticky {
+        header: String,
+        variants: Vec<ResponseVariant>,
+    },
+    ProxyPool {
+        targets: Vec<String>,
+        #[serde(default)]
+        strategy: Strategy,
+        cache_ttl_ms: Option<u64>,
+    },
+    CountGated {
+        threshold: usize,
+        before: Box<ResponseVariant>,
+        after: Box<ResponseVariant>,
+    },
+    Raw {
+        status: u16,
+        headers: Option<IndexMap<String, String>>,
+        body: String,
+    },
+    GrpcWeb {
+        #[serde(deserialize_with = "deserialize_base64")]
+        message: Vec<u8>,
+        #[serde(default)]
+        grpc_status: u32,
+        grpc_message: Option<String>,
+    },
+    RandomBytes {
+        size: usize,
+        #[serde(default)]
+        fill: Fill,
+        content_type: Option<String>,
+        seed: Option<u64>,
+    },
+    RetryUntilSuccess {
+        #[serde(default = "default_idempotency_header")]
+        header: String,
+        success_after: usize,
+        #[serde(default = "default_pending_status")]
+        pending_status: u16,
+        response: Box<ResponseVariant>,
+    },
+    MergePatch {
+        #[serde(default = "default_merge_patch_document")]
+        initial: serde_json::Value,
+    },
+    Resource {
+        collection: String,
+    },
+    TimeWindows {
+        windows: Vec<TimeWindowConfig>,
+        default: Box<ResponseVariant>,
+    },
+    PerSession {
+        per_session_overrides: IndexMap<String, ResponseVariant>,
+        default: Box<ResponseVariant>,
+    },
+    PartialContent {
+        body: String,
+        content_type: Option<String>,
+    },
+    Once {
+        first: Box<ResponseVariant>,
+        then: Box<ResponseVariant>,
+        #[serde(default)]
+        per_session: bool,
+    },
+    ByCookie {
+        cookie: String,
+        responses: IndexMap<String, ResponseVariant>,
+        default: Box<ResponseVariant>,
+    },
+    Idempotent {
+        #[serde(default = "default_idempotency_header")]
+        header: String,
+        #[serde(default = "default_idempotency_ttl_ms")]
+        ttl_ms: u64,
+        response: Box<ResponseVariant>,
+    },
+    ByBodySize {
+        sizes: Vec<BodySizeConfig>,
+        default: Box<ResponseVariant>,
+    },
+    Error {
+        status: u16,
+        message: String,
+    },
 }
 
-pub fn parse_config(src: &str) -> Result<Vec<MockEndpoint>, String> {
-    serde_yaml::from_str::<Config>(src)
-        .map_err(|e| e.to_string())
-        .map(|config| {
-            config
-                .paths
+fn default_idempotency_header() -> String {
+    "idempotency-key".to_string()
+}
+
+// a day, which is how long idempotency keys are commonly honored
+fn default_idempotency_ttl_ms() -> u64 {
+    24 * 60 * 60 * 1000
+}
+
+fn default_pending_status() -> u16 {
+    503
+}
+
+fn default_merge_patch_document() -> serde_json::Value {
+    serde_json::Value::Object(Default::default())
+}
+
+#[derive(Deserialize)]
+struct TimeWindowConfig {
+    #[serde(flatten)]
+    window: TimeWindow,
+    response: ResponseVariant,
+}
+
+#[derive(Deserialize)]
+struct BodySizeConfig {
+    #[serde(flatten)]
+    size: BodySize,
+    response: ResponseVariant,
+}
+
+// a response along with the keys in it, including nested responses, which are not in the schema
+struct ResponseVariant(ResponseConfig, Vec<String>);
+
+impl<'de> Deserialize<'de> for ResponseVariant {
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        deserialize_response(deserializer)
+    }
+}
+
+// `type` can be omitted for backward compatibility, in which case the response is static.
+// The response is rewritten into `!type {...fields}` and read as an externally tagged enum, as
+// internally tagged ones buffer the fields and hide unknown keys from the strict mode
+fn deserialize_response<'de, D: Deserializer<'de>>(
+    deserializer: D,
+) -> Result<ResponseVariant, D::Error> {
+    let value = match serde_yaml::Value::deserialize(deserializer)? {
+        serde_yaml::Value::Mapping(mut fields) => {
+            let tag = match fields.remove("type") {
+                Some(tag) => String::deserialize(tag).map_err(serde::de::Error::custom)?,
+                None => "static".to_string(),
+            };
+            serde_yaml::Value::Tagged(Box::new(TaggedValue {
+                tag: Tag::new(tag),
+                value: serde_yaml::Value::Mapping(fields),
+            }))
+        }
+        value => value,
+    };
+    parse_response(value).map_err(serde::de::Error::custom)
+}
+
+// not generic over the deserializer, as nested responses would instantiate it recursively.
+// Unknown keys are kept rather than rejected, as only the whole config knows where they are
+fn parse_response(value: serde_yaml::Value) -> Result<ResponseVariant, String> {
+    let mut unknown_keys = vec![];
+    let response: ResponseConfig =
+        serde_ignored::deserialize(value, |path| unknown_keys.push(path.to_string()))
+            .map_err(|e: serde_yaml::Error| e.to_string())?;
+    validate_body(&response)
+        .and_then(|_| validate_www_authenticate(&response))
+        .and_then(|_| validate_cookies(&response))?;
+    unknown_keys.extend(response.nested_unknown_keys());
+    Ok(ResponseVariant(response, unknown_keys))
+}
+
+fn validate_body(response: &ResponseConfig) -> Result<(), String> {
+    match response {
+        ResponseConfig::Static {
+            body: None,
+            body_base64: None,
+            ..
+        } => Err("missing field `body`".to_string()),
+        ResponseConfig::Static {
+            body: Some(_),
+            body_base64: Some(_),
+            ..
+        } => Err("body and body_base64 are mutually exclusive".to_string()),
+        ResponseConfig::Static {
+            status_bodies,
+            status_bodies_base64,
+            ..
+        } => match status_bodies
+            .keys()
+            .find(|status| status_bodies_base64.contains_key(*status))
+        {
+            Some(status) => Err(format!(
+                "status_bodies and status_bodies_base64 both have {}",
+                status
+            )),
+            None => Ok(()),
+        },
+        _ => Ok(()),
+    }
+}
+
+// a single challenge of RFC 7235: a scheme followed by a token68 or by comma-separated parameters
+static CHALLENGE_REGEX: Lazy<Regex> = Lazy::new(|| {
+    let token = r"[!#$%&'*+.^_`|~0-9A-Za-z-]+";
+    let param = format!(r#"{token}\s*=\s*(?:{token}|"(?:[^"\\]|\\.)*")"#);
+    Regex::new(&format!(
+        r"^{token}(?: +(?:[A-Za-z0-9._~+/-]+=*|{param}(?:\s*,\s*{param})*))?$"
+    ))
+    .unwrap()
+});
+
+fn validate_www_authenticate(response: &ResponseConfig) -> Result<(), String> {
+    match response {
+        ResponseConfig::Static {
+            status,
+            www_authenticate: Some(challenge),
+            ..
+        } => {
+            if *status != Some(401) {
+                Err("www_authenticate requires status 401".to_string())
+            } else if !CHALLENGE_REGEX.is_match(challenge) {
+                Err(format!(
+                    "www_authenticate: \"{}\" is not a valid challenge",
+                    challenge
+                ))
+            } else {
+                Ok(())
+            }
+        }
+        _ => Ok(()),
+    }
+}
+
+fn validate_cookies(response: &ResponseConfig) -> Result<(), String> {
+    let ResponseConfig::Static { cookies, .. } = response else {
+        return Ok(());
+    };
+    cookies.iter().try_for_each(|cookie| {
+        validate_cookie(cookie)
+            .map_err(|message| format!("cookie \"{}\": {}", cookie.name, message))
+    })
+}
+
+// browsers silently drop cookies breaking these rules, which is hard to notice from a client
+fn validate_cookie(cookie: &Cookie) -> Result<(), String> {
+    // a token of RFC 7230, and a cookie-octet of RFC 6265 which may be quoted
+    let is_token = |c: char| c.is_ascii_alphanumeric() || "!#$%&'*+-.^_`|~".contains(c);
+    let is_octet = |c: char| c.is_ascii_graphic() && !"\",;\\".contains(c);
+    let value = cookie.value.as_str();
+    let unquoted = value
+        .strip_prefix('"')
+        .and_then(|value| value.strip_suffix('"'))
+        .unwrap_or(value);
+    let has_attribute_delimiter =
+        |attribute: &Option<String>| attribute.as_ref().is_some_and(|value| value.contains(';'));
+
+    if cookie.name.is_empty() || !cookie.name.chars().all(is_token) {
+        Err("name is not a valid token".to_string())
+    } else if !unquoted.chars().all(is_octet) {
+        Err("value must not contain whitespace, '\"', ',', ';' or '\\'".to_string())
+    } else if has_attribute_delimiter(&cookie.path) || has_attribute_delimiter(&cookie.domain) {
+        Err("path and domain must not contain ';'".to_string())
+    } else if cookie.same_site == Some(SameSite::None) && !cookie.secure {
+        Err("same_site none requires secure".to_string())
+    } else if cookie.name.starts_with("__Secure-") && !cookie.secure {
+        Err("the __Secure- prefix requires secure".to_string())
+    } else if cookie.name.starts_with("__Host-")
+        && !(cookie.secure && cookie.path.as_deref() == Some("/") && cookie.domain.is_none())
+    {
+        Err("the __Host- prefix requires secure, path / and no domain".to_string())
+    } else {
+        Ok(())
+    }
+}
+
+// binary payloads such as protobuf messages are written in base64
+fn deserialize_base64<'de, D: Deserializer<'de>>(deserializer: D) -> Result<Vec<u8>, D::Error> {
+    let encoded = String::deserialize(deserializer)?;
+    BASE64_STANDARD
+        .decode(encoded)
+        .map_err(|err| serde::de::Error::custom(format!("invalid base64: {}", err)))
+}
+
+fn deserialize_optional_base64<'de, D: Deserializer<'de>>(
+    deserializer: D,
+) -> Result<Option<Vec<u8>>, D::Error> {
+    deserialize_base64(deserializer).map(Some)
+}
+
+fn deserialize_base64_values<'de, D: Deserializer<'de>>(
+    deserializer: D,
+) -> Result<IndexMap<u16, Vec<u8>>, D::Error> {
+    IndexMap::<u16, String>::deserialize(deserializer)?
+        .into_iter()
+        .map(|(status, encoded)| {
+            BASE64_STANDARD
+                .decode(encoded)
+                .map(|body| (status, body))
+                .map_err(|err| serde::de::Error::custom(format!("invalid base64: {}", err)))
+        })
+        .collect()
+}
+
+// read in the config schema, where static responses without `status` respond with 200
+impl<'de> Deserialize<'de> for ResponseHandler {
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        deserialize_response(deserializer)
+            .map(|ResponseVariant(response, _)| response.into_handler(200))
+    }
+}
+
+impl ResponseConfig {
+    // the unknown keys of the responses nested in this one, prefixed with where they are
+    fn nested_unknown_keys(&self) -> Vec<String> {
+        let nested: Vec<(String, &ResponseVariant)> = match self {
+            ResponseConfig::Sticky { variants, .. } => variants
+                .iter()
+                .enumerate()
+                .map(|(i, variant)| (format!("variants.{}", i), variant))
+                .collect(),
+            ResponseConfig::CountGated { before, after, .. } => vec![
+                ("before".to_string(), before.as_ref()),
+                ("after".to_string(), after.as_ref()),
+            ],
+            ResponseConfig::Once { first, then, .. } => vec![
+                ("first".to_string(), first.as_ref()),
+                ("then".to_string(), then.as_ref()),
+            ],
+            ResponseConfig::RetryUntilSuccess { response, .. }
+            | ResponseConfig::Idempotent { response, .. } => {
+                vec![("response".to_string(), response.as_ref())]
+            }
+            ResponseConfig::TimeWindows { windows, default } => windows
+                .iter()
+                .enumerate()
+                .map(|(i, window)| (format!("windows.{}.response", i), &window.response))
+                .chain(std::iter::once(("default".to_string(), default.as_ref())))
+                .collect(),
+            ResponseConfig::ByBodySize { sizes, default } => sizes
+                .iter()
+                .enumerate()
+                .map(|(i, size)| (format!("sizes.{}.response", i), &size.response))
+                .chain(std::iter::once(("default".to_string(), default.as_ref())))
+                .collect(),
+            ResponseConfig::PerSession {
+                per_session_overrides,
+                default,
+            } => per_session_overrides
+                .iter()
+                .map(|(session, response)| (format!("per_session_overrides.{}", session), response))
+                .chain(std::iter::once(("default".to_string(), default.as_ref())))
+                .collect(),
+            ResponseConfig::ByCookie {
+                responses, default, ..
+            } => responses
+                .iter()
+                .map(|(value, response)| (format!("responses.{}", value), response))
+                .chain(std::iter::once(("default".to_string(), default.as_ref())))
+                .collect(),
+            _ => vec![],
+        };
+        nested
+            .into_iter()
+            .flat_map(|(prefix, ResponseVariant(_, keys))| {
+                keys.iter().map(move |key| format!("{}.{}", prefix, key))
+            })
+            .collect()
+    }
+
+    // `default_status` is used by static responses without `status`, including nested ones
+    fn into_handler(self, default_status: u16) -> ResponseHandler {
+        match self {
+            ResponseConfig::Static {
+                status,
+                headers,
+                body,
+                body_base64,
+                last_modified,
+                chunk_size,
+                connection_close,
+                delay_ms,
+                expect_continue,
+                drain_body,
+                latency,
+                reset_after,
+                empty_body,
+                status_bodies,
+                status_bodies_base64,
+                www_authenticate,
+                cookies,
+            } => ResponseHandler::Static(StaticResponse {
+                status: status.unwrap_or(default_status),
+                headers: headers
+                    .unwrap_or_default()
+                    .into_iter()
+                    .chain(
+                        www_authenticate
+                            .map(|challenge| (header::WWW_AUTHENTICATE.to_string(), challenge)),
+                    )
+                    .collect(),
+                // exactly one of them is given, as checked by `parse_response`
+                body: body
+                    .map(Bytes::from)
+                    .or(body_base64.map(Bytes::from))
+                    .unwrap_or_default(),
+                last_modified,
+                chunk_size,
+                connection_close,
+                delay_ms,
+                expect_continue,
+                drain_body,
+                latency,
+                reset_after,
+                empty_body,
+                status_bodies: status_bodies
+                    .into_iter()
+                    .map(|(status, body)| (status, Bytes::from(body)))
+                    .chain(
+                        status_bodies_base64
+                            .into_iter()
+                            .map(|(status, body)| (status, Bytes::from(body))),
+                    )
+                    .collect(),
+                cookies,
+            }),
+            ResponseConfig::File { path } => ResponseHandler::File { path },
+            ResponseConfig::Dir { root } => ResponseHandler::Dir { root },
+            ResponseConfig::Hangup { delay_ms } => ResponseHandler::Hangup { delay_ms },
+            ResponseConfig::Truncated {
+                status,
+                content_length,
+                body,
+            } => ResponseHandler::Truncated {
+                status,
+                content_length,
+                body,
+            },
+            ResponseConfig::StatusReflect {
+                status_param,
+                default,
+                statuses,
+            } => ResponseHandler::StatusReflect {
+                status_param,
+                default,
+                statuses,
+            },
+            ResponseConfig::QueryEcho { param } => ResponseHandler::QueryEcho { param },
+            ResponseConfig::Sticky { header, variants } => ResponseHandler::Sticky {
+                header,
+                variants: variants
+                    .into_iter()
+                    .map(|ResponseVariant(variant, _)| variant.into_handler(default_status))
+                    .collect(),
+            },
+            ResponseConfig::ProxyPool {
+                targets,
+                strategy,
+                cache_ttl_ms,
+            } => ResponseHandler::ProxyPool {
+                targets,
+                strategy,
+                cursor: Default::default(),
+                cache_ttl_ms,
+                cache: Default::default(),
+            },
+            ResponseConfig::CountGated {
+                threshold,
+                before,
+                after,
+            } => ResponseHandler::CountGated {
+                threshold,
+                before: Box::new(before.0.into_handler(default_status)),
+                after: Box::new(after.0.into_handler(default_status)),
+                count: Default::default(),
+            },
+            ResponseConfig::Raw {
+                status,
+                headers,
+                body,
+            } => ResponseHandler::Raw {
+                status,
+                headers: headers.unwrap_or_default(),
+                body,
+            },
+            ResponseConfig::GrpcWeb {
+                message,
+                grpc_status,
+                grpc_message,
+            } => ResponseHandler::GrpcWeb {
+                message,
+                grpc_status,
+                grpc_message,
+            },
+            ResponseConfig::RandomBytes {
+                size,
+                fill,
+                content_type,
+                seed,
+            } => ResponseHandler::RandomBytes {
+                size,
+                fill,
+                content_type: content_type
+                    .unwrap_or_else(|| "application/octet-stream".to_string()),
+                seed,
+            },
+            ResponseConfig::RetryUntilSuccess {
+                header,
+                success_after,
+                pending_status,
+                response,
+            } => ResponseHandler::RetryUntilSuccess {
+                header,
+                success_after,
+                pending_status,
+                response: Box::new(response.0.into_handler(default_status)),
+                attempts: Default::default(),
+            },
+            ResponseConfig::MergePatch { initial } => ResponseHandler::MergePatch {
+                initial,
+                documents: Default::default(),
+            },
+            ResponseConfig::Resource { collection } => ResponseHandler::Resource {
+                collection,
+                collections: Default::default(),
+            },
+            ResponseConfig::TimeWindows { windows, default } => ResponseHandler::TimeWindows {
+                windows: windows
+                    .into_iter()
+                    .map(|TimeWindowConfig { window, response }| {
+                        (window, response.0.into_handler(default_status))
+                    })
+                    .collect(),
+                default: Box::new(default.0.into_handler(default_status)),
+                clock: Default::default(),
+            },
+            ResponseConfig::PerSession {
+                per_session_overrides,
+                default,
+            } => ResponseHandler::PerSession {
+                overrides: per_session_overrides
+                    .into_iter()
+                    .map(|(session, ResponseVariant(response, _))| {
+                        (session, response.into_handler(default_status))
+                    })
+                    .collect(),
+                default: Box::new(default.0.into_handler(default_status)),
+            },
+            ResponseConfig::PartialContent { body, content_type } => {
+                ResponseHandler::PartialContent { body, content_type }
+            }
+            ResponseConfig::Once {
+                first,
+                then,
+                per_session,
+            } => ResponseHandler::Once {
+                first: Box::new(first.0.into_handler(default_status)),
+                then: Box::new(then.0.into_handler(default_status)),
+                per_session,
+                served: Default::default(),
+            },
+            ResponseConfig::ByCookie {
+                cookie,
+                responses,
+                default,
+            } => ResponseHandler::ByCookie {
+                cookie,
+                responses: responses
+                    .into_iter()
+                    .map(|(value, ResponseVariant(response, _))| {
+                        (value, response.into_handler(default_status))
+                    })
+                    .collect(),
+                default: Box::new(default.0.into_handler(default_status)),
+            },
+            ResponseConfig::Idempotent {
+                header,
+                ttl_ms,
+                response,
+            } => ResponseHandler::Idempotent {
+                header,
+                ttl_ms,
+                response: Box::new(response.0.into_handler(default_status)),
+                cache: Default::default(),
+            },
+            ResponseConfig::ByBodySize { sizes, default } => ResponseHandler::ByBodySize {
+                sizes: sizes
+                    .into_iter()
+                    .map(|BodySizeConfig { size, response }| {
+                        (size, response.0.into_handler(default_status))
+                    })
+                    .collect(),
+                default: Box::new(default.0.into_handler(default_status)),
+            },
+            ResponseConfig::Error { status, message } => ResponseHandler::Error { status, message },
+        }
+    }
+}
+
+pub const STDIN_PATH: &str = "-";
+
+/// The config written by `serverify init`.
+pub const SAMPLE_CONFIG: &str = r#"# Mock endpoints are served at /mock/{session}{path}, e.g. /mock/default/hello
+paths:
+  /hello:
+    get:
+      response:
+        # `type` defaults to static
+        status: 200
+        headers:
+          Content-Type: text/plain
+        body: "Hello, world!"
+  /users/:id:
+    get:
+      response:
+        # responds with 503 twice before succeeding, to try out client retries
+        type: count_gated
+        threshold: 2
+        before:
+          status: 503
+          body: ""
+        after:
+          status: 200
+          headers:
+            Content-Type: application/json
+          body: '{"id": 1, "name": "Alice"}'
+
+# sessions created at startup, in addition to ones created via POST /session
+sessions:
+  - example
+"#;
+
+pub fn read_config(path: &str, stdin: impl Read) -> Result<ServerConfig, String> {
+    read(path, stdin, false)
+}
+
+/// Same as `read_config`, but fails on keys which are not known instead of ignoring them.
+pub fn read_config_strict(path: &str, stdin: impl Read) -> Result<ServerConfig, String> {
+    read(path, stdin, true)
+}
+
+fn read(path: &str, stdin: impl Read, strict: bool) -> Result<ServerConfig, String> {
+    if Path::new(path).is_dir() {
+        return read_config_dir(Path::new(path), strict);
+    }
+
+    let (label, src) = if path == STDIN_PATH {
+        let mut stdin = stdin;
+        let mut src = String::new();
+        ("<stdin>", stdin.read_to_string(&mut src).map(|_| src))
+    } else {
+        (path, fs::read_to_string(path))
+    };
+
+    src.map_err(|err| err.to_string())
+        .and_then(|src| parse(&src, strict))
+        .map_err(|err| format!("{}: {}", label, err))
+}
+
+const CONFIG_EXTENSIONS: [&str; 3] = ["yaml", "yml", "json"];
+
+// every config file in the directory is merged in filename order
+fn read_config_dir(dir: &Path, strict: bool) -> Result<ServerConfig, String> {
+    let mut paths = fs::read_dir(dir)
+        .and_then(|entries| {
+            entries
+                .map(|entry| entry.map(|entry| entry.path()))
+                .collect::<Result<Vec<_>, _>>()
+        })
+        .map_err(|err| format!("{}: {}", dir.display(), err))?
+        .into_iter()
+        .filter(|path| {
+            path.is_file()
+                && path
+                    .extension()
+                    .and_then(|ext| ext.to_str())
+                    .is_some_and(|ext| CONFIG_EXTENSIONS.contains(&ext))
+        })
+        .collect::<Vec<_>>();
+    paths.sort();
+
+    let mut merged = ServerConfig {
+        endpoints: vec![],
+        default_content_type: None,
+        sessions: vec![],
+        correlation_header: None,
+    };
+    let mut defined_in = IndexMap::<(String, String, Option<String>), String>::new();
+    for path in paths {
+        let label = path.display().to_string();
+        let config = read(&label, std::io::empty(), strict)?;
+        for endpoint in config.endpoints {
+            let key = (
+                endpoint.method.to_string(),
+                endpoint.path.clone(),
+                endpoint.host.clone(),
+            );
+            if let Some(previous) = defined_in.insert(key, label.clone()) {
+                return Err(format!(
+                    "{}: paths.{}.{} is already defined in {}",
+                    label, endpoint.path, endpoint.method, previous
+                ));
+            }
+            merged.endpoints.push(endpoint);
+        }
+        merged.default_content_type = merged.default_content_type.or(config.default_content_type);
+        merged.sessions.extend(config.sessions);
+        merged.correlation_header = merged.correlation_header.or(config.correlation_header);
+    }
+    // each file is validated on its own by `read`, which misses conflicts across files
+    validate_hosts(&merged.endpoints)
+        .and_then(|_| validate_resources(&merged.endpoints))
+        .and_then(|_| validate_routes(&merged.endpoints))
+        .map_err(|err| format!("{}: {}", dir.display(), err))?;
+
+    Ok(merged)
+}
+
+fn builtin_default_status(method: &Method) -> u16 {
+    match method {
+        Method::Post => 201,
+        Method::Delete => 204,
+        Method::Get | Method::Put | Method::Patch => 200,
+    }
+}
+
+pub fn parse_config(src: &str) -> Result<ServerConfig, String> {
+    parse(src, false)
+}
+
+fn parse(src: &str, strict: bool) -> Result<ServerConfig, String> {
+    let config = if strict {
+        let mut unknown_keys = vec![];
+        let config: Config =
+            serde_ignored::deserialize(serde_yaml::Deserializer::from_str(src), |path| {
+                unknown_keys.push(path.to_string())
+            })
+            .map_err(|e: serde_yaml::Error| e.to_string())?;
+        unknown_keys.extend(config.response_unknown_keys());
+        if !unknown_keys.is_empty() {
+            return Err(format!("unknown keys: {}", unknown_keys.join(", ")));
+        }
+        config
+    } else {
+        serde_yaml::from_str::<Config>(src).map_err(|e| e.to_string())?
+    };
+    let default_status = &config.default_status;
+    let endpoints = config
+        .paths
+        .into_iter()
+        .flat_map(|(path, methods)| {
+            methods
                 .into_iter()
-                .flat_map(|(path, methods)| {
-                    methods
-                        .into_iter()
-                        .map(move |(method, endpoint)| MockEndpoint {
-                            method,
-                            path: path.clone(),
-                            status: endpoint.response.status,
-                            headers: endpoint.response.headers.unwrap_or_default(),
-                            body: endpoint.response.body,
-                        })
+                .flat_map(move |(method, EndpointConfigs { endpoints, .. })| {
+                    let default_status = default_status
+                        .get(&method)
+                        .copied()
+                        .unwrap_or_else(|| builtin_default_status(&method));
+                    let path = path.clone();
+                    endpoints.into_iter().map(move |endpoint| MockEndpoint {
+                        method: method.clone(),
+                        path: path.clone(),
+                        host: endpoint.host,
+                        name: endpoint.name,
+                        response: endpoint.response.0.into_handler(default_status),
+                    })
                 })
-                .collect()
         })
+        .map(|endpoint| validate_endpoint(&endpoint).map(|_| endpoint))
+        .collect::<Result<Vec<_>, _>>()?;
+    validate_hosts(&endpoints)?;
+    validate_resources(&endpoints)?;
+    validate_routes(&endpoints)?;
+
+    Ok(ServerConfig {
+        endpoints,
+        default_content_type: config.default_content_type,
+        sessions: config.sessions,
+        correlation_header: config.correlation_header,
+    })
+}
+
+// headers asking a front proxy to serve the content instead, so the body must be left empty
+const INTERNAL_REDIRECT_HEADERS: [&str; 2] = ["x-accel-redirect", "x-sendfile"];
+
+fn validate_endpoint(endpoint: &MockEndpoint) -> Result<(), String> {
+    validate_response(&endpoint.response)
+        .map_err(|err| format!("paths.{}.{}: {}", endpoint.path, endpoint.method, err))
+}
+
+// endpoints sharing a method and path are routed together, so their hosts have to differ
+fn validate_hosts(endpoints: &[MockEndpoint]) -> Result<(), String> {
+    let mut seen = vec![];
+    for endpoint in endpoints {
+        let key = (
+            &endpoint.method,
+            &endpoint.path,
+            endpoint.host.as_ref().map(|host| host.to_ascii_lowercase()),
+        );
+        if seen.contains(&key) {
+            return Err(match &endpoint.host {
+                Some(host) => format!(
+                    "paths.{}.{}: host \"{}\" is defined more than once",
+                    endpoint.path, endpoint.method, host
+                ),
+                None => format!(
+                    "paths.{}.{}: endpoint without host is defined more than once",
+                    endpoint.path, endpoint.method
+                ),
+            });
+        }
+        seen.push(key);
+    }
+    Ok(())
+}
+
+// resources are routed for every CRUD method, so the path cannot have endpoints of another method
+fn validate_resources(endpoints: &[MockEndpoint]) -> Result<(), String> {
+    for resource in endpoints
+        .iter()
+        .filter(|endpoint| matches!(endpoint.response, ResponseHandler::Resource { .. }))
+    {
+        let base = resource.path.trim_end_matches('/');
+        if endpoints.iter().any(|endpoint| {
+            endpoint.path.trim_end_matches('/') == base && endpoint.method != resource.method
+        }) {
+            return Err(format!(
+                "path {} is a resource; other methods cannot be configured",
+                resource.path
+            ));
+        }
+    }
+    Ok(())
+}
+
+// the router cannot order a parameter and a wildcard at the same segment, or two parameters with
+// different names, and panics on them, so they are rejected here
+fn validate_routes(endpoints: &[MockEndpoint]) -> Result<(), String> {
+    let mut routed: Vec<(String, &MockEndpoint)> = vec![];
+    for endpoint in endpoints {
+        for pattern in route_paths(&endpoint.path, &endpoint.response) {
+            if let Some((other_pattern, other)) = routed
+                .iter()
+                .find(|(other_pattern, _)| patterns_conflict(other_pattern, &pattern))
+            {
+                return Err(format!(
+                    "paths.{}.{}: \"{}\" conflicts with \"{}\" of paths.{}.{}",
+                    endpoint.path,
+                    endpoint.method,
+                    pattern,
+                    other_pattern,
+                    other.path,
+                    other.method
+                ));
+            }
+            routed.push((pattern, endpoint));
+        }
+    }
+    Ok(())
+}
+
+// patterns are compared segment by segment up to the first one where a static segment decides
+fn patterns_conflict(left: &str, right: &str) -> bool {
+    for (left, right) in left.split('/').zip(right.split('/')) {
+        match (left.chars().next(), right.chars().next()) {
+            (Some(':'), Some('*')) | (Some('*'), Some(':')) => return true,
+            (Some(':'), Some(':')) | (Some('*'), Some('*')) if left != right => return true,
+            _ if left != right => return false,
+            _ => {}
+        }
+    }
+    false
+}
+
+fn validate_response(response: &ResponseHandler) -> Result<(), String> {
+    match response {
+        ResponseHandler::Static(StaticResponse {
+            latency: Some(latency),
+            ..
+        }) if !(latency.p50 <= latency.p95 && latency.p95 <= latency.p99) => {
+            Err("latency must satisfy p50 <= p95 <= p99".to_string())
+        }
+        ResponseHandler::Static(response) => {
+            if let Some(status) = response
+                .status_bodies
+                .keys()
+                .find(|status| !(100..600).contains(*status))
+            {
+                return Err(format!("status_bodies: {} is not a valid status", status));
+            }
+            match response.headers.keys().find(|name| {
+                INTERNAL_REDIRECT_HEADERS.contains(&name.to_ascii_lowercase().as_str())
+            }) {
+                Some(name) if !response.body.is_empty() => {
+                    Err(format!("body must be empty when {} is given", name))
+                }
+                _ => Ok(()),
+            }
+        }
+        ResponseHandler::Sticky { variants, .. } if variants.is_empty() => {
+            Err("variants must not be empty".to_string())
+        }
+        ResponseHandler::Sticky { variants, .. } => variants.iter().try_for_each(validate_response),
+        ResponseHandler::CountGated { before, after, .. } => {
+            validate_response(before).and_then(|_| validate_response(after))
+        }
+        ResponseHandler::RetryUntilSuccess { response, .. }
+        | ResponseHandler::Idempotent { response, .. } => validate_response(response),
+        ResponseHandler::Once { first, then, .. } => {
+            validate_response(first).and_then(|_| validate_response(then))
+        }
+        ResponseHandler::TimeWindows {
+            windows, default, ..
+        } => windows
+            .iter()
+            .map(|(_, response)| response)
+            .chain(std::iter::once(default.as_ref()))
+            .try_for_each(validate_response),
+        ResponseHandler::PerSession {
+            overrides: responses,
+            default,
+        }
+        | ResponseHandler::ByCookie {
+            responses, default, ..
+        } => responses
+            .values()
+            .chain(std::iter::once(default.as_ref()))
+            .try_for_each(validate_response),
+        ResponseHandler::ByBodySize { sizes, default } => {
+            if let Some((min, max)) = sizes.iter().find_map(|(size, _)| match size {
+                BodySize {
+                    min: Some(min),
+                    max: Some(max),
+                } if min > max => Some((min, max)),
+                _ => None,
+            }) {
+                return Err(format!("sizes: min {} is greater than max {}", min, max));
+            }
+            sizes
+                .iter()
+                .map(|(_, response)| response)
+                .chain(std::iter::once(default.as_ref()))
+                .try_for_each(validate_response)
+        }
+        ResponseHandler::Error { status, .. } if !(100..600).contains(status) => {
+            Err(format!("{} is not a valid status", status))
+        }
+        ResponseHandler::StatusReflect { default, .. } if !(100..600).contains(default) => {
+            Err(format!("default: {} is not a valid status", default))
+        }
+        ResponseHandler::ProxyPool { targets, .. } if targets.is_empty() => {
+            Err("targets must not be empty".to_string())
+        }
+        _ => Ok(()),
+    }
+}
+
+#[derive(Serialize)]
+struct ExportedConfig<'a> {
+    paths: IndexMap<&'a str, IndexMap<&'a Method, Vec<ExportedEndpoint<'a>>>>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    default_content_type: Option<&'a str>,
+    #[serde(skip_serializing_if = "<[_]>::is_empty")]
+    sessions: &'a [String],
+    #[serde(skip_serializing_if = "Option::is_none")]
+    correlation_header: Option<&'a str>,
+}
+
+#[derive(Serialize)]
+struct ExportedEndpoint<'a> {
+    #[serde(skip_serializing_if = "Option::is_none")]
+    host: &'a Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    name: &'a Option<String>,
+    response: &'a ResponseHandler,
+}
+
+impl<'a> ExportedConfig<'a> {
+    fn new(endpoints: &'a [MockEndpoint]) -> Self {
+        let mut paths = IndexMap::<_, IndexMap<_, Vec<_>>>::new();
+        for endpoint in endpoints {
+            paths
+                .entry(endpoint.path.as_str())
+                .or_default()
+                .entry(&endpoint.method)
+                .or_default()
+                .push(ExportedEndpoint {
+                    host: &endpoint.host,
+                    name: &endpoint.name,
+                    response: &endpoint.response,
+                });
+        }
+        Self {
+            paths,
+            default_content_type: None,
+            sessions: &[],
+            correlation_header: None,
+        }
+    }
+}
+
+/// Renders endpoints as a YAML config which `parse_config` reads back.
+pub fn export_config(endpoints: &[MockEndpoint]) -> Result<String, String> {
+    serde_yaml::to_string(&ExportedConfig::new(endpoints)).map_err(|err| err.to_string())
+}
+
+impl ServerConfig {
+    /// Renders the config as YAML with everything resolved, such as the statuses defaulted by
+    /// `default_status` and the files merged from a config directory.
+    pub fn to_yaml(&self) -> Result<String, String> {
+        serde_yaml::to_string(&ExportedConfig {
+            default_content_type: self.default_content_type.as_deref(),
+            sessions: &self.sessions,
+            correlation_header: self.correlation_header.as_deref(),
+            ..ExportedConfig::new(&self.endpoints)
+        })
+        .map_err(|err| err.to_string())
+    }
 }
 
 #[cfg(test)]
 mod tests {
     use super::*;
+    use crate::mock_endpoint::testutil::endpoint;
+    use chrono::{NaiveTime, TimeZone};
     use indexmap::indexmap;
     use pretty_assertions::assert_eq;
     use rstest::rstest;
@@ -71,29 +1214,1243 @@ paths:
                     Content-Type: text/plain
                 body: "Goodbye, world!"
     "#, Ok(vec![
-        MockEndpoint {
-            method: Method::Get,
-            path: "/hello".to_string(),
+        endpoint(Method::Get, "/hello", ResponseHandler::Static(StaticResponse {
             status: 200,
             headers: indexmap! { "Content-Type".to_string() => "text/plain".to_string() },
-            body: "Hello, world!".to_string(),
-        },
-        MockEndpoint {
-            method: Method::Post,
-            path: "/hello".to_string(),
+            body: "Hello, world!".into(),
+            ..Default::default()
+        })),
+        endpoint(Method::Post, "/hello", ResponseHandler::Static(StaticResponse {
             status: 204,
             headers: indexmap! {},
-            body: "".to_string(),
-        },
-        MockEndpoint {
-            method: Method::Get,
-            path: "/goodbye".to_string(),
+            body: "".into(),
+            ..Default::default()
+        })),
+        endpoint(Method::Get, "/goodbye", ResponseHandler::Static(StaticResponse {
             status: 200,
             headers: indexmap! { "Content-Type".to_string() => "text/plain".to_string() },
-            body: "Goodbye, world!".to_string(),
+            body: "Goodbye, world!".into(),
+            ..Default::default()
+        })),
+    ]))]
+    #[case(r#"
+paths:
+    /index.html:
+        get:
+            response:
+                type: file
+                path: ./public/index.html
+    /assets:
+        get:
+            response:
+                type: dir
+                root: ./public/assets
+    /timeout:
+        get:
+            response:
+                type: hangup
+                delay_ms: 3000
+    /download:
+        get:
+            response:
+                type: truncated
+                status: 200
+                content_length: 100
+                body: "partial"
+    /status:
+        get:
+            response:
+                type: status_reflect
+                status_param: code
+                default: 204
+                statuses:
+                    missing: 404
+    /echo:
+        get:
+            response:
+                type: query_echo
+                param: tag
+    /ab:
+        get:
+            response:
+                type: sticky
+                header: X-Client-Id
+                variants:
+                    - status: 200
+                      body: "A"
+                    - type: status_reflect
+                      status_param: code
+                      default: 200
+    /api:
+        get:
+            response:
+                type: proxy_pool
+                targets:
+                    - http://localhost:3001
+                    - http://localhost:3002
+                cache_ttl_ms: 1000
+    /flaky:
+        get:
+            response:
+                type: count_gated
+                threshold: 2
+                before:
+                    status: 503
+                    body: ""
+                after:
+                    status: 200
+                    body: "ok"
+    /broken:
+        get:
+            response:
+                type: raw
+                status: 200
+                body: '{"a":'
+    /grpc:
+        post:
+            response:
+                type: grpc_web
+                message: CJYB
+                grpc_status: 0
+    /random:
+        get:
+            response:
+                type: random_bytes
+                size: 1024
+                fill: random
+                seed: 42
+    /orders:
+        post:
+            response:
+                type: retry_until_success
+                success_after: 3
+                response:
+                    body: "created"
+    /profile:
+        patch:
+            response:
+                type: merge_patch
+                initial:
+                    name: alice
+    /users:
+        get:
+            response:
+                type: resource
+                collection: users
+    /maintenance:
+        get:
+            response:
+                type: time_windows
+                windows:
+                    - start: "02:00"
+                      end: "04:00"
+                      response:
+                          status: 503
+                          body: ""
+                default:
+                    body: "ok"
+    /tenant:
+        get:
+            response:
+                type: per_session
+                per_session_overrides:
+                    alice:
+                        body: "for alice"
+                default:
+                    status: 404
+                    body: ""
+    /downloads/file.txt:
+        get:
+            response:
+                type: partial_content
+                body: "0123456789"
+                content_type: text/plain
+    /accounts:
+        post:
+            response:
+                type: once
+                per_session: true
+                first:
+                    status: 201
+                    body: "created"
+                then:
+                    status: 200
+                    body: "exists"
+    /dashboard:
+        get:
+            response:
+                type: by_cookie
+                cookie: plan
+                responses:
+                    pro:
+                        body: "pro dashboard"
+                default:
+                    body: "free dashboard"
+    /signup:
+        post:
+            response:
+                type: error
+                status: 422
+                message: "name is required"
+    /uploads:
+        post:
+            response:
+                type: by_body_size
+                sizes:
+                    - max: 1024
+                      response:
+                          body: "small"
+                    - min: 1025
+                      max: 1048576
+                      response:
+                          status: 202
+                          body: "large"
+                default:
+                    status: 413
+                    body: "too large"
+    /payments:
+        post:
+            response:
+                type: idempotent
+                response:
+                    type: resource
+                    collection: payments
+    /hello:
+        get:
+            response:
+                type: static
+                status: 200
+                body: "Hello, world!"
+                last_modified: 2024-01-02T03:04:05Z
+                chunk_size: 5
+                connection_close: true
+                delay_ms: 100
+                expect_continue: reject
+                drain_body: true
+                latency:
+                    p50: 10
+                    p95: 50
+                    p99: 200
+                reset_after: 7
+                empty_body: chunked
+                status_bodies:
+                    404: "not found"
+                cookies:
+                    - name: sid
+                      value: abc
+                      path: /
+                      max_age: 3600
+                      http_only: true
+                      secure: true
+                      same_site: strict
+    "#, Ok(vec![
+        endpoint(Method::Get, "/index.html", ResponseHandler::File {
+            path: "./public/index.html".to_string(),
+        }),
+        endpoint(Method::Get, "/assets", ResponseHandler::Dir {
+            root: "./public/assets".to_string(),
+        }),
+        endpoint(Method::Get, "/timeout", ResponseHandler::Hangup { delay_ms: 3000 }),
+        endpoint(Method::Get, "/download", ResponseHandler::Truncated {
+            status: 200,
+            content_length: 100,
+            body: "partial".to_string(),
+        }),
+        endpoint(Method::Get, "/status", ResponseHandler::StatusReflect {
+            status_param: "code".to_string(),
+            default: 204,
+            statuses: indexmap! { "missing".to_string() => 404 },
+        }),
+        endpoint(Method::Get, "/echo", ResponseHandler::QueryEcho {
+            param: "tag".to_string(),
+        }),
+        endpoint(Method::Get, "/ab", ResponseHandler::Sticky {
+            header: "X-Client-Id".to_string(),
+            variants: vec![
+                ResponseHandler::Static(StaticResponse {
+                    status: 200,
+                    body: "A".into(),
+                    ..Default::default()
+                }),
+                ResponseHandler::StatusReflect {
+                    status_param: "code".to_string(),
+                    default: 200,
+                    statuses: indexmap! {},
+                },
+            ],
+        }),
+        endpoint(Method::Get, "/api", ResponseHandler::ProxyPool {
+            targets: vec![
+                "http://localhost:3001".to_string(),
+                "http://localhost:3002".to_string(),
+            ],
+            strategy: Strategy::RoundRobin,
+            cursor: Default::default(),
+            cache_ttl_ms: Some(1000),
+            cache: Default::default(),
+        }),
+        endpoint(Method::Get, "/flaky", ResponseHandler::CountGated {
+            threshold: 2,
+            before: Box::new(ResponseHandler::Static(StaticResponse {
+                status: 503,
+                ..Default::default()
+            })),
+            after: Box::new(ResponseHandler::Static(StaticResponse {
+                status: 200,
+                body: "ok".into(),
+                ..Default::default()
+            })),
+            count: Default::default(),
+        }),
+        endpoint(Method::Get, "/broken", ResponseHandler::Raw {
+            status: 200,
+            headers: indexmap! {},
+            body: r#"{"a":"#.to_string(),
+        }),
+        endpoint(Method::Post, "/grpc", ResponseHandler::GrpcWeb {
+            message: vec![0x08, 0x96, 0x01],
+            grpc_status: 0,
+            grpc_message: None,
+        }),
+        endpoint(Method::Get, "/random", ResponseHandler::RandomBytes {
+            size: 1024,
+            fill: Fill::Random,
+            content_type: "application/octet-stream".to_string(),
+            seed: Some(42),
+        }),
+        endpoint(Method::Post, "/orders", ResponseHandler::RetryUntilSuccess {
+            header: "idempotency-key".to_string(),
+            success_after: 3,
+            pending_status: 503,
+            response: Box::new(ResponseHandler::Static(StaticResponse {
+                status: 201,
+                body: "created".into(),
+                ..Default::default()
+            })),
+            attempts: Default::default(),
+        }),
+        endpoint(Method::Patch, "/profile", ResponseHandler::MergePatch {
+            initial: serde_json::json!({ "name": "alice" }),
+            documents: Default::default(),
+        }),
+        endpoint(Method::Get, "/users", ResponseHandler::Resource {
+            collection: "users".to_string(),
+            collections: Default::default(),
+        }),
+        endpoint(Method::Get, "/maintenance", ResponseHandler::TimeWindows {
+            windows: vec![(
+                TimeWindow {
+                    start: NaiveTime::from_hms_opt(2, 0, 0).unwrap(),
+                    end: NaiveTime::from_hms_opt(4, 0, 0).unwrap(),
+                },
+                ResponseHandler::Static(StaticResponse {
+                    status: 503,
+                    ..Default::default()
+                }),
+            )],
+            default: Box::new(ResponseHandler::Static(StaticResponse {
+                status: 200,
+                body: "ok".into(),
+                ..Default::default()
+            })),
+            clock: Default::default(),
+        }),
+        endpoint(Method::Get, "/tenant", ResponseHandler::PerSession {
+            overrides: indexmap! {
+                "alice".to_string() => ResponseHandler::Static(StaticResponse {
+                    status: 200,
+                    body: "for alice".into(),
+                    ..Default::default()
+                }),
+            },
+            default: Box::new(ResponseHandler::Static(StaticResponse {
+                status: 404,
+                ..Default::default()
+            })),
+        }),
+        endpoint(Method::Get, "/downloads/file.txt", ResponseHandler::PartialContent {
+            body: "0123456789".to_string(),
+            content_type: Some("text/plain".to_string()),
+        }),
+        endpoint(Method::Post, "/accounts", ResponseHandler::Once {
+            first: Box::new(ResponseHandler::Static(StaticResponse {
+                status: 201,
+                body: "created".into(),
+                ..Default::default()
+            })),
+            then: Box::new(ResponseHandler::Static(StaticResponse {
+                status: 200,
+                body: "exists".into(),
+                ..Default::default()
+            })),
+            per_session: true,
+            served: Default::default(),
+        }),
+        endpoint(Method::Get, "/dashboard", ResponseHandler::ByCookie {
+            cookie: "plan".to_string(),
+            responses: indexmap! {
+                "pro".to_string() => ResponseHandler::Static(StaticResponse {
+                    status: 200,
+                    body: "pro dashboard".into(),
+                    ..Default::default()
+                }),
+            },
+            default: Box::new(ResponseHandler::Static(StaticResponse {
+                status: 200,
+                body: "free dashboard".into(),
+                ..Default::default()
+            })),
+        }),
+        endpoint(Method::Post, "/signup", ResponseHandler::Error {
+            status: 422,
+            message: "name is required".to_string(),
+        }),
+        endpoint(Method::Post, "/uploads", ResponseHandler::ByBodySize {
+            sizes: vec![
+                (
+                    BodySize {
+                        min: None,
+                        max: Some(1024),
+                    },
+                    ResponseHandler::Static(StaticResponse {
+                        status: 201,
+                        body: "small".into(),
+                        ..Default::default()
+                    }),
+                ),
+                (
+                    BodySize {
+                        min: Some(1025),
+                        max: Some(1048576),
+                    },
+                    ResponseHandler::Static(StaticResponse {
+                        status: 202,
+                        body: "large".into(),
+                        ..Default::default()
+                    }),
+                ),
+            ],
+            default: Box::new(ResponseHandler::Static(StaticResponse {
+                status: 413,
+                body: "too large".into(),
+                ..Default::default()
+            })),
+        }),
+        endpoint(Method::Post, "/payments", ResponseHandler::Idempotent {
+            header: "idempotency-key".to_string(),
+            ttl_ms: 86_400_000,
+            response: Box::new(ResponseHandler::Resource {
+                collection: "payments".to_string(),
+                collections: Default::default(),
+            }),
+            cache: Default::default(),
+        }),
+        endpoint(Method::Get, "/hello", ResponseHandler::Static(StaticResponse {
+            status: 200,
+            headers: indexmap! {},
+            body: "Hello, world!".into(),
+            last_modified: Some(Utc.with_ymd_and_hms(2024, 1, 2, 3, 4, 5).unwrap()),
+            chunk_size: Some(5),
+            connection_close: true,
+            delay_ms: Some(100),
+            expect_continue: ExpectContinue::Reject,
+            drain_body: true,
+            latency: Some(LatencyDistribution {
+                p50: 10,
+                p95: 50,
+                p99: 200,
+            }),
+            reset_after: Some(7),
+            empty_body: EmptyBody::Chunked,
+            status_bodies: indexmap! { 404 => "not found".into() },
+            cookies: vec![Cookie {
+                name: "sid".to_string(),
+                value: "abc".to_string(),
+                path: Some("/".to_string()),
+                domain: None,
+                max_age: Some(3600),
+                http_only: true,
+                secure: true,
+                same_site: Some(SameSite::Strict),
+            }],
+        })),
+    ]))]
+    #[case(r#"
+paths:
+    /download:
+        get:
+            response:
+                status: 200
+                headers:
+                    X-Accel-Redirect: /protected/file.bin
+                body: ""
+    "#, Ok(vec![
+        endpoint(Method::Get, "/download", ResponseHandler::Static(StaticResponse {
+            status: 200,
+            headers: indexmap! { "X-Accel-Redirect".to_string() => "/protected/file.bin".to_string() },
+            body: "".into(),
+            ..Default::default()
+        })),
+    ]))]
+    #[case(r#"
+paths:
+    /download:
+        get:
+            response:
+                status: 200
+                headers:
+                    X-Sendfile: /var/files/file.bin
+                body: "content"
+    "#, Err("paths./download.get: body must be empty when X-Sendfile is given".to_string()))]
+    #[case(r#"
+paths:
+    /ab:
+        get:
+            response:
+                type: sticky
+                header: X-Client-Id
+                variants: []
+    "#, Err("paths./ab.get: variants must not be empty".to_string()))]
+    #[case(r#"
+paths:
+    /slow:
+        get:
+            response:
+                status: 200
+                body: ""
+                latency:
+                    p50: 100
+                    p95: 50
+                    p99: 200
+    "#, Err("paths./slow.get: latency must satisfy p50 <= p95 <= p99".to_string()))]
+    #[case(r#"
+paths:
+    /item:
+        get:
+            response:
+                status: 200
+                body: "item"
+                status_bodies:
+                    404: "not found"
+                    1000: "broken"
+    "#, Err("paths./item.get: status_bodies: 1000 is not a valid status".to_string()))]
+    #[case(r#"
+paths:
+    /uploads:
+        post:
+            response:
+                type: by_body_size
+                sizes:
+                    - min: 2048
+                      max: 1024
+                      response:
+                          body: "never"
+                default:
+                    body: "any"
+    "#, Err("paths./uploads.post: sizes: min 2048 is greater than max 1024".to_string()))]
+    #[case(r#"
+paths:
+    /signup:
+        post:
+            response:
+                type: error
+                status: 1000
+                message: "broken"
+    "#, Err("paths./signup.post: 1000 is not a valid status".to_string()))]
+    #[case(r#"
+paths:
+    /users/:id:
+        get:
+            response:
+                body: "user"
+    /users/*rest:
+        get:
+            response:
+                body: "rest"
+    "#, Err("paths./users/*rest.get: \"/users/*rest\" conflicts with \"/users/:id\" of paths./users/:id.get".to_string()))]
+    #[case(r#"
+paths:
+    /users/:id:
+        get:
+            response:
+                body: "user"
+    /users/:name:
+        post:
+            response:
+                body: "created"
+    "#, Err("paths./users/:name.post: \"/users/:name\" conflicts with \"/users/:id\" of paths./users/:id.get".to_string()))]
+    #[case(r#"
+paths:
+    /users/:name:
+        post:
+            response:
+                body: "created"
+    /users/:id:
+        get:
+            response:
+                body: "user"
+    "#, Err("paths./users/:id.get: \"/users/:id\" conflicts with \"/users/:name\" of paths./users/:name.post".to_string()))]
+    #[case(r#"
+paths:
+    /users:
+        get:
+            response:
+                type: resource
+                collection: users
+    /users/:name:
+        get:
+            response:
+                body: "user"
+    "#, Err("paths./users/:name.get: \"/users/:name\" conflicts with \"/users/:serverify_id\" of paths./users.get".to_string()))]
+    #[case(r#"
+paths:
+    /users:
+        get:
+            response:
+                type: resource
+                collection: users
+        post:
+            response:
+                status: 201
+                body: "created"
+    "#, Err("path /users is a resource; other methods cannot be configured".to_string()))]
+    #[case(r#"
+paths:
+    /status:
+        get:
+            response:
+                type: status_reflect
+                status_param: code
+                default: 50
+    "#, Err("paths./status.get: default: 50 is not a valid status".to_string()))]
+    #[case(r#"
+paths:
+    /private:
+        get:
+            response:
+                status: 401
+                body: ""
+                www_authenticate: 'Bearer realm="example", error="invalid_token"'
+    "#, Ok(vec![
+        endpoint(Method::Get, "/private", ResponseHandler::Static(StaticResponse {
+            status: 401,
+            headers: indexmap! {
+                "www-authenticate".to_string() => r#"Bearer realm="example", error="invalid_token""#.to_string(),
+            },
+            ..Default::default()
+        })),
+    ]))]
+    #[case(r#"
+paths:
+    /private:
+        get:
+            response:
+                status: 403
+                body: ""
+                www_authenticate: Basic
+    "#, Err("paths./private.get: www_authenticate requires status 401 at line 5 column 13".to_string()))]
+    #[case(r#"
+paths:
+    /private:
+        get:
+            response:
+                status: 401
+                body: ""
+                www_authenticate: 'Bearer realm="unterminated'
+    "#, Err(r#"paths./private.get: www_authenticate: "Bearer realm="unterminated" is not a valid challenge at line 5 column 13"#.to_string()))]
+    #[case(r#"
+paths:
+    /login:
+        post:
+            response:
+                body: ""
+                cookies:
+                    - name: sid
+                      value: abc
+                      same_site: none
+    "#, Err(r#"paths./login.post: cookie "sid": same_site none requires secure at line 5 column 13"#.to_string()))]
+    #[case(r#"
+paths:
+    /login:
+        post:
+            response:
+                body: ""
+                cookies:
+                    - name: __Host-sid
+                      value: abc
+                      path: /account
+                      secure: true
+    "#, Err(r#"paths./login.post: cookie "__Host-sid": the __Host- prefix requires secure, path / and no domain at line 5 column 13"#.to_string()))]
+    #[case(r#"
+paths:
+    /login:
+        post:
+            response:
+                body: ""
+                cookies:
+                    - name: sid
+                      value: "a;b"
+    "#, Err(r#"paths./login.post: cookie "sid": value must not contain whitespace, '"', ',', ';' or '\' at line 5 column 13"#.to_string()))]
+    #[case(r#"
+paths:
+    /bytes:
+        get:
+            response:
+                body_base64: AAEC/w==
+    "#, Ok(vec![
+        endpoint(Method::Get, "/bytes", ResponseHandler::Static(StaticResponse {
+            status: 200,
+            body: vec![0x00, 0x01, 0x02, 0xff].into(),
+            ..Default::default()
+        })),
+    ]))]
+    #[case(r#"
+paths:
+    /bytes:
+        get:
+            response:
+                body: "text"
+                body_base64: AAEC/w==
+    "#, Err("paths./bytes.get: body and body_base64 are mutually exclusive at line 5 column 13".to_string()))]
+    #[case(r#"
+paths:
+    /bytes:
+        get:
+            response:
+                body: "text"
+                status_bodies:
+                    404: "not found"
+                status_bodies_base64:
+                    404: AAEC/w==
+    "#, Err("paths./bytes.get: status_bodies and status_bodies_base64 both have 404 at line 5 column 13".to_string()))]
+    #[case(r#"
+paths:
+    /hello:
+        get:
+            - host: a.example.com
+              response:
+                  status: 200
+                  body: "for a"
+            - response:
+                  status: 200
+                  body: "for others"
+    "#, Ok(vec![
+        MockEndpoint {
+            host: Some("a.example.com".to_string()),
+            ..endpoint(Method::Get, "/hello", ResponseHandler::Static(StaticResponse {
+                status: 200,
+                body: "for a".into(),
+                ..Default::default()
+            }))
         },
+        endpoint(Method::Get, "/hello", ResponseHandler::Static(StaticResponse {
+            status: 200,
+            body: "for others".into(),
+            ..Default::default()
+        })),
     ]))]
+    #[case(r#"
+paths:
+    /hello:
+        get:
+            - host: a.example.com
+              response:
+                  body: ""
+            - host: A.example.com
+              response:
+                  body: ""
+    "#, Err("paths./hello.get: host \"A.example.com\" is defined more than once".to_string()))]
     fn test_parse_config(#[case] src: &str, #[case] expected: Result<Vec<MockEndpoint>, String>) {
-        assert_eq!(expected, parse_config(src));
+        assert_eq!(expected, parse_config(src).map(|config| config.endpoints));
+    }
+
+    #[rstest]
+    #[case("paths: {}", None)]
+    #[case(
+        "default_content_type: application/json\npaths: {}",
+        Some("application/json".to_string())
+    )]
+    fn test_parse_config_default_content_type(#[case] src: &str, #[case] expected: Option<String>) {
+        assert_eq!(
+            Ok(expected),
+            parse_config(src).map(|config| config.default_content_type)
+        );
+    }
+
+    #[rstest]
+    #[case("paths: {}", vec![])]
+    #[case("sessions: [first, second]\npaths: {}", vec!["first", "second"])]
+    fn test_parse_config_sessions(#[case] src: &str, #[case] expected: Vec<&str>) {
+        assert_eq!(
+            Ok(expected.into_iter().map(str::to_string).collect()),
+            parse_config(src).map(|config| config.sessions)
+        );
+    }
+
+    mod default_status {
+        use super::*;
+        use pretty_assertions::assert_eq;
+        use rstest::rstest;
+
+        const PATHS: &str = r#"
+paths:
+    /items:
+        get:
+            response:
+                body: ""
+        post:
+            response:
+                body: ""
+        put:
+            response:
+                body: ""
+        patch:
+            response:
+                body: ""
+        delete:
+            response:
+                body: ""
+    /explicit:
+        post:
+            response:
+                status: 202
+                body: ""
+    /nested:
+        delete:
+            response:
+                type: count_gated
+                threshold: 1
+                before:
+                    status: 503
+                    body: ""
+                after:
+                    body: ""
+"#;
+
+        fn statuses(config: ServerConfig) -> Vec<(String, u16)> {
+            config
+                .endpoints
+                .into_iter()
+                .map(|endpoint| {
+                    let status = match endpoint.response {
+                        ResponseHandler::Static(response) => response.status,
+                        ResponseHandler::CountGated { after, .. } => match *after {
+                            ResponseHandler::Static(response) => response.status,
+                            _ => unreachable!(),
+                        },
+                        _ => unreachable!(),
+                    };
+                    (format!("{} {}", endpoint.method, endpoint.path), status)
+                })
+                .collect()
+        }
+
+        #[rstest]
+        #[case("", vec![200, 201, 200, 200, 204, 202, 204])]
+        #[case("default_status:\n    post: 200\n    delete: 200\n", vec![200, 200, 200, 200, 200, 202, 200])]
+        fn parse(#[case] prefix: &str, #[case] expected: Vec<u16>) {
+            let names = [
+                "get /items",
+                "post /items",
+                "put /items",
+                "patch /items",
+                "delete /items",
+                "post /explicit",
+                "delete /nested",
+            ];
+
+            assert_eq!(
+                Ok(names
+                    .iter()
+                    .map(|name| name.to_string())
+                    .zip(expected)
+                    .collect()),
+                parse_config(&format!("{}{}", prefix, PATHS)).map(statuses)
+            );
+        }
+    }
+
+    #[test]
+    fn sample_config_is_valid() {
+        let config = parse_config(SAMPLE_CONFIG).unwrap();
+
+        assert_eq!(
+            (2, vec!["example".to_string()]),
+            (config.endpoints.len(), config.sessions)
+        );
+    }
+
+    #[test]
+    fn resolved_config_is_printed() {
+        let src = r#"
+paths:
+    /hello:
+        get:
+            response:
+                body: "Hello, world!"
+default_status:
+    get: 204
+default_content_type: text/plain
+sessions: [alice]
+"#;
+        let config = parse_config(src).unwrap();
+
+        let printed = config.to_yaml().unwrap();
+
+        assert!(!printed.contains("default_status"), "{}", printed);
+        assert!(printed.contains("status: 204"), "{}", printed);
+        assert_eq!(config, parse_config(&printed).unwrap());
+    }
+
+    #[tokio::test]
+    async fn www_authenticate_is_sent_with_401() {
+        let src = r#"
+paths:
+    /private:
+        get:
+            response:
+                status: 401
+                body: ""
+                www_authenticate: Bearer realm="example"
+"#;
+        let config = parse_config(src).unwrap();
+
+        let response = config.endpoints[0]
+            .response
+            .respond(&crate::response_handler::testutil::new_context(
+                "default", "/private",
+            ))
+            .await;
+
+        assert_eq!(
+            (
+                axum::http::StatusCode::UNAUTHORIZED,
+                Some(r#"Bearer realm="example""#)
+            ),
+            (
+                response.status(),
+                response
+                    .headers()
+                    .get(header::WWW_AUTHENTICATE)
+                    .and_then(|value| value.to_str().ok())
+            )
+        );
+    }
+
+    #[tokio::test]
+    async fn cookies_are_sent_as_set_cookie() {
+        let src = r#"
+paths:
+    /login:
+        post:
+            response:
+                status: 200
+                body: ""
+                headers:
+                    Set-Cookie: theme=dark
+                cookies:
+                    - name: sid
+                      value: abc123
+                      path: /
+                      max_age: 3600
+                      http_only: true
+                      secure: true
+                      same_site: strict
+                    - name: lang
+                      value: ja
+"#;
+        let config = parse_config(src).unwrap();
+
+        let response = config.endpoints[0]
+            .response
+            .respond(&crate::response_handler::testutil::new_context(
+                "default", "/login",
+            ))
+            .await;
+
+        assert_eq!(
+            vec![
+                "theme=dark",
+                "sid=abc123; Path=/; Max-Age=3600; HttpOnly; Secure; SameSite=Strict",
+                "lang=ja",
+            ],
+            response
+                .headers()
+                .get_all(header::SET_COOKIE)
+                .iter()
+                .map(|value| value.to_str().unwrap())
+                .collect::<Vec<_>>()
+        );
+    }
+
+    #[test]
+    fn endpoints_round_trip_through_serde() {
+        let src = r#"
+paths:
+    /hello:
+        post:
+            response:
+                status: 201
+                headers:
+                    X-Answer: "42"
+                body: "Hello, world!"
+                last_modified: 2024-01-02T03:04:05Z
+                latency:
+                    p50: 10
+                    p95: 50
+                    p99: 200
+    /pixel.png:
+        get:
+            - host: a.example.com
+              response:
+                  body_base64: iVBORw0KGgo=
+    /maintenance:
+        get:
+            response:
+                type: time_windows
+                windows:
+                    - start: "02:00"
+                      end: "04:00"
+                      response:
+                          status: 503
+                          body: ""
+                default:
+                    type: count_gated
+                    threshold: 1
+                    before:
+                        body: "first"
+                    after:
+                        type: proxy_pool
+                        targets: [http://localhost:8081]
+                        strategy: random
+    /tenant:
+        get:
+            response:
+                type: per_session
+                per_session_overrides:
+                    alice:
+                        type: grpc_web
+                        message: CgVoZWxsbw==
+                default:
+                    type: once
+                    first:
+                        type: random_bytes
+                        size: 16
+                    then:
+                        type: merge_patch
+                        initial: { "name": "serverify" }
+        "#;
+        let endpoints = parse_config(src).unwrap().endpoints;
+
+        let yaml = serde_yaml::to_string(&endpoints).unwrap();
+        let json = serde_json::to_string(&endpoints).unwrap();
+
+        assert_eq!(
+            (Ok(endpoints.clone()), Ok(endpoints)),
+            (
+                serde_yaml::from_str::<Vec<MockEndpoint>>(&yaml).map_err(|err| err.to_string()),
+                serde_json::from_str::<Vec<MockEndpoint>>(&json).map_err(|err| err.to_string()),
+            )
+        );
+    }
+
+    #[test]
+    fn binary_status_bodies_round_trip_through_export() {
+        let src = r#"
+paths:
+    /item:
+        get:
+            response:
+                body: "item"
+                status_bodies:
+                    404: "not found"
+                status_bodies_base64:
+                    410: AAEC/w==
+        "#;
+        let endpoints = parse_config(src).unwrap().endpoints;
+
+        let exported = export_config(&endpoints).unwrap();
+
+        assert_eq!(
+            Ok(endpoints),
+            parse_config(&exported).map(|config| config.endpoints)
+        );
+        assert!(exported.contains("410: AAEC/w=="), "{}", exported);
+    }
+
+    mod read_config {
+        use super::*;
+        use pretty_assertions::assert_eq;
+        use std::io::{Cursor, Write};
+
+        const SRC: &str = r#"
+paths:
+    /hello:
+        get:
+            response:
+                status: 200
+                body: "Hello, world!"
+"#;
+
+        fn hello_endpoints() -> Vec<MockEndpoint> {
+            vec![endpoint(
+                Method::Get,
+                "/hello",
+                ResponseHandler::Static(StaticResponse {
+                    status: 200,
+                    headers: indexmap! {},
+                    body: "Hello, world!".into(),
+                    ..Default::default()
+                }),
+            )]
+        }
+
+        #[test]
+        fn from_stdin() {
+            assert_eq!(
+                Ok(hello_endpoints()),
+                read_config("-", Cursor::new(SRC.as_bytes())).map(|config| config.endpoints)
+            );
+        }
+
+        #[test]
+        fn from_file() {
+            let mut file = tempfile::NamedTempFile::new().unwrap();
+            write!(file, "{}", SRC).unwrap();
+
+            assert_eq!(
+                Ok(hello_endpoints()),
+                read_config(file.path().to_str().unwrap(), std::io::empty())
+                    .map(|config| config.endpoints)
+            );
+        }
+
+        #[test]
+        fn from_dir() {
+            let dir = tempfile::tempdir().unwrap();
+            std::fs::write(
+                dir.path().join("b.yml"),
+                "paths:\n  /b:\n    get:\n      response:\n        status: 200\n        body: b\n",
+            )
+            .unwrap();
+            std::fs::write(
+                dir.path().join("a.yaml"),
+                "paths:\n  /a:\n    get:\n      response:\n        status: 200\n        body: a\n",
+            )
+            .unwrap();
+            std::fs::write(
+                dir.path().join("c.json"),
+                r#"{"paths": {"/a": {"post": {"response": {"status": 201, "body": "c"}}}}}"#,
+            )
+            .unwrap();
+            std::fs::write(dir.path().join("README.md"), "not a config").unwrap();
+
+            let endpoint = |method, path: &str, status, body: &str| {
+                endpoint(
+                    method,
+                    path,
+                    ResponseHandler::Static(StaticResponse {
+                        status,
+                        body: body.to_string().into(),
+                        ..Default::default()
+                    }),
+                )
+            };
+            assert_eq!(
+                Ok(vec![
+                    endpoint(Method::Get, "/a", 200, "a"),
+                    endpoint(Method::Get, "/b", 200, "b"),
+                    endpoint(Method::Post, "/a", 201, "c"),
+                ]),
+                read_config(dir.path().to_str().unwrap(), std::io::empty())
+                    .map(|config| config.endpoints)
+            );
+        }
+
+        #[test]
+        fn when_dir_has_duplicated_endpoints() {
+            let dir = tempfile::tempdir().unwrap();
+            std::fs::write(dir.path().join("a.yaml"), SRC).unwrap();
+            std::fs::write(dir.path().join("b.yaml"), SRC).unwrap();
+
+            assert_eq!(
+                Err(format!(
+                    "{}: paths./hello.get is already defined in {}",
+                    dir.path().join("b.yaml").display(),
+                    dir.path().join("a.yaml").display()
+                )),
+                read_config(dir.path().to_str().unwrap(), std::io::empty())
+            );
+        }
+
+        #[test]
+        fn when_dir_has_conflicting_patterns() {
+            let dir = tempfile::tempdir().unwrap();
+            std::fs::write(
+                dir.path().join("a.yaml"),
+                "paths:\n  /users/:id:\n    get:\n      response:\n        body: a\n",
+            )
+            .unwrap();
+            std::fs::write(
+                dir.path().join("b.yaml"),
+                "paths:\n  /users/*rest:\n    get:\n      response:\n        body: b\n",
+            )
+            .unwrap();
+
+            assert_eq!(
+                Err(format!(
+                    "{}: paths./users/*rest.get: \"/users/*rest\" conflicts with \"/users/:id\" of paths./users/:id.get",
+                    dir.path().display()
+                )),
+                read_config(dir.path().to_str().unwrap(), std::io::empty())
+                    .map(|config| config.endpoints.len())
+            );
+        }
+
+        #[rstest]
+        #[case(
+            "paths:\n  /hello:\n    get:\n      response:\n        status: 200\n        body: hi\n",
+            Ok(1)
+        )]
+        #[case(
+            "paths:\n  /hello:\n    get:\n      response:\n        stauts: 200\n        body: hi\n",
+            Err("<stdin>: unknown keys: paths./hello.get.response.stauts".to_string())
+        )]
+        #[case(
+            "paths:\n  /hello:\n    get:\n      response:\n        type: count_gated\n        threshold: 1\n        before:\n          body: a\n          delay: 10\n        after:\n          body: b\n",
+            Err("<stdin>: unknown keys: paths./hello.get.response.before.delay".to_string())
+        )]
+        #[case(
+            "paths:\n  /hello:\n    get:\n      - host: a.example.com\n        response:\n          type: by_cookie\n          cookie: plan\n          responses:\n            pro:\n              body: a\n              stauts: 200\n          default:\n            body: b\n",
+            Err("<stdin>: unknown keys: paths./hello.get.0.response.responses.pro.stauts".to_string())
+        )]
+        #[case(
+            "paths:\n  /hello:\n    get:\n      - host: a.example.com\n        hots: b.example.com\n        response:\n          body: hi\n",
+            Err("<stdin>: unknown keys: paths./hello.get.0.hots".to_string())
+        )]
+        #[case(
+            "sesions: [a]\npaths:\n  /hello:\n    get:\n      response:\n        body: hi\n",
+            Err("<stdin>: unknown keys: sesions".to_string())
+        )]
+        fn strict(#[case] src: &str, #[case] expected: Result<usize, String>) {
+            let lenient = read_config("-", Cursor::new(src.as_bytes()));
+            let strict = read_config_strict("-", Cursor::new(src.as_bytes()));
+
+            assert!(lenient.is_ok() || expected.is_err());
+            assert_eq!(expected, strict.map(|config| config.endpoints.len()));
+        }
+
+        #[test]
+        fn when_stdin_is_invalid() {
+            let result = read_config("-", Cursor::new("paths: 42".as_bytes()));
+
+            assert!(
+                result
+                    .as_ref()
+                    .is_err_and(|err| err.starts_with("<stdin>: ")),
+                "{:?}",
+                result
+            );
+        }
     }
 }
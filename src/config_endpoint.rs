@@ -0,0 +1,105 @@
+use std::sync::Arc;
+
+use axum::{http::StatusCode, routing::get, Router};
+use serde::Serialize;
+
+use crate::{
+    method::Method, mock_endpoint::MockEndpoint, response::success_response, state::AppState,
+};
+
+#[derive(Serialize, PartialEq, Debug, Clone)]
+struct EndpointSummary {
+    method: Method,
+    path: String,
+    host: Option<String>,
+    response_type: &'static str,
+}
+
+#[derive(Serialize, Clone)]
+struct GetResBody {
+    endpoints: Vec<EndpointSummary>,
+}
+
+// only the type of each response is dumped, so that configured contents such as credentials
+// are never exposed
+pub fn route_config_to(app: Router<AppState>, endpoints: &[MockEndpoint]) -> Router<AppState> {
+    let body = Arc::new(GetResBody {
+        endpoints: endpoints
+            .iter()
+            .map(|endpoint| EndpointSummary {
+                method: endpoint.method.clone(),
+                path: endpoint.path.clone(),
+                host: endpoint.host.clone(),
+                response_type: endpoint.response.type_name(),
+            })
+            .collect(),
+    });
+
+    app.route(
+        "/config",
+        get(move || async move { success_response(StatusCode::OK, (*body).clone()) }),
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{
+        mock_endpoint::testutil::endpoint,
+        request_logger::testutil::new_logger,
+        response_handler::{ResponseHandler, StaticResponse},
+    };
+    use axum_test::TestServer;
+    use pretty_assertions::assert_eq;
+    use serde_json::json;
+
+    #[tokio::test]
+    async fn get_config() {
+        let endpoints = vec![
+            endpoint(
+                Method::Get,
+                "/hello",
+                ResponseHandler::Static(StaticResponse {
+                    status: 200,
+                    body: "Hello, world!".into(),
+                    ..Default::default()
+                }),
+            ),
+            MockEndpoint {
+                host: Some("api.example.com".to_string()),
+                ..endpoint(
+                    Method::Post,
+                    "/status",
+                    ResponseHandler::StatusReflect {
+                        status_param: "code".to_string(),
+                        default: 200,
+                        statuses: Default::default(),
+                    },
+                )
+            },
+        ];
+        let app = route_config_to(Router::new(), &endpoints)
+            .with_state(AppState::new(new_logger().await));
+        let server = TestServer::new(app).unwrap();
+
+        let response = server.get("/config").await;
+
+        assert_eq!(
+            (
+                StatusCode::OK,
+                json!({
+                    "endpoints": [
+                        { "method": "get", "path": "/hello", "host": null, "response_type": "static" },
+                        {
+                            "method": "post",
+                            "path": "/status",
+                            "host": "api.example.com",
+                            "response_type": "status_reflect"
+                        },
+                    ]
+                })
+            ),
+            (response.status_code(), response.json())
+        );
+    }
+}
@@ -1,8 +1,14 @@
+pub mod admin_endpoint;
 pub mod config;
+pub mod config_endpoint;
 pub mod history;
 pub mod method;
+pub mod method_override;
 pub mod mock_endpoint;
+pub mod request_id;
 pub mod request_logger;
 pub mod response;
+pub mod response_handler;
+pub mod server;
 pub mod session_endpoint;
 pub mod state;
@@ -1,50 +1,240 @@
-use std::fs;
+use std::{
+    fs::OpenOptions,
+    io::{ErrorKind, Write},
+};
 
-use axum::{self, http::StatusCode, routing::get, Json, Router};
-use clap::Parser;
+use clap::{ArgGroup, Args, Parser, Subcommand};
 use serverify::{
-    config, request_logger::RequestLogger, session_endpoint::route_session_to, state::AppState,
+    config,
+    request_logger::{DbOptions, RequestLogger},
+    server::{build_app, serve_many, Listener, ServerOptions, SocketOptions},
+    session_endpoint::seed_sessions,
+    state::{AppOptions, AppState},
 };
+use sqlx::sqlite::SqliteJournalMode;
 use tokio::signal;
+use tracing_subscriber::EnvFilter;
 
 #[derive(Parser)]
-struct Args {
+#[clap(args_conflicts_with_subcommands = true, subcommand_negates_reqs = true)]
+struct Cli {
+    #[clap(subcommand)]
+    command: Option<Command>,
+    #[clap(flatten)]
+    serve: Option<ServeArgs>,
+}
+
+#[derive(Subcommand)]
+enum Command {
+    /// Writes a sample config to the path, or to stdout when omitted
+    Init {
+        path: Option<String>,
+        /// Overwrites the file at the path when it exists
+        #[clap(long = "force", requires = "path")]
+        force: bool,
+    },
+}
+
+#[derive(Args)]
+#[clap(group = ArgGroup::new("cors").args(["cors_allow_all", "cors_reflect"]))]
+struct ServeArgs {
     #[clap(long = "port", default_value = "8080")]
-    port: u16,
+    port: Vec<u16>,
+    #[clap(long = "unix-socket", conflicts_with = "port")]
+    unix_socket: Option<String>,
+    #[clap(long = "listen-backlog", conflicts_with = "unix_socket")]
+    listen_backlog: Option<u32>,
+    #[clap(long = "tcp-nodelay", conflicts_with = "unix_socket")]
+    tcp_nodelay: bool,
+    #[clap(long = "no-reuseaddr", conflicts_with = "unix_socket")]
+    no_reuseaddr: bool,
+    #[clap(long = "cors-allow-all")]
+    cors_allow_all: bool,
+    #[clap(long = "cors-reflect", conflicts_with = "cors_allow_all")]
+    cors_reflect: bool,
+    #[clap(long = "preflight-max-age", requires = "cors")]
+    preflight_max_age: Option<u64>,
+    /// Request headers whose values are stored as `***` in the history. Header names are
+    /// matched case-insensitively, and are always stored in lowercase.
+    #[clap(
+        long = "redact-headers",
+        value_delimiter = ',',
+        default_value = "authorization,cookie"
+    )]
+    redact_headers: Vec<String>,
+    #[clap(long = "redact-body-fields", value_delimiter = ',')]
+    redact_body_fields: Vec<String>,
+    #[clap(long = "parse-multipart")]
+    parse_multipart: bool,
+    #[clap(long = "base-path", default_value = "")]
+    base_path: String,
+    #[clap(long = "default-content-type")]
+    default_content_type: Option<String>,
+    #[clap(long = "trust-forwarded-for")]
+    trust_forwarded_for: bool,
+    #[clap(long = "strict-query-parse")]
+    strict_query_parse: bool,
+    #[clap(long = "index-body")]
+    index_body: Option<String>,
+    #[clap(long = "index-file", conflicts_with = "index_body")]
+    index_file: Option<String>,
+    #[clap(long = "not-found-body")]
+    not_found_body: Option<String>,
+    #[clap(long = "http2", conflicts_with = "unix_socket")]
+    http2: bool,
+    #[clap(long = "log-default-session")]
+    log_default_session: bool,
+    #[clap(long = "method-override")]
+    method_override: bool,
+    #[clap(long = "fallback-upstream")]
+    fallback_upstream: Option<String>,
+    #[clap(long = "disable-session-api")]
+    disable_session_api: bool,
+    #[clap(long = "store-response-headers")]
+    store_response_headers: bool,
+    #[clap(long = "max-concurrent-requests")]
+    max_concurrent_requests: Option<usize>,
+    #[clap(long = "load-shed", requires = "max_concurrent_requests")]
+    load_shed: bool,
+    #[clap(long = "print-config")]
+    print_config: bool,
+    #[clap(long = "fail-on-unknown-config-keys")]
+    fail_on_unknown_config_keys: bool,
+    #[clap(long = "db-path")]
+    db_path: Option<String>,
+    #[clap(long = "db-busy-timeout-ms")]
+    db_busy_timeout_ms: Option<u64>,
+    #[clap(long = "db-journal-mode")]
+    db_journal_mode: Option<SqliteJournalMode>,
     config_path: String,
 }
 
 #[tokio::main]
 async fn main() {
-    let args = Args::parse();
-    let src = fs::read_to_string(args.config_path).unwrap();
-    let endpoints = config::parse_config(&src).unwrap();
+    // written to stderr, as stdout carries the output of `init` and `--print-config`
+    tracing_subscriber::fmt()
+        .with_env_filter(
+            EnvFilter::try_from_default_env().unwrap_or_else(|_| EnvFilter::new("info")),
+        )
+        .with_writer(std::io::stderr)
+        .init();
 
-    let health = Router::new().route("/health", get(health));
-    let mocks = endpoints
-        .into_iter()
-        .fold(health, |app, endpoint| endpoint.route_to(app));
+    let cli = Cli::parse();
+    match (cli.command, cli.serve) {
+        (Some(Command::Init { path, force }), _) => init(path, force),
+        (None, Some(args)) => serve(args).await,
+        (None, None) => unreachable!("config_path is required without a subcommand"),
+    }
+}
 
-    let pool = sqlx::sqlite::SqlitePool::connect("sqlite::memory:")
-        .await
-        .unwrap();
-    let logger = RequestLogger::new(pool).unwrap();
-    logger.init().await.unwrap();
+fn init(path: Option<String>, force: bool) {
+    let Some(path) = path else {
+        print!("{}", config::SAMPLE_CONFIG);
+        return;
+    };
+    let written = OpenOptions::new()
+        .write(true)
+        .create(true)
+        .truncate(force)
+        .create_new(!force)
+        .open(&path)
+        .and_then(|mut file| file.write_all(config::SAMPLE_CONFIG.as_bytes()));
+    match written {
+        Ok(()) => {}
+        Err(err) if err.kind() == ErrorKind::AlreadyExists => {
+            eprintln!("{} already exists; pass --force to overwrite it", path);
+            std::process::exit(1);
+        }
+        Err(err) => {
+            eprintln!("{}: {}", path, err);
+            std::process::exit(1);
+        }
+    }
+}
 
-    let app = route_session_to(mocks).with_state(AppState { logger });
+async fn serve(args: ServeArgs) {
+    let config = if args.fail_on_unknown_config_keys {
+        config::read_config_strict(&args.config_path, std::io::stdin())
+    } else {
+        config::read_config(&args.config_path, std::io::stdin())
+    }
+    .unwrap();
+    if args.print_config {
+        print!("{}", config.to_yaml().unwrap());
+        return;
+    }
+
+    let pool = DbOptions {
+        path: args.db_path,
+        busy_timeout_ms: args.db_busy_timeout_ms,
+        journal_mode: args.db_journal_mode,
+    }
+    .connect()
+    .await
+    .unwrap();
+    let logger = RequestLogger::new(pool)
+        .unwrap()
+        .with_redacted_headers(args.redact_headers)
+        .with_redacted_body_fields(args.redact_body_fields);
+    logger.init().await.unwrap();
+    let mut sessions = config.sessions;
+    if args.log_default_session && !sessions.iter().any(|session| session == "default") {
+        sessions.push("default".to_string());
+    }
+    seed_sessions(&logger, &sessions).await.unwrap();
 
-    let listener = tokio::net::TcpListener::bind(("0.0.0.0", args.port))
-        .await
-        .unwrap();
+    let options = ServerOptions {
+        cors_allow_all: args.cors_allow_all,
+        cors_reflect: args.cors_reflect,
+        preflight_max_age: args.preflight_max_age,
+        base_path: args.base_path,
+        index_body: match args.index_file {
+            Some(path) => Some(std::fs::read_to_string(path).unwrap()),
+            None => args.index_body,
+        },
+        not_found_body: args.not_found_body,
+        method_override: args.method_override,
+        fallback_upstream: args.fallback_upstream,
+        disable_session_api: args.disable_session_api,
+        max_concurrent_requests: args.max_concurrent_requests,
+        load_shed: args.load_shed,
+    };
+    let state = AppState::new(logger).with_options(AppOptions {
+        parse_multipart: args.parse_multipart,
+        default_content_type: args.default_content_type.or(config.default_content_type),
+        trust_forwarded_for: args.trust_forwarded_for,
+        strict_query_parse: args.strict_query_parse,
+        log_default_session: args.log_default_session,
+        correlation_header: config.correlation_header,
+        store_response_headers: args.store_response_headers,
+    });
+    let app = build_app(config.endpoints, state, &options);
 
-    axum::serve(listener, app)
-        .with_graceful_shutdown(shutdown_signal())
-        .await
-        .unwrap();
-}
+    let listeners = match args.unix_socket {
+        Some(path) => vec![Listener::bind_unix(path).unwrap()],
+        None => {
+            let defaults = SocketOptions::default();
+            let socket_options = SocketOptions {
+                backlog: args.listen_backlog.unwrap_or(defaults.backlog),
+                nodelay: args.tcp_nodelay,
+                reuse_address: defaults.reuse_address && !args.no_reuseaddr,
+            };
+            let mut listeners = vec![];
+            for port in args.port {
+                let listener = if args.http2 {
+                    Listener::bind_h2c(port, &socket_options)
+                } else {
+                    Listener::bind_tcp(port, &socket_options)
+                };
+                listeners.push(listener.unwrap());
+            }
+            listeners
+        }
+    };
 
-async fn health() -> (StatusCode, Json<serde_json::Value>) {
-    (StatusCode::OK, Json(serde_json::json!({ "status": "ok" })))
+    let handle = serve_many(listeners, app);
+    shutdown_signal().await;
+    handle.shutdown().await.unwrap();
 }
 
 async fn shutdown_signal() {
@@ -0,0 +1,46 @@
+use axum::{
+    extract::Request,
+    http::{HeaderName, Method, StatusCode},
+    middleware::Next,
+    response::{IntoResponse, Response},
+};
+
+use crate::response::error_response;
+
+pub static METHOD_OVERRIDE_HEADER: HeaderName = HeaderName::from_static("x-http-method-override");
+
+// only the methods which can be mocked are accepted, so that a tunneled request never reaches a
+// method the routes do not know
+const OVERRIDABLE_METHODS: [Method; 5] = [
+    Method::GET,
+    Method::POST,
+    Method::PUT,
+    Method::DELETE,
+    Method::PATCH,
+];
+
+// replaces the method of POST requests with the one in X-HTTP-Method-Override
+pub async fn override_method(mut req: Request, next: Next) -> Response {
+    if req.method() != Method::POST {
+        return next.run(req).await;
+    }
+    let Some(value) = req.headers().get(&METHOD_OVERRIDE_HEADER) else {
+        return next.run(req).await;
+    };
+
+    let value = String::from_utf8_lossy(value.as_bytes()).to_ascii_uppercase();
+    match OVERRIDABLE_METHODS
+        .iter()
+        .find(|method| method.as_str() == value)
+    {
+        Some(method) => {
+            *req.method_mut() = method.clone();
+            next.run(req).await
+        }
+        None => error_response::<()>(
+            StatusCode::BAD_REQUEST,
+            format!("unsupported method override \"{}\"", value),
+        )
+        .into_response(),
+    }
+}
@@ -1,29 +1,51 @@
-use std::io::ErrorKind;
+use std::{net::SocketAddr, sync::atomic::Ordering, time::Instant};
 
 use axum::{
-    body::Body,
-    extract::{FromRequestParts, Path, Query, Request, State},
-    routing::{on, MethodFilter},
+    body::{Body, Bytes},
+    extract::{
+        rejection::PathRejection, ConnectInfo, FromRequestParts, OriginalUri, Path, Query, Request,
+        State,
+    },
+    http::{
+        header, request::Parts, uri::Authority, HeaderMap, HeaderName, HeaderValue, StatusCode,
+    },
+    response::{IntoResponse, Response},
+    routing::{on, options, MethodFilter},
     Router,
 };
-use chrono::Local;
+use chrono::{DateTime, Local};
 use futures::TryStreamExt;
 use indexmap::IndexMap;
-use serde::Deserialize;
+use serde::{Deserialize, Serialize};
 use tokio::io::AsyncReadExt;
 use tokio_util::io::StreamReader;
 
-use crate::{method::Method, request_logger::RequestLog, state::AppState};
+use crate::{
+    method::Method,
+    request_id::RequestId,
+    request_logger::{repeated_query, LoggerError, RequestLog},
+    response::error_response,
+    response_handler::{forward, ExpectContinue, RequestContext, ResponseHandler, StaticResponse},
+    state::{AppOptions, AppState},
+};
 
-#[derive(PartialEq, Debug)]
+#[derive(PartialEq, Debug, Clone, Serialize, Deserialize)]
 pub struct MockEndpoint {
     pub method: Method,
     pub path: String,
-    pub status: u16,
-    pub headers: IndexMap<String, String>,
-    pub body: String,
+    /// Host the endpoint is served for. It serves any host not taken by another endpoint when
+    /// `None`.
+    #[serde(default)]
+    pub host: Option<String>,
+    /// Name of the operation, such as `create-user`, recorded in the history.
+    #[serde(default)]
+    pub name: Option<String>,
+    pub response: ResponseHandler,
 }
 
+// upper limit of the request body read by `drain_body` responses
+const MAX_DRAINED_BODY_SIZE: usize = 1 << 30;
+
 #[derive(Deserialize)]
 struct PathParams {
     serverify_session: String,
@@ -31,82 +53,576 @@ struct PathParams {
 
 impl MockEndpoint {
     pub fn route_to(self, app: axum::Router<AppState>) -> axum::Router<AppState> {
-        let method = match self.method {
+        route_endpoints_to(app, vec![self])
+    }
+}
+
+/// Routes the endpoints. Endpoints with the same method and path share a route, which responds
+/// with the endpoint for the `Host` of the request.
+///
+/// When several paths match a request, the most specific one wins regardless of the config order.
+/// Paths are compared segment by segment from the left, and at the first segment where they
+/// differ, a static segment, such as `/users/me`, wins over a parameter, such as `/users/:id`, or a
+/// wildcard, such as `/users/*rest`. A parameter and a wildcard at the same segment, or two
+/// parameters with different names, cannot be ordered, so `parse_config` rejects the later of the
+/// two in config order.
+pub fn route_endpoints_to(
+    app: axum::Router<AppState>,
+    endpoints: Vec<MockEndpoint>,
+) -> axum::Router<AppState> {
+    let mut groups = IndexMap::<(String, String), Vec<MockEndpoint>>::new();
+    for endpoint in endpoints {
+        groups
+            .entry((endpoint.method.to_string(), endpoint.path.clone()))
+            .or_default()
+            .push(endpoint);
+    }
+    groups.into_values().fold(app, route_group_to)
+}
+
+fn route_group_to(
+    app: axum::Router<AppState>,
+    endpoints: Vec<MockEndpoint>,
+) -> axum::Router<AppState> {
+    let method = endpoints
+        .iter()
+        .flat_map(|endpoint| route_methods(&endpoint.method, &endpoint.response))
+        .map(|method| match method {
             Method::Get => MethodFilter::GET,
             Method::Post => MethodFilter::POST,
             Method::Put => MethodFilter::PUT,
             Method::Delete => MethodFilter::DELETE,
             Method::Patch => MethodFilter::PATCH,
-        };
+        })
+        .reduce(MethodFilter::or)
+        .unwrap();
 
-        let route = on(
-            method,
-            move |State(state): State<AppState>, req: Request<Body>| async move {
-                // save history
-                let (mut parts, body) = req.into_parts();
-                let Path(PathParams { serverify_session }) =
-                    Path::from_request_parts(&mut parts, &state).await.unwrap(); // TODO: handle error
-                if serverify_session != "default" {
-                    let method = match parts.method {
-                        axum::http::Method::GET => Method::Get,
-                        axum::http::Method::POST => Method::Post,
-                        axum::http::Method::PUT => Method::Put,
-                        axum::http::Method::DELETE => Method::Delete,
-                        axum::http::Method::PATCH => Method::Patch,
-                        _ => unreachable!(),
-                    };
-                    let headers = parts
-                        .headers
-                        .iter()
-                        .map(|(name, value)| {
-                            (name.to_string(), value.to_str().unwrap().to_string())
-                        })
-                        .collect();
-                    let path = parts.uri.path().to_string();
-
-                    let Query(query) =
-                        Query::<IndexMap<String, String>>::try_from_uri(&parts.uri).unwrap(); // TODO: handle error
-
-                    let mut stream = StreamReader::new(
-                        body.into_data_stream()
-                            .map_err(|err| std::io::Error::new(ErrorKind::Other, err)),
-                    );
-
-                    let mut buf: Vec<u8> = vec![];
-                    stream.read_buf(&mut buf).await.unwrap(); // TODO handle error
-
-                    let log = RequestLog {
-                        method,
-                        headers,
-                        path,
-                        query,
-                        body: String::from_utf8_lossy(&buf).to_string(),
-                        requested_at: Local::now(),
-                    };
-
-                    state
-                        .logger
-                        .log_request(&serverify_session, &log)
-                        .await
-                        .unwrap(); // TODO: handle error
+    let mut paths = vec![];
+    for path in endpoints
+        .iter()
+        .flat_map(|endpoint| route_paths(&endpoint.path, &endpoint.response))
+    {
+        if !paths.contains(&path) {
+            paths.push(path);
+        }
+    }
+    let route = on(
+        method,
+        move |State(state): State<AppState>, req: Request<Body>| async move {
+            let (mut parts, body) = req.into_parts();
+            // unknown hosts are answered like unmatched routes
+            let Some(MockEndpoint {
+                path: path_pattern,
+                name,
+                response,
+                ..
+            }) = select_by_host(&endpoints, &parts)
+            else {
+                return StatusCode::NOT_FOUND.into_response();
+            };
+            // the routes always have the session segment, so a failure here means that it
+            // cannot be decoded, such as invalid UTF-8
+            let (serverify_session, path_params) =
+                match extract_path_params(&mut parts, &state).await {
+                    Ok(params) => params,
+                    Err(rejection) => {
+                        // the URI is stripped of the nesting, which includes the session
+                        let uri = match parts.extensions.get::<OriginalUri>() {
+                            Some(OriginalUri(uri)) => uri,
+                            None => &parts.uri,
+                        };
+                        return error_response::<()>(
+                            StatusCode::BAD_REQUEST,
+                            format!(
+                                "the session segment of \"{}\" is missing or invalid: {}",
+                                uri,
+                                rejection.body_text()
+                            ),
+                        )
+                        .into_response();
+                    }
+                };
+
+            let (expect_continue, drain_body) = match response {
+                ResponseHandler::Static(response) => {
+                    (response.expect_continue, response.drain_body)
                 }
+                _ => (ExpectContinue::Continue, false),
+            };
+            let expects_continue = parts
+                .headers
+                .get(header::EXPECT)
+                .is_some_and(|value| value.as_bytes().eq_ignore_ascii_case(b"100-continue"));
 
-                // respond
-                self.headers
-                    .into_iter()
-                    .fold(axum::http::Response::builder(), |builder, (key, value)| {
-                        builder.header(key, value)
-                    })
-                    .status(axum::http::StatusCode::from_u16(self.status).unwrap())
-                    .body(self.body)
-                    .unwrap()
-            },
-        );
+            let mut buf: Vec<u8> = vec![];
+            match expect_continue {
+                ExpectContinue::Reject if expects_continue => {
+                    return StatusCode::EXPECTATION_FAILED.into_response();
+                }
+                // the body is left unread, so that hyper never sends `100 Continue`
+                ExpectContinue::Ignore if expects_continue => {}
+                // the body is read chunk by chunk and thrown away, so it is never logged
+                _ if drain_body => {
+                    let mut stream = body.into_data_stream();
+                    let mut size = 0;
+                    loop {
+                        match stream.try_next().await {
+                            Ok(Some(chunk)) => {
+                                size += chunk.len();
+                                if size > MAX_DRAINED_BODY_SIZE {
+                                    return StatusCode::PAYLOAD_TOO_LARGE.into_response();
+                                }
+                            }
+                            Ok(None) => break,
+                            // e.g. the client aborted the upload
+                            Err(err) => {
+                                return error_response::<()>(StatusCode::BAD_REQUEST, err)
+                                    .into_response();
+                            }
+                        }
+                    }
+                }
+                _ => {
+                    let mut stream =
+                        StreamReader::new(body.into_data_stream().map_err(std::io::Error::other));
+                    stream.read_to_end(&mut buf).await.unwrap(); // TODO handle error
+                }
+            }
+
+            if state.options.strict_query_parse {
+                if let Err(message) = validate_query(parts.uri.query().unwrap_or_default()) {
+                    return error_response::<()>(StatusCode::BAD_REQUEST, message).into_response();
+                }
+            }
+
+            let ctx = RequestContext {
+                session: serverify_session,
+                parts,
+                path_params,
+                body: buf.into(),
+            };
+            let requested_at = Local::now();
+            let started = Instant::now();
+            let mut res = response.respond(&ctx).await;
+            if let ResponseHandler::Static(
+                StaticResponse {
+                    delay_ms: Some(_), ..
+                }
+                | StaticResponse {
+                    latency: Some(_), ..
+                },
+            ) = response
+            {
+                let server_timing = format!("total;dur={}", started.elapsed().as_millis());
+                res.headers_mut().insert(
+                    HeaderName::from_static("server-timing"),
+                    HeaderValue::from_str(&server_timing).unwrap(),
+                );
+            }
+            if let (ResponseHandler::Static(_), Some(content_type)) =
+                (response, &state.options.default_content_type)
+            {
+                if !res.headers().contains_key(header::CONTENT_TYPE) {
+                    if let Ok(value) = HeaderValue::from_str(content_type) {
+                        res.headers_mut().insert(header::CONTENT_TYPE, value);
+                    }
+                }
+            }
+
+            let logs_session = ctx.session != "default" || state.options.log_default_session;
+            if logs_session && !state.logging_paused.load(Ordering::Relaxed) {
+                let log = new_request_log(
+                    &ctx,
+                    &state.options,
+                    path_pattern,
+                    name.as_deref(),
+                    res.status(),
+                    res.headers(),
+                    requested_at,
+                )
+                .await;
+                if let Err(err) = state.logger.log_request(&ctx.session, &log).await {
+                    return logger_error_response(&ctx.session, err);
+                }
+            }
+
+            res
+        },
+    );
+
+    let router = paths.into_iter().fold(Router::new(), |router, path| {
+        router.route(&path, route.clone())
+    });
+
+    app.nest("/mock/:serverify_session", router)
+}
+
+/// Forwards a request to an unmatched mock path to `upstream` with the session segment stripped,
+/// logging it together with the upstream response.
+pub async fn forward_unmatched(upstream: &str, state: AppState, req: Request) -> Response {
+    let (mut parts, body) = req.into_parts();
+    let Some((session, path)) = parts
+        .uri
+        .path()
+        .strip_prefix("/mock/")
+        .map(|rest| rest.split_once('/').unwrap_or((rest, "")))
+        .map(|(session, path)| (session.to_string(), format!("/{}", path)))
+    else {
+        return StatusCode::NOT_FOUND.into_response();
+    };
+    // only the methods which can be logged are forwarded
+    if ![
+        axum::http::Method::GET,
+        axum::http::Method::POST,
+        axum::http::Method::PUT,
+        axum::http::Method::DELETE,
+        axum::http::Method::PATCH,
+    ]
+    .contains(&parts.method)
+    {
+        return StatusCode::NOT_FOUND.into_response();
+    }
+
+    let path_and_query = match parts.uri.query() {
+        Some(query) => format!("{}?{}", path, query),
+        None => path,
+    };
+    parts.uri = match path_and_query.parse() {
+        Ok(uri) => uri,
+        Err(_) => return StatusCode::NOT_FOUND.into_response(),
+    };
+    let body = match axum::body::to_bytes(body, usize::MAX).await {
+        Ok(body) => body,
+        Err(err) => {
+            return error_response::<()>(StatusCode::BAD_REQUEST, err.to_string()).into_response()
+        }
+    };
+
+    let ctx = RequestContext {
+        session,
+        parts,
+        path_params: IndexMap::new(),
+        body,
+    };
+    let requested_at = Local::now();
+    let (res_parts, res_body) = forward(upstream, &ctx).await.into_parts();
+    // the upstream response is already buffered by `forward`
+    let res_body = axum::body::to_bytes(res_body, usize::MAX)
+        .await
+        .unwrap_or_default();
 
-        app.nest(
-            "/mock/:serverify_session",
-            Router::new().route(&self.path, route),
+    let logs_session = ctx.session != "default" || state.options.log_default_session;
+    if logs_session && !state.logging_paused.load(Ordering::Relaxed) {
+        let mut log = new_request_log(
+            &ctx,
+            &state.options,
+            "",
+            None,
+            res_parts.status,
+            &res_parts.headers,
+            requested_at,
         )
+        .await;
+        log.response_body = Some(String::from_utf8_lossy(&res_body).to_string());
+        log.response_content_type = res_parts
+            .headers
+            .get(header::CONTENT_TYPE)
+            .map(|value| String::from_utf8_lossy(value.as_bytes()).to_string());
+        if let Err(err) = state.logger.log_request(&ctx.session, &log).await {
+            return logger_error_response(&ctx.session, err);
+        }
+    }
+
+    Response::from_parts(res_parts, Body::from(res_body))
+}
+
+// internal errors are logged as well, since their detail in the body is seen only by clients
+fn logger_error_response(session: &str, err: LoggerError) -> Response {
+    match err {
+        LoggerError::InvalidSession(message) => {
+            error_response::<()>(StatusCode::NOT_FOUND, message).into_response()
+        }
+        LoggerError::InternalError(message) => {
+            tracing::error!(session, error = message, "failed to log a request");
+            error_response::<()>(StatusCode::INTERNAL_SERVER_ERROR, message).into_response()
+        }
+    }
+}
+
+// prefers the endpoint for the host of the request over the one without a host
+fn select_by_host<'a>(endpoints: &'a [MockEndpoint], parts: &Parts) -> Option<&'a MockEndpoint> {
+    let host = parts
+        .headers
+        .get(header::HOST)
+        .and_then(|value| value.to_str().ok())
+        .and_then(|value| value.parse::<Authority>().ok())
+        .map(|authority| authority.host().to_string())
+        .or_else(|| parts.uri.host().map(str::to_string));
+
+    endpoints
+        .iter()
+        .find(|endpoint| {
+            endpoint
+                .host
+                .as_ref()
+                .zip(host.as_ref())
+                .is_some_and(|(expected, actual)| expected.eq_ignore_ascii_case(actual))
+        })
+        .or_else(|| endpoints.iter().find(|endpoint| endpoint.host.is_none()))
+}
+
+// the query is decoded lossily by default, so broken escapes and invalid UTF-8 are checked here
+fn validate_query(query: &str) -> Result<(), String> {
+    for pair in query.split('&') {
+        let mut decoded = vec![];
+        let mut bytes = pair.bytes();
+        while let Some(byte) = bytes.next() {
+            if byte != b'%' {
+                decoded.push(byte);
+                continue;
+            }
+            let escape = [bytes.next(), bytes.next()];
+            let value = std::str::from_utf8(&escape.map(|byte| byte.unwrap_or_default()))
+                .ok()
+                .filter(|hex| hex.bytes().all(|byte| byte.is_ascii_hexdigit()))
+                .and_then(|hex| u8::from_str_radix(hex, 16).ok())
+                .ok_or_else(|| {
+                    format!("malformed query: invalid percent-encoding in \"{}\"", pair)
+                })?;
+            decoded.push(value);
+        }
+        String::from_utf8(decoded)
+            .map_err(|_| format!("malformed query: \"{}\" is not valid UTF-8", pair))?;
+    }
+    Ok(())
+}
+
+async fn extract_path_params(
+    parts: &mut Parts,
+    state: &AppState,
+) -> Result<(String, IndexMap<String, String>), PathRejection> {
+    let Path(PathParams { serverify_session }) = Path::from_request_parts(parts, state).await?;
+    let Path(path_params) = Path::from_request_parts(parts, state).await?;
+    Ok((serverify_session, path_params))
+}
+
+/// The route patterns of the endpoint, which cover more than its path for some responses.
+pub(crate) fn route_paths(path: &str, response: &ResponseHandler) -> Vec<String> {
+    let base = path.trim_end_matches('/');
+    match response {
+        // directories are served under the endpoint path and everything below it
+        ResponseHandler::Dir { .. } => {
+            vec![format!("{}/", base), format!("{}/*serverify_path", base)]
+        }
+        ResponseHandler::Resource { .. } => {
+            vec![base.to_string(), format!("{}/:serverify_id", base)]
+        }
+        _ => vec![path.to_string()],
+    }
+}
+
+// resources answer every CRUD method, whichever method they are configured under
+fn route_methods(method: &Method, response: &ResponseHandler) -> Vec<Method> {
+    match response {
+        ResponseHandler::Resource { .. } => {
+            vec![Method::Get, Method::Post, Method::Put, Method::Delete]
+        }
+        _ => vec![method.clone()],
+    }
+}
+
+/// Answers OPTIONS on every mocked path with 204 and an `Allow` header of its configured methods.
+pub fn route_options_to(
+    app: axum::Router<AppState>,
+    endpoints: &[MockEndpoint],
+) -> axum::Router<AppState> {
+    let mut allowed = IndexMap::<String, Vec<String>>::new();
+    for endpoint in endpoints {
+        for path in route_paths(&endpoint.path, &endpoint.response) {
+            let methods = allowed.entry(path).or_default();
+            for method in route_methods(&endpoint.method, &endpoint.response) {
+                let method = method.to_string().to_uppercase();
+                if !methods.contains(&method) {
+                    methods.push(method);
+                }
+            }
+        }
+    }
+
+    let router = allowed
+        .into_iter()
+        .fold(Router::new(), |router, (path, methods)| {
+            let allow = methods.join(", ");
+            router.route(
+                &path,
+                options(|| async move { (StatusCode::NO_CONTENT, [(header::ALLOW, allow)]) }),
+            )
+        });
+
+    app.nest("/mock/:serverify_session", router)
+}
+
+async fn new_request_log(
+    ctx: &RequestContext,
+    options: &AppOptions,
+    path_pattern: &str,
+    name: Option<&str>,
+    status: StatusCode,
+    response_headers: &HeaderMap,
+    requested_at: DateTime<Local>,
+) -> RequestLog {
+    let parts = &ctx.parts;
+    let method = match parts.method {
+        axum::http::Method::GET => Method::Get,
+        axum::http::Method::POST => Method::Post,
+        axum::http::Method::PUT => Method::Put,
+        axum::http::Method::DELETE => Method::Delete,
+        axum::http::Method::PATCH => Method::Patch,
+        _ => unreachable!(),
+    };
+    let headers = parts
+        .headers
+        .iter()
+        .map(|(name, value)| {
+            (
+                name.to_string(),
+                String::from_utf8_lossy(value.as_bytes()).to_string(),
+            )
+        })
+        .collect();
+    let path = parts.uri.path().to_string();
+    let content_type = parts
+        .headers
+        .get(axum::http::header::CONTENT_TYPE)
+        .map(|value| String::from_utf8_lossy(value.as_bytes()).to_string());
+
+    let Query(query) = Query::<IndexMap<String, String>>::try_from_uri(&parts.uri).unwrap(); // TODO: handle error
+    let repeated_query = repeated_query(form_urlencoded::parse(
+        parts.uri.query().unwrap_or_default().as_bytes(),
+    ));
+
+    let multipart_summary = match &content_type {
+        Some(content_type) if options.parse_multipart => {
+            summarize_multipart(content_type, &ctx.body).await
+        }
+        _ => None,
+    };
+
+    RequestLog {
+        method,
+        headers,
+        path,
+        query,
+        repeated_query,
+        body: multipart_summary.unwrap_or_else(|| String::from_utf8_lossy(&ctx.body).to_string()),
+        content_type,
+        body_size: ctx.body.len(),
+        status: status.as_u16(),
+        request_id: parts
+            .extensions
+            .get::<RequestId>()
+            .map(|RequestId(id)| id.clone()),
+        correlation_id: correlation_id(parts, response_headers, options),
+        response_body: None,
+        response_content_type: None,
+        response_headers: if options.store_response_headers {
+            response_headers
+                .iter()
+                .map(|(name, value)| {
+                    (
+                        name.to_string(),
+                        String::from_utf8_lossy(value.as_bytes()).to_string(),
+                    )
+                })
+                .collect()
+        } else {
+            IndexMap::new()
+        },
+        remote_addr: remote_addr(parts, options),
+        path_pattern: path_pattern.to_string(),
+        name: name.map(str::to_string),
+        requested_at,
+    }
+}
+
+// the request header is preferred, so that clients can tag their flows even if the response
+// does not echo it
+fn correlation_id(
+    parts: &Parts,
+    response_headers: &HeaderMap,
+    options: &AppOptions,
+) -> Option<String> {
+    let name = options.correlation_header.as_deref()?;
+    parts
+        .headers
+        .get(name)
+        .or_else(|| response_headers.get(name))
+        .map(|value| String::from_utf8_lossy(value.as_bytes()).to_string())
+}
+
+// the leftmost X-Forwarded-For entry is the original client when the proxy is trusted
+fn remote_addr(parts: &Parts, options: &AppOptions) -> Option<String> {
+    let forwarded = parts
+        .headers
+        .get("x-forwarded-for")
+        .and_then(|value| value.to_str().ok())
+        .and_then(|value| value.split(',').next())
+        .map(str::trim)
+        .filter(|addr| !addr.is_empty());
+
+    match forwarded {
+        Some(addr) if options.trust_forwarded_for => Some(addr.to_string()),
+        _ => parts
+            .extensions
+            .get::<ConnectInfo<SocketAddr>>()
+            .map(|ConnectInfo(addr)| addr.ip().to_string()),
+    }
+}
+
+// summarizes each part of a multipart/form-data body instead of keeping the raw bytes
+async fn summarize_multipart(content_type: &str, body: &Bytes) -> Option<String> {
+    let mime: mime::Mime = content_type.parse().ok()?;
+    if mime.essence_str() != "multipart/form-data" {
+        return None;
+    }
+
+    let boundary = multer::parse_boundary(content_type).ok()?;
+    let body = body.clone();
+    let mut multipart = multer::Multipart::new(
+        futures::stream::once(async move { Ok::<_, std::io::Error>(body) }),
+        boundary,
+    );
+
+    let mut parts = vec![];
+    while let Some(field) = multipart.next_field().await.ok()? {
+        let name = field.name().map(str::to_string);
+        let filename = field.file_name().map(str::to_string);
+        let content_type = field.content_type().map(|mime| mime.to_string());
+        let size = field.bytes().await.ok()?.len();
+        parts.push(serde_json::json!({
+            "name": name,
+            "filename": filename,
+            "content_type": content_type,
+            "size": size,
+        }));
+    }
+
+    Some(serde_json::json!({ "parts": parts }).to_string())
+}
+
+#[cfg(test)]
+pub mod testutil {
+    use super::*;
+
+    /// An endpoint served for any host, without a name.
+    pub fn endpoint(method: Method, path: &str, response: ResponseHandler) -> MockEndpoint {
+        MockEndpoint {
+            method,
+            path: path.to_string(),
+            host: None,
+            name: None,
+            response,
+        }
     }
 }
 
@@ -114,15 +630,18 @@ impl MockEndpoint {
 mod tests {
     use std::vec;
 
-    use crate::request_logger::testutil::new_logger;
+    use super::testutil::endpoint;
+    use crate::{request_logger::testutil::new_logger, response_handler::StaticResponse};
 
     use super::*;
 
     use axum::http::{HeaderMap, HeaderName, HeaderValue};
     use axum_test::TestServer;
+    use base64::{prelude::BASE64_STANDARD, Engine};
 
     use indexmap::indexmap;
     use pretty_assertions::assert_eq;
+    use serde_json::{json, Value};
 
     fn headers(kvs: Vec<(&'static str, &'static str)>) -> HeaderMap {
         HeaderMap::from_iter(
@@ -134,17 +653,20 @@ mod tests {
     #[tokio::test]
     async fn route_to() {
         let app = axum::Router::new();
-        let endpoint = MockEndpoint {
-            method: Method::Post,
-            path: "/hello".to_string(),
-            status: 200,
-            headers: indexmap! { "answer".to_string() => "42".to_string() },
-            body: "Hello, world!".to_string(),
-        };
+        let endpoint = endpoint(
+            Method::Post,
+            "/hello",
+            ResponseHandler::Static(StaticResponse {
+                status: 200,
+                headers: indexmap! { "answer".to_string() => "42".to_string() },
+                body: "Hello, world!".into(),
+                ..Default::default()
+            }),
+        );
 
         let logger = new_logger().await;
         logger.create_session("123").await.unwrap();
-        let state = AppState { logger };
+        let state = AppState::new(logger);
 
         let app = endpoint.route_to(app).with_state(state.clone());
         let server = TestServer::new(app).unwrap();
@@ -176,10 +698,1356 @@ mod tests {
             log.headers
         );
         assert_eq!("/hello".to_string(), log.path);
+        assert_eq!("/hello".to_string(), log.path_pattern);
         assert_eq!(
             indexmap! { "foo".to_string() => "x".to_string(), "bar".to_string() => "y".to_string() },
             log.query
         );
         assert_eq!("hello world".to_string(), log.body);
+        assert_eq!(200, log.status);
+        assert_eq!(
+            (Some("text/plain".to_string()), 11),
+            (log.content_type.clone(), log.body_size)
+        );
+    }
+
+    #[tokio::test]
+    async fn route_to_with_non_ascii_content_type() {
+        let endpoint = endpoint(
+            Method::Post,
+            "/hello",
+            ResponseHandler::Static(StaticResponse {
+                status: 200,
+                ..Default::default()
+            }),
+        );
+        let logger = new_logger().await;
+        logger.create_session("123").await.unwrap();
+        let state = AppState::new(logger);
+        let app = endpoint
+            .route_to(axum::Router::new())
+            .with_state(state.clone());
+        let server = TestServer::new(app).unwrap();
+
+        // obs-text is allowed in header values, though it is not UTF-8
+        let response = server
+            .post("/mock/123/hello")
+            .add_header(
+                header::CONTENT_TYPE,
+                HeaderValue::from_bytes(b"text/plain; name=caf\xe9").unwrap(),
+            )
+            .bytes("hello".into())
+            .await;
+
+        assert_eq!(200, response.status_code());
+        let logs = state.logger.get_session_history("123").await.unwrap();
+        assert_eq!(
+            Some("text/plain; name=caf\u{fffd}".to_string()),
+            logs[0].content_type
+        );
+    }
+
+    #[tokio::test]
+    async fn route_to_with_json_body() {
+        let endpoint = endpoint(
+            Method::Post,
+            "/users",
+            ResponseHandler::Static(StaticResponse {
+                status: 201,
+                headers: indexmap! {},
+                body: "".into(),
+                ..Default::default()
+            }),
+        );
+
+        let logger = new_logger().await;
+        logger.create_session("123").await.unwrap();
+        let state = AppState::new(logger);
+
+        let app = endpoint
+            .route_to(axum::Router::new())
+            .with_state(state.clone());
+        let server = TestServer::new(app).unwrap();
+        server
+            .post("/mock/123/users")
+            .json(&serde_json::json!({ "name": "alice" }))
+            .await;
+
+        let logs = state.logger.get_session_history("123").await.unwrap();
+        let log = logs.first().unwrap();
+        assert_eq!(
+            (Some("application/json".to_string()), 16),
+            (log.content_type.clone(), log.body_size)
+        );
+    }
+
+    #[tokio::test]
+    async fn route_to_with_dir() {
+        let root = tempfile::tempdir().unwrap();
+        std::fs::create_dir(root.path().join("nested")).unwrap();
+        std::fs::write(root.path().join("nested/hello.txt"), "Hello, dir!").unwrap();
+
+        let endpoint = endpoint(
+            Method::Get,
+            "/assets",
+            ResponseHandler::Dir {
+                root: root.path().to_str().unwrap().to_string(),
+            },
+        );
+        let state = AppState::new(new_logger().await);
+        let app = endpoint.route_to(axum::Router::new()).with_state(state);
+        let server = TestServer::new(app).unwrap();
+
+        let response = server.get("/mock/default/assets/nested/hello.txt").await;
+        assert_eq!(
+            (StatusCode::OK, "Hello, dir!".to_string()),
+            (response.status_code(), response.text())
+        );
+
+        let response = server
+            .get("/mock/default/assets/..%2Fnested/hello.txt")
+            .await;
+        assert_eq!(StatusCode::NOT_FOUND, response.status_code());
+    }
+
+    // sends a raw HTTP/1.1 request so that broken responses can be observed as they are
+    async fn send_raw_request(app: axum::Router, request: &str) -> Vec<u8> {
+        use tokio::io::AsyncWriteExt;
+
+        let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let addr = listener.local_addr().unwrap();
+        tokio::spawn(async move { axum::serve(listener, app).await });
+
+        let mut stream = tokio::net::TcpStream::connect(addr).await.unwrap();
+        stream.write_all(request.as_bytes()).await.unwrap();
+
+        // either EOF or a reset ends the response
+        let mut received = vec![];
+        let _ = stream.read_to_end(&mut received).await;
+        received
+    }
+
+    #[tokio::test]
+    async fn route_to_with_hangup() {
+        let endpoint = endpoint(
+            Method::Get,
+            "/hangup",
+            ResponseHandler::Hangup { delay_ms: 10 },
+        );
+        let logger = new_logger().await;
+        logger.create_session("123").await.unwrap();
+        let state = AppState::new(logger);
+        let app = endpoint
+            .route_to(axum::Router::new())
+            .with_state(state.clone());
+
+        let received = send_raw_request(
+            app,
+            "GET /mock/123/hangup HTTP/1.1\r\nhost: localhost\r\n\r\n",
+        )
+        .await;
+        assert_eq!("", String::from_utf8_lossy(&received));
+
+        let logs = state.logger.get_session_history("123").await.unwrap();
+        assert_eq!(
+            vec![("/hangup".to_string(), 444)],
+            logs.into_iter()
+                .map(|log| (log.path, log.status))
+                .collect::<Vec<_>>()
+        );
+    }
+
+    #[tokio::test]
+    async fn route_to_with_truncated() {
+        let endpoint = endpoint(
+            Method::Get,
+            "/download",
+            ResponseHandler::Truncated {
+                status: 200,
+                content_length: 100,
+                body: "partial".to_string(),
+            },
+        );
+        let state = AppState::new(new_logger().await);
+        let app = endpoint.route_to(axum::Router::new()).with_state(state);
+
+        let received = send_raw_request(
+            app,
+            "GET /mock/default/download HTTP/1.1\r\nhost: localhost\r\n\r\n",
+        )
+        .await;
+        let received = String::from_utf8_lossy(&received);
+        let (head, body) = received.split_once("\r\n\r\n").unwrap();
+
+        assert!(head.starts_with("HTTP/1.1 200 OK\r\n"), "{}", head);
+        assert!(head.contains("\r\ncontent-length: 100"), "{}", head);
+        assert_eq!("partial", body);
+    }
+
+    mod reset_after {
+        use super::*;
+        use pretty_assertions::assert_eq;
+        use rstest::rstest;
+
+        #[rstest]
+        #[case(None, "11\r\nHello, chunked wo\r\n")]
+        #[case(Some(5), "5\r\nHello\r\n5\r\n, chu\r\n5\r\nnked \r\n2\r\nwo\r\n")]
+        #[tokio::test]
+        async fn respond(#[case] chunk_size: Option<usize>, #[case] expected: &str) {
+            let endpoint = endpoint(
+                Method::Get,
+                "/download",
+                ResponseHandler::Static(StaticResponse {
+                    status: 200,
+                    body: "Hello, chunked world!".into(),
+                    chunk_size,
+                    reset_after: Some(17),
+                    ..Default::default()
+                }),
+            );
+            let state = AppState::new(new_logger().await);
+            let app = endpoint.route_to(axum::Router::new()).with_state(state);
+
+            let received = send_raw_request(
+                app,
+                "GET /mock/default/download HTTP/1.1\r\nhost: localhost\r\n\r\n",
+            )
+            .await;
+            let received = String::from_utf8_lossy(&received);
+            let (head, body) = received.split_once("\r\n\r\n").unwrap();
+
+            // the prefix is received, but the terminating chunk is not
+            assert!(head.starts_with("HTTP/1.1 200 OK\r\n"), "{}", head);
+            assert!(head.contains("\r\ntransfer-encoding: chunked"), "{}", head);
+            assert_eq!(expected, body);
+        }
+    }
+
+    mod empty_body {
+        use super::*;
+        use crate::response_handler::EmptyBody;
+        use pretty_assertions::assert_eq;
+        use rstest::rstest;
+
+        #[rstest]
+        #[case(EmptyBody::ContentLength, true, false)]
+        #[case(EmptyBody::Chunked, false, true)]
+        #[tokio::test]
+        async fn respond(
+            #[case] empty_body: EmptyBody,
+            #[case] has_content_length: bool,
+            #[case] is_chunked: bool,
+        ) {
+            let endpoint = endpoint(
+                Method::Get,
+                "/empty",
+                ResponseHandler::Static(StaticResponse {
+                    status: 200,
+                    empty_body,
+                    ..Default::default()
+                }),
+            );
+            let state = AppState::new(new_logger().await);
+            let app = endpoint.route_to(axum::Router::new()).with_state(state);
+
+            let received = send_raw_request(
+                app,
+                "GET /mock/default/empty HTTP/1.1\r\nhost: localhost\r\nconnection: close\r\n\r\n",
+            )
+            .await;
+            let received = String::from_utf8_lossy(&received);
+            let (head, _) = received.split_once("\r\n\r\n").unwrap();
+
+            assert_eq!(
+                (has_content_length, is_chunked),
+                (
+                    head.contains("\r\ncontent-length: 0"),
+                    head.contains("\r\ntransfer-encoding: chunked")
+                ),
+                "{}",
+                head
+            );
+        }
+    }
+
+    #[tokio::test]
+    async fn route_to_with_chunked() {
+        let endpoint = endpoint(
+            Method::Get,
+            "/chunked",
+            ResponseHandler::Static(StaticResponse {
+                status: 200,
+                body: "Hello, chunked world!".into(),
+                chunk_size: Some(8),
+                ..Default::default()
+            }),
+        );
+        let state = AppState::new(new_logger().await);
+        let app = endpoint.route_to(axum::Router::new()).with_state(state);
+
+        let received = send_raw_request(
+            app,
+            "GET /mock/default/chunked HTTP/1.1\r\nhost: localhost\r\nconnection: close\r\n\r\n",
+        )
+        .await;
+        let received = String::from_utf8_lossy(&received);
+        let (head, body) = received.split_once("\r\n\r\n").unwrap();
+
+        assert!(head.contains("\r\ntransfer-encoding: chunked"), "{}", head);
+        assert!(!head.contains("\r\ncontent-length:"), "{}", head);
+
+        // reassemble the chunked body: `<size in hex>\r\n<data>\r\n` until a zero size chunk
+        let mut reassembled = String::new();
+        let mut rest = body;
+        loop {
+            let (size, after) = rest.split_once("\r\n").unwrap();
+            let size = usize::from_str_radix(size, 16).unwrap();
+            if size == 0 {
+                break;
+            }
+            reassembled.push_str(&after[..size]);
+            rest = &after[size + 2..];
+        }
+        assert_eq!("Hello, chunked world!", reassembled);
+    }
+
+    mod default_content_type {
+        use super::*;
+        use pretty_assertions::assert_eq;
+        use rstest::rstest;
+
+        #[rstest]
+        #[case(indexmap! {}, None, None)]
+        #[case(indexmap! {}, Some("application/json"), Some("application/json"))]
+        #[case(
+            indexmap! { "Content-Type".to_string() => "text/plain".to_string() },
+            Some("application/json"),
+            Some("text/plain")
+        )]
+        #[tokio::test]
+        async fn respond(
+            #[case] headers: IndexMap<String, String>,
+            #[case] default_content_type: Option<&str>,
+            #[case] expected: Option<&str>,
+        ) {
+            let endpoint = endpoint(
+                Method::Get,
+                "/hello",
+                ResponseHandler::Static(StaticResponse {
+                    status: 200,
+                    headers,
+                    body: "{}".into(),
+                    ..Default::default()
+                }),
+            );
+            let state = AppState::new(new_logger().await).with_options(AppOptions {
+                default_content_type: default_content_type.map(str::to_string),
+                ..Default::default()
+            });
+            let app = endpoint.route_to(axum::Router::new()).with_state(state);
+            let server = TestServer::new(app).unwrap();
+
+            let response = server.get("/mock/default/hello").await;
+
+            assert_eq!(
+                expected,
+                response
+                    .maybe_header("content-type")
+                    .as_ref()
+                    .map(|value| value.to_str().unwrap())
+            );
+        }
+    }
+
+    #[tokio::test]
+    async fn route_options_to_lists_configured_methods() {
+        let endpoints = vec![
+            endpoint(
+                Method::Get,
+                "/hello",
+                ResponseHandler::Static(StaticResponse::default()),
+            ),
+            endpoint(
+                Method::Post,
+                "/hello",
+                ResponseHandler::Static(StaticResponse::default()),
+            ),
+            endpoint(
+                Method::Delete,
+                "/goodbye",
+                ResponseHandler::Static(StaticResponse::default()),
+            ),
+        ];
+        let state = AppState::new(new_logger().await);
+        let app = route_options_to(axum::Router::new(), &endpoints);
+        let app = endpoints
+            .into_iter()
+            .fold(app, |app, endpoint| endpoint.route_to(app))
+            .with_state(state);
+        let server = TestServer::new(app).unwrap();
+
+        let hello = server
+            .method(axum::http::Method::OPTIONS, "/mock/default/hello")
+            .await;
+        let goodbye = server
+            .method(axum::http::Method::OPTIONS, "/mock/default/goodbye")
+            .await;
+
+        assert_eq!(
+            (
+                (StatusCode::NO_CONTENT, "GET, POST"),
+                (StatusCode::NO_CONTENT, "DELETE")
+            ),
+            (
+                (hello.status_code(), hello.header("allow").to_str().unwrap()),
+                (
+                    goodbye.status_code(),
+                    goodbye.header("allow").to_str().unwrap()
+                )
+            )
+        );
+    }
+
+    #[tokio::test]
+    async fn route_to_with_connection_close() {
+        use tokio::io::AsyncWriteExt;
+
+        let endpoint = endpoint(
+            Method::Get,
+            "/legacy",
+            ResponseHandler::Static(StaticResponse {
+                status: 200,
+                body: "bye".into(),
+                connection_close: true,
+                ..Default::default()
+            }),
+        );
+        let state = AppState::new(new_logger().await);
+        let app = endpoint.route_to(axum::Router::new()).with_state(state);
+
+        let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let addr = listener.local_addr().unwrap();
+        tokio::spawn(async move { axum::serve(listener, app).await });
+
+        // two pipelined keep-alive requests; only the first one is answered before closing
+        let request = "GET /mock/default/legacy HTTP/1.1\r\nhost: localhost\r\n\r\n";
+        let mut stream = tokio::net::TcpStream::connect(addr).await.unwrap();
+        stream
+            .write_all(format!("{}{}", request, request).as_bytes())
+            .await
+            .unwrap();
+
+        let mut received = vec![];
+        tokio::time::timeout(
+            std::time::Duration::from_secs(5),
+            stream.read_to_end(&mut received),
+        )
+        .await
+        .expect("connection should be closed by the server")
+        .unwrap();
+        let received = String::from_utf8_lossy(&received);
+
+        assert!(
+            received.contains("\r\nconnection: close\r\n"),
+            "{}",
+            received
+        );
+        assert_eq!(
+            1,
+            received.matches("HTTP/1.1 200 OK").count(),
+            "{}",
+            received
+        );
+    }
+
+    mod remote_addr {
+        use super::*;
+        use pretty_assertions::assert_eq;
+        use rstest::rstest;
+
+        #[rstest]
+        #[case(false, None, Some("127.0.0.1"))]
+        #[case(false, Some("203.0.113.5"), Some("127.0.0.1"))]
+        #[case(true, Some("203.0.113.5, 10.0.0.1"), Some("203.0.113.5"))]
+        #[case(true, None, Some("127.0.0.1"))]
+        fn resolve(
+            #[case] trust_forwarded_for: bool,
+            #[case] forwarded_for: Option<&str>,
+            #[case] expected: Option<&str>,
+        ) {
+            let builder = axum::http::Request::get("/");
+            let builder = match forwarded_for {
+                Some(value) => builder.header("x-forwarded-for", value),
+                None => builder,
+            };
+            let (mut parts, _) = builder.body(()).unwrap().into_parts();
+            parts
+                .extensions
+                .insert(ConnectInfo(SocketAddr::from(([127, 0, 0, 1], 54321))));
+            let options = AppOptions {
+                trust_forwarded_for,
+                ..Default::default()
+            };
+
+            assert_eq!(expected.map(str::to_string), remote_addr(&parts, &options));
+        }
+    }
+
+    mod strict_query_parse {
+        use super::*;
+        use pretty_assertions::assert_eq;
+        use rstest::rstest;
+
+        #[rstest]
+        #[case(false, "a=%FF&b=%zz", StatusCode::OK, None)]
+        #[case(true, "a=1&b=%E3%81%82+c", StatusCode::OK, None)]
+        #[case(
+            true,
+            "a=1&b=%zz",
+            StatusCode::BAD_REQUEST,
+            Some("malformed query: invalid percent-encoding in \"b=%zz\"")
+        )]
+        #[case(
+            true,
+            "a=%F",
+            StatusCode::BAD_REQUEST,
+            Some("malformed query: invalid percent-encoding in \"a=%F\"")
+        )]
+        #[case(
+            true,
+            "a=%FF",
+            StatusCode::BAD_REQUEST,
+            Some("malformed query: \"a=%FF\" is not valid UTF-8")
+        )]
+        #[tokio::test]
+        async fn respond(
+            #[case] strict_query_parse: bool,
+            #[case] query: &str,
+            #[case] expected_status: StatusCode,
+            #[case] expected_message: Option<&str>,
+        ) {
+            let endpoint = endpoint(
+                Method::Get,
+                "/hello",
+                ResponseHandler::Static(StaticResponse {
+                    status: 200,
+                    ..Default::default()
+                }),
+            );
+            let state = AppState::new(new_logger().await).with_options(AppOptions {
+                strict_query_parse,
+                ..Default::default()
+            });
+            let app = endpoint.route_to(axum::Router::new()).with_state(state);
+            let server = TestServer::new(app).unwrap();
+
+            let response = server
+                .get("/mock/default/hello")
+                .add_raw_query_param(query)
+                .await;
+
+            assert_eq!(expected_status, response.status_code());
+            if let Some(message) = expected_message {
+                assert_eq!(
+                    serde_json::json!({ "serverify_error": { "message": message } }),
+                    response.json::<serde_json::Value>()
+                );
+            }
+        }
+    }
+
+    #[tokio::test]
+    async fn route_to_with_drain_body() {
+        let endpoint = endpoint(
+            Method::Post,
+            "/upload",
+            ResponseHandler::Static(StaticResponse {
+                status: 200,
+                body: "uploaded".into(),
+                drain_body: true,
+                ..Default::default()
+            }),
+        );
+        let logger = new_logger().await;
+        logger.create_session("upload").await.unwrap();
+        let state = AppState::new(logger);
+        let app = endpoint
+            .route_to(axum::Router::new())
+            .with_state(state.clone());
+        let server = TestServer::new(app).unwrap();
+
+        let response = server
+            .post("/mock/upload/upload")
+            .bytes(vec![b'x'; 16 * 1024 * 1024].into())
+            .await;
+
+        assert_eq!(
+            (StatusCode::OK, "uploaded"),
+            (response.status_code(), response.text().as_str())
+        );
+        let logs = state.logger.get_session_history("upload").await.unwrap();
+        assert_eq!(0, logs[0].body_size);
+    }
+
+    #[tokio::test]
+    async fn route_to_with_drain_body_aborted() {
+        use tower::ServiceExt;
+
+        let endpoint = endpoint(
+            Method::Post,
+            "/upload",
+            ResponseHandler::Static(StaticResponse {
+                drain_body: true,
+                ..Default::default()
+            }),
+        );
+        let state = AppState::new(new_logger().await);
+        let app = endpoint.route_to(axum::Router::new()).with_state(state);
+        let body = futures::stream::iter(vec![
+            Ok(Bytes::from("x")),
+            Err(std::io::Error::other("connection reset")),
+        ]);
+        let req = axum::http::Request::post("/mock/default/upload")
+            .body(Body::from_stream(body))
+            .unwrap();
+
+        let response = app.oneshot(req).await.unwrap();
+
+        assert_eq!(StatusCode::BAD_REQUEST, response.status());
+        let body = axum::body::to_bytes(response.into_body(), usize::MAX)
+            .await
+            .unwrap();
+        assert_eq!(
+            json!({ "serverify_error": { "message": "connection reset" } }),
+            serde_json::from_slice::<Value>(&body).unwrap()
+        );
+    }
+
+    #[tokio::test]
+    async fn route_to_with_base64_body() {
+        const PNG: &str = "iVBORw0KGgoAAAANSUhEUgAAAAEAAAABCAYAAAAfFcSJAAAADUlEQVR42mP4z8DwHwAFAAIBoLinxQAAAABJRU5ErkJggg==";
+        let src = format!(
+            "paths:\n  /pixel.png:\n    get:\n      response:\n        headers:\n          Content-Type: image/png\n        body_base64: {}\n",
+            PNG
+        );
+        let endpoint = crate::config::parse_config(&src)
+            .unwrap()
+            .endpoints
+            .pop()
+            .unwrap();
+        let state = AppState::new(new_logger().await);
+        let app = endpoint.route_to(axum::Router::new()).with_state(state);
+        let server = TestServer::new(app).unwrap();
+
+        let response = server.get("/mock/default/pixel.png").await;
+
+        assert_eq!(
+            (StatusCode::OK, BASE64_STANDARD.decode(PNG).unwrap()),
+            (response.status_code(), response.as_bytes().to_vec())
+        );
+    }
+
+    #[tokio::test]
+    async fn route_to_with_invalid_session_segment() {
+        let endpoint = endpoint(
+            Method::Get,
+            "/hello",
+            ResponseHandler::Static(StaticResponse {
+                status: 200,
+                ..Default::default()
+            }),
+        );
+        let state = AppState::new(new_logger().await);
+        let app = endpoint.route_to(axum::Router::new()).with_state(state);
+        let server = TestServer::new(app).unwrap();
+
+        let response = server.get("/mock/%FF/hello").await;
+
+        assert_eq!(
+            (
+                StatusCode::BAD_REQUEST,
+                json!({ "serverify_error": { "message": "the session segment of \"http://localhost/mock/%FF/hello\" is missing or invalid: Invalid URL: Invalid UTF-8 in `serverify_session`" } })
+            ),
+            (response.status_code(), response.json::<Value>())
+        );
+    }
+
+    mod virtual_hosts {
+        use super::*;
+        use pretty_assertions::assert_eq;
+        use rstest::rstest;
+
+        fn hosted_endpoint(host: Option<&str>, body: &str) -> MockEndpoint {
+            MockEndpoint {
+                host: host.map(str::to_string),
+                ..endpoint(
+                    Method::Get,
+                    "/hello",
+                    ResponseHandler::Static(StaticResponse {
+                        status: 200,
+                        body: body.to_string().into(),
+                        ..Default::default()
+                    }),
+                )
+            }
+        }
+
+        #[rstest]
+        #[case(false, "a.example.com", 200, "for a")]
+        #[case(false, "B.example.com:8080", 200, "for b")]
+        #[case(false, "c.example.com", 404, "")]
+        #[case(true, "c.example.com", 200, "for others")]
+        #[tokio::test]
+        async fn respond(
+            #[case] with_fallback: bool,
+            #[case] host: &'static str,
+            #[case] expected_status: u16,
+            #[case] expected_body: &str,
+        ) {
+            let mut endpoints = vec![
+                hosted_endpoint(Some("a.example.com"), "for a"),
+                hosted_endpoint(Some("b.example.com"), "for b"),
+            ];
+            if with_fallback {
+                endpoints.push(hosted_endpoint(None, "for others"));
+            }
+            let logger = new_logger().await;
+            logger.create_session("123").await.unwrap();
+            let app = route_endpoints_to(axum::Router::new(), endpoints)
+                .with_state(AppState::new(logger));
+            let server = TestServer::new(app).unwrap();
+
+            let response = server
+                .get("/mock/123/hello")
+                .add_header(header::HOST, HeaderValue::from_static(host))
+                .await;
+
+            assert_eq!(
+                (expected_status, expected_body),
+                (response.status_code().as_u16(), response.text().as_str())
+            );
+        }
+    }
+
+    mod correlation_id {
+        use super::*;
+        use crate::session_endpoint::route_session_to;
+        use pretty_assertions::assert_eq;
+
+        #[tokio::test]
+        async fn logged_and_filterable() {
+            let endpoint = endpoint(
+                Method::Get,
+                "/hello",
+                ResponseHandler::Static(StaticResponse {
+                    status: 200,
+                    headers: indexmap! { "X-Flow-Id".to_string() => "from-response".to_string() },
+                    ..Default::default()
+                }),
+            );
+            let logger = new_logger().await;
+            logger.create_session("flows").await.unwrap();
+            let state = AppState::new(logger).with_options(AppOptions {
+                correlation_header: Some("X-Flow-Id".to_string()),
+                ..Default::default()
+            });
+            let app =
+                route_session_to(endpoint.route_to(axum::Router::new())).with_state(state.clone());
+            let server = TestServer::new(app).unwrap();
+
+            for flow in [Some("flow-1"), Some("flow-2"), Some("flow-1"), None] {
+                let request = server.get("/mock/flows/hello");
+                match flow {
+                    Some(flow) => {
+                        request
+                            .add_header(
+                                HeaderName::from_static("x-flow-id"),
+                                HeaderValue::from_static(flow),
+                            )
+                            .await
+                    }
+                    None => request.await,
+                };
+            }
+
+            let logs = state.logger.get_session_history("flows").await.unwrap();
+            assert_eq!(
+                vec![
+                    Some("flow-1"),
+                    Some("flow-2"),
+                    Some("flow-1"),
+                    Some("from-response")
+                ],
+                logs.iter()
+                    .map(|log| log.correlation_id.as_deref())
+                    .collect::<Vec<_>>()
+            );
+
+            let response = server
+                .get("/session/flows")
+                .add_query_param("correlation_id", "flow-1")
+                .await;
+            assert_eq!(
+                2,
+                response.json::<Value>()["histories"]
+                    .as_array()
+                    .unwrap()
+                    .len()
+            );
+        }
+    }
+
+    mod endpoint_name {
+        use super::*;
+        use crate::session_endpoint::route_session_to;
+        use pretty_assertions::assert_eq;
+
+        fn named_endpoint(method: Method, name: Option<&str>) -> MockEndpoint {
+            MockEndpoint {
+                name: name.map(str::to_string),
+                ..endpoint(
+                    method,
+                    "/users",
+                    ResponseHandler::Static(StaticResponse {
+                        status: 200,
+                        ..Default::default()
+                    }),
+                )
+            }
+        }
+
+        #[tokio::test]
+        async fn logged_and_filterable() {
+            let endpoints = vec![
+                named_endpoint(Method::Post, Some("create-user")),
+                named_endpoint(Method::Get, None),
+            ];
+            let logger = new_logger().await;
+            logger.create_session("names").await.unwrap();
+            let state = AppState::new(logger);
+            let app = route_session_to(route_endpoints_to(axum::Router::new(), endpoints))
+                .with_state(state.clone());
+            let server = TestServer::new(app).unwrap();
+
+            server.post("/mock/names/users").await;
+            server.get("/mock/names/users").await;
+            server.post("/mock/names/users").await;
+
+            let logs = state.logger.get_session_history("names").await.unwrap();
+            assert_eq!(
+                vec![Some("create-user"), None, Some("create-user")],
+                logs.iter()
+                    .map(|log| log.name.as_deref())
+                    .collect::<Vec<_>>()
+            );
+
+            let response = server
+                .get("/session/names")
+                .add_query_param("name", "create-user")
+                .await;
+            let histories = response.json::<Value>()["histories"].clone();
+            assert_eq!(
+                vec![Value::from("create-user"), Value::from("create-user")],
+                histories
+                    .as_array()
+                    .unwrap()
+                    .iter()
+                    .map(|history| history["name"].clone())
+                    .collect::<Vec<_>>()
+            );
+        }
+    }
+
+    mod logger_error {
+        use super::*;
+        use crate::request_logger::testutil::close;
+        use pretty_assertions::assert_eq;
+        use std::sync::Arc;
+
+        fn new_endpoint() -> MockEndpoint {
+            endpoint(
+                Method::Get,
+                "/hello",
+                ResponseHandler::Static(StaticResponse {
+                    status: 200,
+                    ..Default::default()
+                }),
+            )
+        }
+
+        #[tokio::test]
+        async fn internal_error_is_logged() {
+            let logger = new_logger().await;
+            logger.create_session("mysession").await.unwrap();
+            close(&logger).await;
+            let app = new_endpoint()
+                .route_to(axum::Router::new())
+                .with_state(AppState::new(logger));
+            let server = TestServer::new(app).unwrap();
+            // formatted as by the binary, into a file which is read back
+            let output = tempfile::NamedTempFile::new().unwrap();
+            let subscriber = tracing_subscriber::fmt()
+                .with_writer(Arc::new(output.reopen().unwrap()))
+                .with_ansi(false)
+                .finish();
+            let _guard = tracing::subscriber::set_default(subscriber);
+
+            let response = server.get("/mock/mysession/hello").await;
+
+            assert_eq!(StatusCode::INTERNAL_SERVER_ERROR, response.status_code());
+            let output = std::fs::read_to_string(output.path()).unwrap();
+            let lines = output.lines().collect::<Vec<_>>();
+            assert_eq!(1, lines.len(), "{}", output);
+            assert!(
+                lines[0].contains(" ERROR serverify::mock_endpoint: failed to log a request session=\"mysession\""),
+                "{}",
+                lines[0]
+            );
+        }
+
+        #[tokio::test]
+        async fn unknown_session_is_not_found() {
+            let app = new_endpoint()
+                .route_to(axum::Router::new())
+                .with_state(AppState::new(new_logger().await));
+            let server = TestServer::new(app).unwrap();
+
+            let response = server.get("/mock/unknown/hello").await;
+
+            assert_eq!(
+                (
+                    StatusCode::NOT_FOUND,
+                    json!({ "serverify_error": { "message": "session \"unknown\" is not found" } })
+                ),
+                (response.status_code(), response.json::<Value>())
+            );
+        }
+    }
+
+    mod precedence {
+        use super::*;
+        use pretty_assertions::assert_eq;
+        use rstest::rstest;
+
+        #[rstest]
+        #[case("/users/:id", "/users/me", "exact")]
+        #[case("/users/:id", "/users/42", "other")]
+        #[case("/users/*rest", "/users/me", "exact")]
+        #[case("/users/*rest", "/users/42/posts", "other")]
+        #[tokio::test]
+        async fn exact_path_wins(
+            #[case] other_path: &str,
+            #[case] path: &str,
+            #[case] expected: &str,
+        ) {
+            // the other path comes first, so that the config order cannot decide
+            let endpoints = [(other_path, "other"), ("/users/me", "exact")]
+                .into_iter()
+                .map(|(path, body)| {
+                    endpoint(
+                        Method::Get,
+                        path,
+                        ResponseHandler::Static(StaticResponse {
+                            status: 200,
+                            body: body.into(),
+                            ..Default::default()
+                        }),
+                    )
+                })
+                .collect();
+            let app = route_endpoints_to(axum::Router::new(), endpoints)
+                .with_state(AppState::new(new_logger().await));
+            let server = TestServer::new(app).unwrap();
+
+            let response = server.get(&format!("/mock/default{}", path)).await;
+
+            assert_eq!(expected, response.text());
+        }
+
+        #[rstest]
+        #[case(vec!["/:kind/me", "/users/:id"])]
+        #[case(vec!["/users/:id", "/:kind/me"])]
+        #[tokio::test]
+        async fn leftmost_static_segment_wins_in_any_config_order(
+            #[case] paths: Vec<&'static str>,
+        ) {
+            let endpoints = paths
+                .into_iter()
+                .map(|path| {
+                    endpoint(
+                        Method::Get,
+                        path,
+                        ResponseHandler::Static(StaticResponse {
+                            status: 200,
+                            body: path.into(),
+                            ..Default::default()
+                        }),
+                    )
+                })
+                .collect();
+            let app = route_endpoints_to(axum::Router::new(), endpoints)
+                .with_state(AppState::new(new_logger().await));
+            let server = TestServer::new(app).unwrap();
+
+            let response = server.get("/mock/default/users/me").await;
+
+            assert_eq!("/users/:id", response.text());
+        }
+    }
+
+    mod store_response_headers {
+        use super::*;
+        use pretty_assertions::assert_eq;
+        use rstest::rstest;
+
+        #[rstest]
+        #[case(false, indexmap! {})]
+        #[case(true, indexmap! { "x-answer".to_string() => "42".to_string() })]
+        #[tokio::test]
+        async fn respond(
+            #[case] store_response_headers: bool,
+            #[case] expected: IndexMap<String, String>,
+        ) {
+            let endpoint = endpoint(
+                Method::Get,
+                "/hello",
+                ResponseHandler::Static(StaticResponse {
+                    status: 200,
+                    headers: indexmap! { "X-Answer".to_string() => "42".to_string() },
+                    ..Default::default()
+                }),
+            );
+            let logger = new_logger().await;
+            logger.create_session("mysession").await.unwrap();
+            let state = AppState::new(logger).with_options(AppOptions {
+                store_response_headers,
+                ..Default::default()
+            });
+            let app = endpoint
+                .route_to(axum::Router::new())
+                .with_state(state.clone());
+            let server = TestServer::new(app).unwrap();
+
+            server.get("/mock/mysession/hello").await;
+
+            let logs = state.logger.get_session_history("mysession").await.unwrap();
+            assert_eq!(
+                vec![expected],
+                logs.into_iter()
+                    .map(|log| log.response_headers)
+                    .collect::<Vec<_>>()
+            );
+        }
+    }
+
+    mod log_default_session {
+        use super::*;
+        use pretty_assertions::assert_eq;
+        use rstest::rstest;
+
+        #[rstest]
+        #[case(false, 0)]
+        #[case(true, 1)]
+        #[tokio::test]
+        async fn respond(#[case] log_default_session: bool, #[case] expected_logs: usize) {
+            let endpoint = endpoint(
+                Method::Get,
+                "/hello",
+                ResponseHandler::Static(StaticResponse {
+                    status: 200,
+                    ..Default::default()
+                }),
+            );
+            let logger = new_logger().await;
+            logger.create_session("default").await.unwrap();
+            let state = AppState::new(logger).with_options(AppOptions {
+                log_default_session,
+                ..Default::default()
+            });
+            let app = endpoint
+                .route_to(axum::Router::new())
+                .with_state(state.clone());
+            let server = TestServer::new(app).unwrap();
+
+            server.get("/mock/default/hello").await;
+
+            let logs = state.logger.get_session_history("default").await.unwrap();
+            assert_eq!(expected_logs, logs.len());
+        }
+    }
+
+    mod expect_continue {
+        use super::*;
+        use pretty_assertions::assert_eq;
+        use rstest::rstest;
+        use tokio::io::{AsyncBufReadExt, AsyncWriteExt, BufReader};
+
+        #[rstest]
+        #[case(ExpectContinue::Continue, "HTTP/1.1 100 Continue")]
+        #[case(ExpectContinue::Ignore, "HTTP/1.1 200 OK")]
+        #[case(ExpectContinue::Reject, "HTTP/1.1 417 Expectation Failed")]
+        #[tokio::test]
+        async fn respond(#[case] expect_continue: ExpectContinue, #[case] expected: &str) {
+            let endpoint = endpoint(
+                Method::Post,
+                "/upload",
+                ResponseHandler::Static(StaticResponse {
+                    status: 200,
+                    expect_continue,
+                    ..Default::default()
+                }),
+            );
+            let state = AppState::new(new_logger().await);
+            let app = endpoint.route_to(axum::Router::new()).with_state(state);
+
+            let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+            let addr = listener.local_addr().unwrap();
+            tokio::spawn(async move { axum::serve(listener, app).await });
+
+            // only the head is sent, as a client waiting for `100 Continue` does
+            let mut stream = tokio::net::TcpStream::connect(addr).await.unwrap();
+            stream
+                .write_all(
+                    b"POST /mock/default/upload HTTP/1.1\r\nhost: localhost\r\n\
+                      content-length: 5\r\nexpect: 100-continue\r\n\r\n",
+                )
+                .await
+                .unwrap();
+
+            let mut status_line = String::new();
+            tokio::time::timeout(
+                std::time::Duration::from_secs(5),
+                BufReader::new(stream).read_line(&mut status_line),
+            )
+            .await
+            .unwrap()
+            .unwrap();
+
+            assert_eq!(expected, status_line.trim_end());
+        }
+    }
+
+    #[tokio::test]
+    async fn route_to_with_raw() {
+        let endpoint = endpoint(
+            Method::Get,
+            "/broken",
+            ResponseHandler::Raw {
+                status: 200,
+                headers: indexmap! {},
+                body: r#"{"a":"#.to_string(),
+            },
+        );
+        let state = AppState::new(new_logger().await).with_options(AppOptions {
+            default_content_type: Some("application/json".to_string()),
+            ..Default::default()
+        });
+        let app = endpoint.route_to(axum::Router::new()).with_state(state);
+        let server = TestServer::new(app).unwrap();
+
+        let response = server.get("/mock/default/broken").await;
+
+        assert_eq!(
+            (StatusCode::OK, None, br#"{"a":"#.as_slice()),
+            (
+                response.status_code(),
+                response.maybe_header("content-type"),
+                response.as_bytes().as_ref()
+            )
+        );
+    }
+
+    #[tokio::test]
+    async fn route_to_with_delay() {
+        let endpoint = endpoint(
+            Method::Get,
+            "/slow",
+            ResponseHandler::Static(StaticResponse {
+                status: 200,
+                delay_ms: Some(50),
+                ..Default::default()
+            }),
+        );
+        let state = AppState::new(new_logger().await);
+        let app = endpoint.route_to(axum::Router::new()).with_state(state);
+        let server = TestServer::new(app).unwrap();
+
+        let response = server.get("/mock/default/slow").await;
+        let server_timing = response.header("server-timing");
+        let dur = server_timing
+            .to_str()
+            .unwrap()
+            .strip_prefix("total;dur=")
+            .and_then(|dur| dur.parse::<u128>().ok());
+
+        assert!(dur.is_some_and(|dur| dur >= 50), "{:?}", server_timing);
+    }
+
+    #[tokio::test]
+    async fn route_to_with_query_echo() {
+        let endpoint = endpoint(
+            Method::Post,
+            "/echo",
+            ResponseHandler::QueryEcho {
+                param: "tag".to_string(),
+            },
+        );
+        let logger = new_logger().await;
+        logger.create_session("tags").await.unwrap();
+        let state = AppState::new(logger);
+        let app = endpoint
+            .route_to(axum::Router::new())
+            .with_state(state.clone());
+        let server = TestServer::new(app).unwrap();
+
+        let tags = (0..500).map(|i| format!("tag{}", i)).collect::<Vec<_>>();
+        let query = tags
+            .iter()
+            .map(|tag| format!("tag={}", tag))
+            .collect::<Vec<_>>()
+            .join("&");
+        let response = server
+            .post("/mock/tags/echo")
+            .add_raw_query_param(&query)
+            .await;
+
+        assert_eq!(StatusCode::OK, response.status_code());
+        assert_eq!(tags, response.json::<Vec<String>>());
+
+        let logs = state.logger.get_session_history("tags").await.unwrap();
+        assert_eq!(1, logs.len());
+        assert_eq!(
+            indexmap! { "tag".to_string() => tags },
+            logs[0].repeated_query
+        );
+        assert_eq!(Some(&"tag499".to_string()), logs[0].query.get("tag"));
+    }
+
+    #[tokio::test]
+    async fn route_to_with_resource() {
+        let endpoint = endpoint(
+            Method::Get,
+            "/users",
+            ResponseHandler::Resource {
+                collection: "users".to_string(),
+                collections: Default::default(),
+            },
+        );
+        let logger = new_logger().await;
+        logger.create_session("crud").await.unwrap();
+        let state = AppState::new(logger);
+        let app = endpoint.route_to(axum::Router::new()).with_state(state);
+        let server = TestServer::new(app).unwrap();
+
+        let created = server
+            .post("/mock/crud/users")
+            .json(&json!({ "name": "alice" }))
+            .await;
+        assert_eq!(
+            (StatusCode::CREATED, json!({ "name": "alice", "id": "1" })),
+            (created.status_code(), created.json())
+        );
+        server
+            .post("/mock/crud/users")
+            .json(&json!({ "name": "bob" }))
+            .await;
+
+        let replaced = server
+            .put("/mock/crud/users/1")
+            .json(&json!({ "name": "carol" }))
+            .await;
+        assert_eq!(
+            (StatusCode::OK, json!({ "name": "carol", "id": "1" })),
+            (replaced.status_code(), replaced.json())
+        );
+        let found = server.get("/mock/crud/users/1").await;
+        assert_eq!(json!({ "name": "carol", "id": "1" }), found.json::<Value>());
+
+        let deleted = server.delete("/mock/crud/users/2").await;
+        assert_eq!(StatusCode::NO_CONTENT, deleted.status_code());
+        let listed = server.get("/mock/crud/users").await;
+        assert_eq!(
+            json!([{ "name": "carol", "id": "1" }]),
+            listed.json::<Value>()
+        );
+
+        for response in [
+            server.get("/mock/crud/users/2").await,
+            server
+                .put("/mock/crud/users/2")
+                .json(&json!({ "name": "dave" }))
+                .await,
+            server.delete("/mock/crud/users/2").await,
+        ] {
+            assert_eq!(StatusCode::NOT_FOUND, response.status_code());
+        }
+    }
+
+    mod multipart {
+        use super::*;
+        use pretty_assertions::assert_eq;
+
+        const BODY: &str = "--XBOUNDARY\r\n\
+            Content-Disposition: form-data; name=\"title\"\r\n\
+            \r\n\
+            hello\r\n\
+            --XBOUNDARY\r\n\
+            Content-Disposition: form-data; name=\"file\"; filename=\"a.txt\"\r\n\
+            Content-Type: text/plain\r\n\
+            \r\n\
+            file content\r\n\
+            --XBOUNDARY--\r\n";
+
+        async fn upload(parse_multipart: bool) -> RequestLog {
+            let endpoint = endpoint(
+                Method::Post,
+                "/upload",
+                ResponseHandler::Static(StaticResponse {
+                    status: 201,
+                    ..Default::default()
+                }),
+            );
+            let logger = new_logger().await;
+            logger.create_session("123").await.unwrap();
+            let state = AppState::new(logger).with_options(AppOptions {
+                parse_multipart,
+                ..Default::default()
+            });
+            let app = endpoint
+                .route_to(axum::Router::new())
+                .with_state(state.clone());
+            let server = TestServer::new(app).unwrap();
+
+            server
+                .post("/mock/123/upload")
+                .content_type("multipart/form-data; boundary=XBOUNDARY")
+                .bytes(BODY.into())
+                .await;
+
+            state.logger.get_session_history("123").await.unwrap()[0].clone()
+        }
+
+        #[tokio::test]
+        async fn when_enabled() {
+            let log = upload(true).await;
+
+            assert_eq!(
+                serde_json::json!({
+                    "parts": [
+                        { "name": "title", "filename": null, "content_type": null, "size": 5 },
+                        { "name": "file", "filename": "a.txt", "content_type": "text/plain", "size": 12 },
+                    ]
+                }),
+                serde_json::from_str::<serde_json::Value>(&log.body).unwrap()
+            );
+            assert_eq!(BODY.len(), log.body_size);
+        }
+
+        #[tokio::test]
+        async fn when_disabled() {
+            let log = upload(false).await;
+
+            assert_eq!(BODY, log.body);
+        }
     }
 }
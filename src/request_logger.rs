@@ -1,45 +1,102 @@
+use std::{borrow::Cow, time::Duration};
+
 use chrono::{DateTime, Local};
 use indexmap::IndexMap;
 use serde::Serialize;
-use sqlx::{error::ErrorKind, prelude::FromRow, SqlitePool};
+use sqlx::{
+    error::ErrorKind,
+    prelude::FromRow,
+    sqlite::{SqliteConnectOptions, SqliteJournalMode},
+    SqlitePool,
+};
 
 use crate::method::Method;
 
 #[derive(Serialize, PartialEq, Debug, Clone)]
 pub struct RequestLog {
     pub method: Method,
+    /// Headers of the request. Their names are always lowercase, whatever casing the client sent,
+    /// since `http::HeaderName` normalizes them, so no option to normalize them is needed.
     pub headers: IndexMap<String, String>,
     pub path: String,
     pub query: IndexMap<String, String>,
+    /// Every value of the query parameters given more than once, in the order they were sent,
+    /// while `query` holds only the last of them.
+    pub repeated_query: IndexMap<String, Vec<String>>,
     pub body: String,
+    pub content_type: Option<String>,
+    pub body_size: usize,
+    pub status: u16,
+    pub request_id: Option<String>,
+    /// Value of the header named by `correlation_header` in the config.
+    pub correlation_id: Option<String>,
+    /// Body of the response, which is only recorded for requests forwarded to the fallback
+    /// upstream.
+    pub response_body: Option<String>,
+    pub response_content_type: Option<String>,
+    /// Headers of the response, which are only recorded with `--store-response-headers`.
+    pub response_headers: IndexMap<String, String>,
+    pub remote_addr: Option<String>,
+    /// The configured path of the endpoint, such as `/users/:id`.
+    pub path_pattern: String,
+    /// The configured name of the endpoint, such as `create-user`.
+    pub name: Option<String>,
     pub requested_at: DateTime<Local>,
 }
 
+#[derive(Serialize, PartialEq, Debug, Clone)]
+pub struct SessionRequestLog {
+    pub session: String,
+    #[serde(flatten)]
+    pub log: RequestLog,
+}
+
+#[derive(Serialize, PartialEq, Debug, Clone)]
+pub struct DuplicateGroup {
+    pub method: Method,
+    pub path: String,
+    pub body: String,
+    pub count: usize,
+    pub histories: Vec<RequestLog>,
+}
+
 #[derive(Clone)]
 pub struct RequestLogger {
     pool: SqlitePool,
+    redacted_headers: Vec<String>,
+    redacted_body_fields: Vec<String>,
 }
 
+const REDACTED_VALUE: &str = "***";
+
+// tables are kept when they exist, so that a database file keeps the history of earlier runs
 const SCHEMA: &str = r#"
-DROP TABLE IF EXISTS session;
-CREATE TABLE session (
+CREATE TABLE IF NOT EXISTS session (
     id INTEGER PRIMARY KEY AUTOINCREMENT,
     name VARCBAR(255) UNIQUE NOT NULL
 );
 
-DROP TABLE IF EXISTS request_log;
-CREATE TABLE request_log (
+CREATE TABLE IF NOT EXISTS request_log (
     id INTEGER PRIMARY KEY AUTOINCREMENT,
     session_id INTEGER NOT NULL,
     method VARCHAR(255) NOT NULL,
     path VARCHAR(255) NOT NULL,
     body TEXT NOT NULL,
+    content_type TEXT,
+    body_size INTEGER NOT NULL,
+    status INTEGER NOT NULL,
+    request_id TEXT,
+    correlation_id TEXT,
+    response_body TEXT,
+    response_content_type TEXT,
+    remote_addr TEXT,
+    path_pattern VARCHAR(255) NOT NULL,
+    name TEXT,
     requested_at TIMESTAMP NOT NULL,
     FOREIGN KEY (session_id) REFERENCES session(id) ON DELETE CASCADE
 );
 
-DROP TABLE IF EXISTS request_header;
-CREATE TABLE request_header (
+CREATE TABLE IF NOT EXISTS request_header (
     id INTEGER PRIMARY KEY AUTOINCREMENT,
     request_log_id INTEGER NOT NULL,
     name TEXT NOT NULL,
@@ -47,8 +104,15 @@ CREATE TABLE request_header (
     FOREIGN KEY (request_log_id) REFERENCES request_log(id) ON DELETE CASCADE
 );
 
-DROP TABLE IF EXISTS request_query;
-CREATE TABLE request_query (
+CREATE TABLE IF NOT EXISTS response_header (
+    id INTEGER PRIMARY KEY AUTOINCREMENT,
+    request_log_id INTEGER NOT NULL,
+    name TEXT NOT NULL,
+    value TEXT NOT NULL,
+    FOREIGN KEY (request_log_id) REFERENCES request_log(id) ON DELETE CASCADE
+);
+
+CREATE TABLE IF NOT EXISTS request_query (
     id INTEGER PRIMARY KEY AUTOINCREMENT,
     request_log_id INTEGER NOT NULL,
     name TEXT NOT NULL,
@@ -57,6 +121,44 @@ CREATE TABLE request_query (
 );
 "#;
 
+/// Settings of the SQLite database storing the request logs.
+#[derive(Debug, Clone, Default)]
+pub struct DbOptions {
+    /// The database file. An in-memory database is used when `None`.
+    pub path: Option<String>,
+    pub busy_timeout_ms: Option<u64>,
+    /// Defaults to WAL for database files, and is left as is for in-memory databases.
+    pub journal_mode: Option<SqliteJournalMode>,
+}
+
+impl DbOptions {
+    pub async fn connect(&self) -> Result<SqlitePool, String> {
+        let options = match &self.path {
+            Some(path) => SqliteConnectOptions::new()
+                .filename(path)
+                .create_if_missing(true)
+                .journal_mode(self.journal_mode.unwrap_or(SqliteJournalMode::Wal)),
+            None => {
+                let options = "sqlite::memory:"
+                    .parse::<SqliteConnectOptions>()
+                    .map_err(|err| err.to_string())?;
+                match self.journal_mode {
+                    Some(journal_mode) => options.journal_mode(journal_mode),
+                    None => options,
+                }
+            }
+        };
+        let options = match self.busy_timeout_ms {
+            Some(busy_timeout_ms) => options.busy_timeout(Duration::from_millis(busy_timeout_ms)),
+            None => options,
+        };
+
+        SqlitePool::connect_with(options)
+            .await
+            .map_err(|err| err.to_string())
+    }
+}
+
 #[derive(Debug, PartialEq)]
 pub enum LoggerError {
     InvalidSession(String),
@@ -67,7 +169,55 @@ pub type LoggerResult<T> = Result<T, LoggerError>;
 
 impl RequestLogger {
     pub fn new(pool: SqlitePool) -> Result<Self, String> {
-        Ok(Self { pool })
+        Ok(Self {
+            pool,
+            redacted_headers: vec![],
+            redacted_body_fields: vec![],
+        })
+    }
+
+    pub fn with_redacted_headers(self, names: impl IntoIterator<Item = String>) -> Self {
+        Self {
+            redacted_headers: names.into_iter().collect(),
+            ..self
+        }
+    }
+
+    // fields are JSON paths such as `$.user.password` or `items.0.token`
+    pub fn with_redacted_body_fields(self, paths: impl IntoIterator<Item = String>) -> Self {
+        Self {
+            redacted_body_fields: paths.into_iter().collect(),
+            ..self
+        }
+    }
+
+    fn redact_body<'a>(&self, body: &'a str) -> Cow<'a, str> {
+        if self.redacted_body_fields.is_empty() {
+            return Cow::Borrowed(body);
+        }
+
+        let Ok(mut json) = serde_json::from_str::<serde_json::Value>(body) else {
+            return Cow::Borrowed(body);
+        };
+
+        let redacted = self
+            .redacted_body_fields
+            .iter()
+            .filter(|path| redact_json_path(&mut json, path))
+            .count()
+            > 0;
+
+        if redacted {
+            Cow::Owned(json.to_string())
+        } else {
+            Cow::Borrowed(body)
+        }
+    }
+
+    fn is_redacted_header(&self, name: &str) -> bool {
+        self.redacted_headers
+            .iter()
+            .any(|redacted| redacted.eq_ignore_ascii_case(name))
     }
 
     pub async fn init(&self) -> LoggerResult<()> {
@@ -78,6 +228,15 @@ impl RequestLogger {
         Ok(())
     }
 
+    /// Checks that the database can be queried.
+    pub async fn ping(&self) -> LoggerResult<()> {
+        sqlx::query("SELECT 1")
+            .execute(&self.pool)
+            .await
+            .map_err(|err| LoggerError::InternalError(err.to_string()))?;
+        Ok(())
+    }
+
     pub async fn create_session(&self, session: &str) -> LoggerResult<()> {
         sqlx::query("INSERT INTO session (name) VALUES (?)")
             .bind(session)
@@ -101,6 +260,16 @@ impl RequestLogger {
         Ok(())
     }
 
+    /// Creates the session unless it exists, such as one left in a database file by an earlier run.
+    pub async fn ensure_session(&self, session: &str) -> LoggerResult<()> {
+        sqlx::query("INSERT OR IGNORE INTO session (name) VALUES (?)")
+            .bind(session)
+            .execute(&self.pool)
+            .await
+            .map(|_| ())
+            .map_err(|err| LoggerError::InternalError(err.to_string()))
+    }
+
     pub async fn delete_session(&self, session: &str) -> LoggerResult<()> {
         let qr = sqlx::query("DELETE FROM session WHERE name = ?")
             .bind(session)
@@ -126,11 +295,21 @@ impl RequestLogger {
             .map_err(|err| LoggerError::InternalError(err.to_string()))?;
 
         // Insert request_log
-        let request_log_id = sqlx::query("INSERT INTO request_log (session_id, method, path, body, requested_at) VALUES ((SELECT id FROM session WHERE name = ?), ?, ?, ?, ?)")
+        let request_log_id = sqlx::query("INSERT INTO request_log (session_id, method, path, body, content_type, body_size, status, request_id, correlation_id, response_body, response_content_type, remote_addr, path_pattern, name, requested_at) VALUES ((SELECT id FROM session WHERE session.name = ?), ?, ?, ?, ?, ?, ?, ?, ?, ?, ?, ?, ?, ?, ?)")
             .bind(session)
             .bind(log.method.to_string())
             .bind(log.path.as_str())
-            .bind(log.body.as_str())
+            .bind(self.redact_body(&log.body))
+            .bind(log.content_type.as_deref())
+            .bind(log.body_size as i64)
+            .bind(log.status)
+            .bind(log.request_id.as_deref())
+            .bind(log.correlation_id.as_deref())
+            .bind(log.response_body.as_deref())
+            .bind(log.response_content_type.as_deref())
+            .bind(log.remote_addr.as_deref())
+            .bind(log.path_pattern.as_str())
+            .bind(log.name.as_deref())
             .bind(log.requested_at)
             .execute(&mut *tx)
             .await
@@ -154,10 +333,34 @@ impl RequestLogger {
             log.headers
                 .iter()
                 .fold(sqlx::query(&prepared), |query, (name, value)| {
-                    query
-                        .bind(request_log_id)
-                        .bind(name.as_str())
-                        .bind(value.as_str())
+                    let value = if self.is_redacted_header(name) {
+                        REDACTED_VALUE
+                    } else {
+                        value.as_str()
+                    };
+                    query.bind(request_log_id).bind(name.as_str()).bind(value)
+                })
+                .execute(&mut *tx)
+                .await
+                .map_err(|err| LoggerError::InternalError(err.to_string()))?;
+        }
+
+        // Insert response_header
+        if !log.response_headers.is_empty() {
+            let prepared = format!(
+                "INSERT INTO response_header (request_log_id, name, value) VALUES {}",
+                vec!["(?, ?, ?)"; log.response_headers.len()].join(", ")
+            );
+
+            log.response_headers
+                .iter()
+                .fold(sqlx::query(&prepared), |query, (name, value)| {
+                    let value = if self.is_redacted_header(name) {
+                        REDACTED_VALUE
+                    } else {
+                        value.as_str()
+                    };
+                    query.bind(request_log_id).bind(name.as_str()).bind(value)
                 })
                 .execute(&mut *tx)
                 .await
@@ -165,13 +368,21 @@ impl RequestLogger {
         }
 
         // Insert request_query
-        if !log.query.is_empty() {
+        let query_pairs = log
+            .query
+            .iter()
+            .flat_map(|(name, value)| match log.repeated_query.get(name) {
+                Some(values) => values.iter().map(|value| (name, value)).collect(),
+                None => vec![(name, value)],
+            })
+            .collect::<Vec<_>>();
+        if !query_pairs.is_empty() {
             let prepared = format!(
                 "INSERT INTO request_query (request_log_id, name, value) VALUES {}",
-                vec!["(?, ?, ?)"; log.query.len()].join(", ")
+                vec!["(?, ?, ?)"; query_pairs.len()].join(", ")
             );
 
-            log.query
+            query_pairs
                 .iter()
                 .fold(sqlx::query(&prepared), |query, (name, value)| {
                     query
@@ -196,12 +407,94 @@ impl RequestLogger {
             id: i64,
         }
 
+        let session_id = sqlx::query_as::<_, SessionRow>("SELECT id FROM session WHERE name = ?")
+            .bind(session)
+            .fetch_optional(&self.pool)
+            .await
+            .map_err(|err| LoggerError::InternalError(err.to_string()))
+            .and_then(|session_opt| {
+                session_opt.ok_or_else(|| {
+                    LoggerError::InvalidSession(format!("session \"{}\" is not found", session))
+                })
+            })?
+            .id;
+
+        self.fetch_history(Some(session_id))
+            .await
+            .map(|logs| logs.into_iter().map(|log| log.log).collect())
+    }
+
+    /// Returns the requests of the session sharing the same method, path and body.
+    pub async fn get_session_duplicates(&self, session: &str) -> LoggerResult<Vec<DuplicateGroup>> {
+        #[derive(FromRow)]
+        struct DuplicateRow {
+            method: String,
+            path: String,
+            body: String,
+            count: i64,
+        }
+
+        let histories = self.get_session_history(session).await?;
+
+        let rows: Vec<DuplicateRow> = sqlx::query_as(
+            "SELECT method, path, body, COUNT(*) AS count FROM request_log WHERE session_id = (SELECT id FROM session WHERE session.name = ?) GROUP BY method, path, body HAVING COUNT(*) > 1 ORDER BY MIN(id)",
+        )
+        .bind(session)
+        .fetch_all(&self.pool)
+        .await
+        .map_err(|err| LoggerError::InternalError(err.to_string()))?;
+
+        rows.into_iter()
+            .map(|row| {
+                let method: Method = row
+                    .method
+                    .as_str()
+                    .try_into()
+                    .map_err(|err: String| LoggerError::InternalError(err))?;
+                let histories = histories
+                    .iter()
+                    .filter(|history| {
+                        history.method == method
+                            && history.path == row.path
+                            && history.body == row.body
+                    })
+                    .cloned()
+                    .collect();
+                Ok(DuplicateGroup {
+                    method,
+                    path: row.path,
+                    body: row.body,
+                    count: row.count as usize,
+                    histories,
+                })
+            })
+            .collect()
+    }
+
+    /// Returns the history of every session, tagged with the session name.
+    pub async fn get_all_history(&self) -> LoggerResult<Vec<SessionRequestLog>> {
+        self.fetch_history(None).await
+    }
+
+    // every session is fetched when `session_id` is `None`
+    async fn fetch_history(&self, session_id: Option<i64>) -> LoggerResult<Vec<SessionRequestLog>> {
         #[derive(FromRow)]
         struct RequestLogRow {
             id: i64,
+            session: String,
             method: String,
             path: String,
             body: String,
+            content_type: Option<String>,
+            body_size: i64,
+            status: u16,
+            request_id: Option<String>,
+            correlation_id: Option<String>,
+            response_body: Option<String>,
+            response_content_type: Option<String>,
+            remote_addr: Option<String>,
+            path_pattern: String,
+            name: Option<String>,
             requested_at: DateTime<Local>,
         }
 
@@ -219,20 +512,8 @@ impl RequestLogger {
             value: String,
         }
 
-        let session_id = sqlx::query_as::<_, SessionRow>("SELECT id FROM session WHERE name = ?")
-            .bind(session)
-            .fetch_optional(&self.pool)
-            .await
-            .map_err(|err| LoggerError::InternalError(err.to_string()))
-            .and_then(|session_opt| {
-                session_opt.ok_or_else(|| {
-                    LoggerError::InvalidSession(format!("session \"{}\" is not found", session))
-                })
-            })?
-            .id;
-
         let logs: Vec<RequestLogRow> = sqlx::query_as(
-            "SELECT id, method, path, body, requested_at FROM request_log WHERE session_id = ?",
+            "SELECT request_log.id, CAST(session.name AS TEXT) AS session, method, path, body, content_type, body_size, status, request_id, correlation_id, response_body, response_content_type, remote_addr, path_pattern, request_log.name, requested_at FROM request_log INNER JOIN session ON session.id = request_log.session_id WHERE ?1 IS NULL OR request_log.session_id = ?1 ORDER BY request_log.id",
         )
         .bind(session_id)
         .fetch_all(&self.pool)
@@ -240,7 +521,7 @@ impl RequestLogger {
         .map_err(|err| LoggerError::InternalError(err.to_string()))?;
 
         let all_headers: Vec<RequestHeaderRow> = sqlx::query_as(
-            "SELECT request_log_id, name, value FROM request_header LEFT JOIN request_log ON request_log.id = request_header.request_log_id WHERE request_log.session_id = ?",
+            "SELECT request_log_id, request_header.name, value FROM request_header LEFT JOIN request_log ON request_log.id = request_header.request_log_id WHERE ?1 IS NULL OR request_log.session_id = ?1",
         ).bind(session_id).fetch_all(&self.pool).await.map_err(|err| LoggerError::InternalError(err.to_string()))?;
 
         let headers: IndexMap<i64, Vec<RequestHeaderRow>> =
@@ -251,8 +532,19 @@ impl RequestLogger {
                     acc
                 });
 
+        let all_response_headers: Vec<RequestHeaderRow> = sqlx::query_as(
+            "SELECT request_log_id, response_header.name, value FROM response_header LEFT JOIN request_log ON request_log.id = response_header.request_log_id WHERE ?1 IS NULL OR request_log.session_id = ?1",
+        ).bind(session_id).fetch_all(&self.pool).await.map_err(|err| LoggerError::InternalError(err.to_string()))?;
+
+        let response_headers: IndexMap<i64, Vec<RequestHeaderRow>> = all_response_headers
+            .into_iter()
+            .fold(IndexMap::new(), |mut acc, row| {
+                acc.entry(row.request_log_id).or_default().push(row);
+                acc
+            });
+
         let all_queries: Vec<RequestQueryRow> = sqlx::query_as(
-            "SELECT request_log_id, name, value FROM request_query LEFT JOIN request_log ON request_log.id = request_query.request_log_id WHERE request_log.session_id = ?",
+            "SELECT request_log_id, request_query.name, value FROM request_query LEFT JOIN request_log ON request_log.id = request_query.request_log_id WHERE ?1 IS NULL OR request_log.session_id = ?1 ORDER BY request_query.id",
         ).bind(session_id).fetch_all(&self.pool).await.map_err(|err| LoggerError::InternalError(err.to_string()))?;
 
         let queries: IndexMap<i64, Vec<RequestQueryRow>> =
@@ -274,7 +566,7 @@ impl RequestLogger {
                     })
                     .unwrap_or_default();
 
-                let queries = queries
+                let response_headers = response_headers
                     .get(&log.id)
                     .map(|rows| {
                         rows.iter()
@@ -283,23 +575,87 @@ impl RequestLogger {
                     })
                     .unwrap_or_default();
 
+                let query_rows = queries.get(&log.id).map(Vec::as_slice).unwrap_or_default();
+                let query = query_rows
+                    .iter()
+                    .map(|row| (row.name.clone(), row.value.clone()))
+                    .collect();
+                let repeated_query = repeated_query(
+                    query_rows
+                        .iter()
+                        .map(|row| (row.name.as_str(), row.value.as_str())),
+                );
+
                 log.method
                     .as_str()
                     .try_into()
                     .map_err(|err: String| LoggerError::InternalError(err))
-                    .map(|method| RequestLog {
-                        method,
-                        headers,
-                        path: log.path,
-                        query: queries,
-                        body: log.body,
-                        requested_at: log.requested_at,
+                    .map(|method| SessionRequestLog {
+                        session: log.session,
+                        log: RequestLog {
+                            method,
+                            headers,
+                            path: log.path,
+                            query,
+                            repeated_query,
+                            body: log.body,
+                            content_type: log.content_type,
+                            body_size: log.body_size as usize,
+                            status: log.status,
+                            request_id: log.request_id,
+                            correlation_id: log.correlation_id,
+                            response_body: log.response_body,
+                            response_content_type: log.response_content_type,
+                            response_headers,
+                            remote_addr: log.remote_addr,
+                            path_pattern: log.path_pattern,
+                            name: log.name,
+                            requested_at: log.requested_at,
+                        },
                     })
             })
             .collect::<Result<Vec<_>, _>>()
     }
 }
 
+/// Collects the values of the query parameters which appear more than once in `pairs`, as
+/// recorded in `RequestLog::repeated_query`.
+pub fn repeated_query<N: Into<String>, V: Into<String>>(
+    pairs: impl IntoIterator<Item = (N, V)>,
+) -> IndexMap<String, Vec<String>> {
+    let mut values = pairs.into_iter().fold(
+        IndexMap::<String, Vec<String>>::new(),
+        |mut acc, (name, value)| {
+            acc.entry(name.into()).or_default().push(value.into());
+            acc
+        },
+    );
+    values.retain(|_, values| values.len() > 1);
+    values
+}
+
+fn redact_json_path(json: &mut serde_json::Value, path: &str) -> bool {
+    let path = path.strip_prefix("$.").unwrap_or(path);
+    let target = path
+        .split('.')
+        .try_fold(json, |current, segment| match current {
+            serde_json::Value::Object(map) => map.get_mut(segment),
+            serde_json::Value::Array(items) => segment
+                .parse::<usize>()
+                .ok()
+                .and_then(|index| items.get_mut(index)),
+            _ => None,
+        });
+
+    match target {
+        Some(value) => {
+            *value = serde_json::Value::String(REDACTED_VALUE.to_string());
+            true
+        }
+        None => false,
+    }
+}
+
 #[cfg(test)]
 pub mod testutil {
     use super::*;
@@ -313,6 +669,11 @@ pub mod testutil {
         logger.init().await.unwrap();
         logger
     }
+
+    /// Closes the pool, so that every later query fails with an internal error.
+    pub async fn close(logger: &RequestLogger) {
+        logger.pool.close().await;
+    }
 }
 
 #[cfg(test)]
@@ -397,7 +758,19 @@ mod tests {
                     "qname1".to_string() => "qvalue1".to_string(),
                     "qname2".to_string() => "qvalue2".to_string(),
                 },
+                repeated_query: IndexMap::new(),
                 body: "".to_string(),
+                content_type: None,
+                body_size: 0,
+                status: 200,
+                request_id: None,
+                correlation_id: None,
+                response_body: None,
+                response_content_type: None,
+                response_headers: indexmap! {},
+                name: None,
+                remote_addr: None,
+                path_pattern: "/hello".to_string(),
                 requested_at: log1_requested_at,
             };
 
@@ -416,8 +789,25 @@ mod tests {
                     "hname2".to_string() => "hvalue2".to_string(),
                 },
                 path: "/greet".to_string(),
-                query: IndexMap::new(),
+                query: indexmap! {
+                    "tag".to_string() => "b".to_string(),
+                    "page".to_string() => "1".to_string(),
+                },
+                repeated_query: indexmap! {
+                    "tag".to_string() => vec!["a".to_string(), "b".to_string()],
+                },
                 body: r#"{"message":"hi"}"#.to_string(),
+                content_type: Some("application/json".to_string()),
+                body_size: 16,
+                status: 200,
+                request_id: None,
+                correlation_id: None,
+                response_body: None,
+                response_content_type: None,
+                response_headers: indexmap! {},
+                name: None,
+                remote_addr: None,
+                path_pattern: "/greet".to_string(),
                 requested_at: log2_requested_at,
             };
 
@@ -434,7 +824,19 @@ mod tests {
                 headers: indexmap! {},
                 path: "/bye".to_string(),
                 query: IndexMap::new(),
+                repeated_query: IndexMap::new(),
                 body: "".to_string(),
+                content_type: None,
+                body_size: 0,
+                status: 200,
+                request_id: None,
+                correlation_id: None,
+                response_body: None,
+                response_content_type: None,
+                response_headers: indexmap! {},
+                name: None,
+                remote_addr: None,
+                path_pattern: "/bye".to_string(),
                 requested_at: log3_requested_at,
             };
 
@@ -453,6 +855,101 @@ mod tests {
             );
         }
 
+        #[tokio::test]
+        async fn when_headers_are_redacted() {
+            let logger = new_logger_with_default_session()
+                .await
+                .with_redacted_headers(vec!["Authorization".to_string(), "cookie".to_string()]);
+
+            let log = RequestLog {
+                method: Method::Get,
+                headers: indexmap! {
+                    "authorization".to_string() => "Bearer secret".to_string(),
+                    "Cookie".to_string() => "sid=secret".to_string(),
+                    "accept".to_string() => "*/*".to_string(),
+                },
+                path: "/hello".to_string(),
+                query: IndexMap::new(),
+                repeated_query: IndexMap::new(),
+                body: "".to_string(),
+                content_type: None,
+                body_size: 0,
+                status: 200,
+                request_id: None,
+                correlation_id: None,
+                response_body: None,
+                response_content_type: None,
+                response_headers: indexmap! {},
+                name: None,
+                remote_addr: None,
+                path_pattern: "/hello".to_string(),
+                requested_at: Local::now(),
+            };
+            logger.log_request(DEFAULT_SESSION, &log).await.unwrap();
+
+            assert_eq!(
+                indexmap! {
+                    "authorization".to_string() => "***".to_string(),
+                    "Cookie".to_string() => "***".to_string(),
+                    "accept".to_string() => "*/*".to_string(),
+                },
+                logger.get_session_history(DEFAULT_SESSION).await.unwrap()[0].headers
+            );
+        }
+
+        #[tokio::test]
+        async fn when_body_fields_are_redacted() {
+            let logger = new_logger_with_default_session()
+                .await
+                .with_redacted_body_fields(vec![
+                    "password".to_string(),
+                    "$.cards.0.number".to_string(),
+                    "missing.field".to_string(),
+                ]);
+
+            let bodies = [
+                r#"{"user":"alice","password":"hunter2","cards":[{"number":"4242"}]}"#,
+                "password=hunter2",
+            ];
+            for body in bodies {
+                let log = RequestLog {
+                    method: Method::Post,
+                    headers: IndexMap::new(),
+                    path: "/login".to_string(),
+                    query: IndexMap::new(),
+                    repeated_query: IndexMap::new(),
+                    body: body.to_string(),
+                    content_type: None,
+                    body_size: body.len(),
+                    status: 200,
+                    request_id: None,
+                    correlation_id: None,
+                    response_body: None,
+                    response_content_type: None,
+                    response_headers: indexmap! {},
+                    name: None,
+                    remote_addr: None,
+                    path_pattern: "/login".to_string(),
+                    requested_at: Local::now(),
+                };
+                logger.log_request(DEFAULT_SESSION, &log).await.unwrap();
+            }
+
+            assert_eq!(
+                vec![
+                    r#"{"user":"alice","password":"***","cards":[{"number":"***"}]}"#.to_string(),
+                    "password=hunter2".to_string(),
+                ],
+                logger
+                    .get_session_history(DEFAULT_SESSION)
+                    .await
+                    .unwrap()
+                    .into_iter()
+                    .map(|log| log.body)
+                    .collect::<Vec<_>>()
+            );
+        }
+
         #[tokio::test]
         async fn when_no_requests_are_logged() {
             let logger = new_logger_with_default_session().await;
@@ -479,7 +976,19 @@ mod tests {
                             headers: IndexMap::new(),
                             path: "/hello".to_string(),
                             query: IndexMap::new(),
+                            repeated_query: IndexMap::new(),
                             body: "".to_string(),
+                            content_type: None,
+                            body_size: 0,
+                            status: 200,
+                            request_id: None,
+                            correlation_id: None,
+                            response_body: None,
+                            response_content_type: None,
+                            response_headers: indexmap! {},
+                            name: None,
+                            remote_addr: None,
+                            path_pattern: "/hello".to_string(),
                             requested_at: Local::now(),
                         }
                     )
@@ -499,4 +1008,132 @@ mod tests {
             );
         }
     }
+
+    mod db_options {
+        use super::*;
+        use chrono::Local;
+        use indexmap::indexmap;
+        use pretty_assertions::assert_eq;
+
+        #[tokio::test(flavor = "multi_thread")]
+        async fn concurrent_log_requests_to_file() {
+            let dir = tempfile::tempdir().unwrap();
+            let pool = DbOptions {
+                path: Some(
+                    dir.path()
+                        .join("serverify.db")
+                        .to_str()
+                        .unwrap()
+                        .to_string(),
+                ),
+                busy_timeout_ms: Some(5000),
+                journal_mode: None,
+            }
+            .connect()
+            .await
+            .unwrap();
+            let logger = RequestLogger::new(pool).unwrap();
+            logger.init().await.unwrap();
+            logger.create_session(DEFAULT_SESSION).await.unwrap();
+
+            let handles = (0..50)
+                .map(|i| {
+                    let logger = logger.clone();
+                    tokio::spawn(async move {
+                        logger
+                            .log_request(
+                                DEFAULT_SESSION,
+                                &RequestLog {
+                                    method: Method::Post,
+                                    path: format!("/items/{}", i),
+                                    headers: indexmap! {
+                                        "x-index".to_string() => i.to_string(),
+                                    },
+                                    query: Default::default(),
+                                    repeated_query: IndexMap::new(),
+                                    body: "".to_string(),
+                                    content_type: None,
+                                    body_size: 0,
+                                    status: 201,
+                                    request_id: None,
+                                    correlation_id: None,
+                                    response_body: None,
+                                    response_content_type: None,
+                                    response_headers: indexmap! {},
+                                    name: None,
+                                    remote_addr: None,
+                                    path_pattern: "/items/:id".to_string(),
+                                    requested_at: Local::now(),
+                                },
+                            )
+                            .await
+                    })
+                })
+                .collect::<Vec<_>>();
+            for handle in handles {
+                assert_eq!(Ok(()), handle.await.unwrap());
+            }
+
+            assert_eq!(
+                50,
+                logger
+                    .get_session_history(DEFAULT_SESSION)
+                    .await
+                    .unwrap()
+                    .len()
+            );
+        }
+
+        #[tokio::test]
+        async fn history_is_kept_when_file_is_reopened() {
+            let dir = tempfile::tempdir().unwrap();
+            let options = DbOptions {
+                path: Some(
+                    dir.path()
+                        .join("serverify.db")
+                        .to_str()
+                        .unwrap()
+                        .to_string(),
+                ),
+                ..Default::default()
+            };
+            let log = RequestLog {
+                method: Method::Get,
+                path: "/hello".to_string(),
+                headers: indexmap! {},
+                query: Default::default(),
+                repeated_query: IndexMap::new(),
+                body: "".to_string(),
+                content_type: None,
+                body_size: 0,
+                status: 200,
+                request_id: None,
+                correlation_id: None,
+                response_body: None,
+                response_content_type: None,
+                response_headers: indexmap! {},
+                name: None,
+                remote_addr: None,
+                path_pattern: "/hello".to_string(),
+                requested_at: Local::now(),
+            };
+
+            let logger = RequestLogger::new(options.connect().await.unwrap()).unwrap();
+            logger.init().await.unwrap();
+            logger.create_session(DEFAULT_SESSION).await.unwrap();
+            logger.log_request(DEFAULT_SESSION, &log).await.unwrap();
+            logger.pool.close().await;
+
+            let reopened = RequestLogger::new(options.connect().await.unwrap()).unwrap();
+            reopened.init().await.unwrap();
+
+            assert_eq!(
+                Ok(vec!["/hello".to_string()]),
+                reopened
+                    .get_session_history(DEFAULT_SESSION)
+                    .await
+                    .map(|logs| logs.into_iter().map(|log| log.path).collect::<Vec<_>>())
+            );
+        }
+    }
 }
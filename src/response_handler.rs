@@ -0,0 +1,2927 @@
+use std::{
+    collections::HashMap,
+    io,
+    path::{Component, Path, PathBuf},
+    sync::{
+        atomic::{AtomicUsize, Ordering},
+        Arc, Mutex, PoisonError,
+    },
+    time::{Duration, Instant},
+};
+
+use axum::{
+    body::{Body, Bytes},
+    extract::Query,
+    http::{header, request::Parts, HeaderMap, HeaderName, StatusCode},
+    response::{IntoResponse, Response},
+    Json,
+};
+use base64::{prelude::BASE64_STANDARD, Engine};
+use chrono::{DateTime, NaiveDateTime, NaiveTime, Utc};
+use futures::StreamExt;
+use indexmap::IndexMap;
+use once_cell::sync::Lazy;
+use rand::{rngs::StdRng, Rng, RngCore, SeedableRng};
+use serde::{ser::SerializeMap, Deserialize, Serialize, Serializer};
+use serde_json::Value;
+use tokio_util::io::ReaderStream;
+
+use crate::response::error_response;
+
+pub struct RequestContext {
+    pub session: String,
+    pub parts: Parts,
+    pub path_params: IndexMap<String, String>,
+    pub body: Bytes,
+}
+
+#[derive(PartialEq, Debug, Clone, Default)]
+pub struct StaticResponse {
+    pub status: u16,
+    pub headers: IndexMap<String, String>,
+    pub body: Bytes,
+    pub last_modified: Option<DateTime<Utc>>,
+    /// Sends the body in chunks of this size without `Content-Length` when given.
+    pub chunk_size: Option<usize>,
+    /// Sends `Connection: close`, which makes hyper close the connection after the response.
+    pub connection_close: bool,
+    /// Waits before responding. The time taken is reported in `Server-Timing`.
+    pub delay_ms: Option<u64>,
+    pub expect_continue: ExpectContinue,
+    /// Reads the whole request body without keeping it before responding.
+    pub drain_body: bool,
+    /// Waits for a delay sampled from this distribution, in addition to `delay_ms`.
+    pub latency: Option<LatencyDistribution>,
+    /// Sends only this many bytes of the body and then aborts the connection instead of ending
+    /// the response.
+    pub reset_after: Option<usize>,
+    /// How the length of an empty body is given.
+    pub empty_body: EmptyBody,
+    /// Bodies sent instead of `body` when the `force_status` query parameter asks for their
+    /// status.
+    pub status_bodies: IndexMap<u16, Bytes>,
+    /// Cookies sent in a `Set-Cookie` header each, after those in `headers`.
+    pub cookies: Vec<Cookie>,
+}
+
+// serialized in the config schema, where bodies which are not UTF-8 are written in base64
+impl Serialize for StaticResponse {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        let mut map = serializer.serialize_map(None)?;
+        map.serialize_entry("status", &self.status)?;
+        map.serialize_entry("headers", &self.headers)?;
+        match std::str::from_utf8(&self.body) {
+            Ok(body) => map.serialize_entry("body", body)?,
+            Err(_) => map.serialize_entry("body_base64", &BASE64_STANDARD.encode(&self.body))?,
+        }
+        map.serialize_entry("last_modified", &self.last_modified)?;
+        map.serialize_entry("chunk_size", &self.chunk_size)?;
+        map.serialize_entry("connection_close", &self.connection_close)?;
+        map.serialize_entry("delay_ms", &self.delay_ms)?;
+        map.serialize_entry("expect_continue", &self.expect_continue)?;
+        map.serialize_entry("drain_body", &self.drain_body)?;
+        map.serialize_entry("latency", &self.latency)?;
+        map.serialize_entry("reset_after", &self.reset_after)?;
+        map.serialize_entry("empty_body", &self.empty_body)?;
+        let status_bodies = self
+            .status_bodies
+            .iter()
+            .filter_map(|(status, body)| Some((status, std::str::from_utf8(body).ok()?)))
+            .collect::<IndexMap<_, _>>();
+        map.serialize_entry("status_bodies", &status_bodies)?;
+        let status_bodies_base64 = self
+            .status_bodies
+            .iter()
+            .filter(|(_, body)| std::str::from_utf8(body).is_err())
+            .map(|(status, body)| (status, BASE64_STANDARD.encode(body)))
+            .collect::<IndexMap<_, _>>();
+        if !status_bodies_base64.is_empty() {
+            map.serialize_entry("status_bodies_base64", &status_bodies_base64)?;
+        }
+        map.serialize_entry("cookies", &self.cookies)?;
+        map.end()
+    }
+}
+
+#[derive(PartialEq, Debug, Clone, Copy, Default, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum Fill {
+    #[default]
+    Zero,
+    Random,
+}
+
+/// Latency percentiles in milliseconds, from which the delay of each response is sampled.
+#[derive(PartialEq, Debug, Clone, Copy, Serialize, Deserialize)]
+pub struct LatencyDistribution {
+    pub p50: u64,
+    pub p95: u64,
+    pub p99: u64,
+}
+
+impl LatencyDistribution {
+    /// Samples a delay by interpolating linearly between the percentiles, from 0 at p0.
+    /// The slowest 1% is capped at p99.
+    pub fn sample(&self, rng: &mut impl Rng) -> u64 {
+        let quantile = rng.gen::<f64>();
+        let points = [
+            (0.0, 0),
+            (0.5, self.p50),
+            (0.95, self.p95),
+            (0.99, self.p99),
+        ];
+        points
+            .windows(2)
+            .find(|window| quantile < window[1].0)
+            .map(|window| {
+                let ((q0, v0), (q1, v1)) = (window[0], window[1]);
+                let ratio = (quantile - q0) / (q1 - q0);
+                v0 + ((v1 as f64 - v0 as f64) * ratio).round() as u64
+            })
+            .unwrap_or(self.p99)
+    }
+}
+
+/// A daily time window in UTC. The window spans midnight when `end` is before `start`.
+#[derive(PartialEq, Debug, Clone, Copy, Serialize, Deserialize)]
+pub struct TimeWindow {
+    pub start: NaiveTime,
+    pub end: NaiveTime,
+}
+
+impl TimeWindow {
+    pub fn contains(&self, now: DateTime<Utc>) -> bool {
+        let time = now.time();
+        if self.start <= self.end {
+            self.start <= time && time < self.end
+        } else {
+            self.start <= time || time < self.end
+        }
+    }
+}
+
+/// A range of request body sizes in bytes. Both ends are inclusive, and a missing end is unbounded.
+#[derive(PartialEq, Debug, Clone, Copy, Serialize, Deserialize)]
+pub struct BodySize {
+    pub min: Option<usize>,
+    pub max: Option<usize>,
+}
+
+impl BodySize {
+    pub fn contains(&self, size: usize) -> bool {
+        self.min.is_none_or(|min| min <= size) && self.max.is_none_or(|max| size <= max)
+    }
+}
+
+/// The source of the current time, which is fixed in tests.
+#[derive(PartialEq, Debug, Clone, Copy, Default)]
+pub enum Clock {
+    #[default]
+    System,
+    Fixed(DateTime<Utc>),
+}
+
+impl Clock {
+    pub fn now(&self) -> DateTime<Utc> {
+        match self {
+            Clock::System => Utc::now(),
+            Clock::Fixed(now) => *now,
+        }
+    }
+}
+
+/// How a request with `Expect: 100-continue` is answered.
+///
+/// hyper sends `100 Continue` by itself once the request body is read, which is the default.
+#[derive(PartialEq, Debug, Clone, Copy, Default, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum ExpectContinue {
+    /// Reads the body, so that the client gets `100 Continue` before the response.
+    #[default]
+    Continue,
+    /// Responds without reading the body, so that no `100 Continue` is sent.
+    Ignore,
+    /// Responds with `417 Expectation Failed` without reading the body.
+    Reject,
+}
+
+/// A cookie set by a static response, written as the `Set-Cookie` header of RFC 6265.
+#[derive(PartialEq, Debug, Clone, Serialize, Deserialize)]
+pub struct Cookie {
+    pub name: String,
+    pub value: String,
+    pub path: Option<String>,
+    pub domain: Option<String>,
+    pub max_age: Option<i64>,
+    #[serde(default)]
+    pub http_only: bool,
+    #[serde(default)]
+    pub secure: bool,
+    pub same_site: Option<SameSite>,
+}
+
+impl Cookie {
+    pub fn to_header_value(&self) -> String {
+        let mut value = format!("{}={}", self.name, self.value);
+        if let Some(path) = &self.path {
+            value.push_str(&format!("; Path={}", path));
+        }
+        if let Some(domain) = &self.domain {
+            value.push_str(&format!("; Domain={}", domain));
+        }
+        if let Some(max_age) = self.max_age {
+            value.push_str(&format!("; Max-Age={}", max_age));
+        }
+        if self.http_only {
+            value.push_str("; HttpOnly");
+        }
+        if self.secure {
+            value.push_str("; Secure");
+        }
+        if let Some(same_site) = self.same_site {
+            value.push_str(match same_site {
+                SameSite::Strict => "; SameSite=Strict",
+                SameSite::Lax => "; SameSite=Lax",
+                SameSite::None => "; SameSite=None",
+            });
+        }
+        value
+    }
+}
+
+#[derive(PartialEq, Debug, Clone, Copy, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum SameSite {
+    Strict,
+    Lax,
+    /// Sends the cookie with cross-site requests, which browsers accept only with `secure`.
+    None,
+}
+
+/// How a response with an empty body tells its length, which some clients treat differently.
+#[derive(PartialEq, Debug, Clone, Copy, Default, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum EmptyBody {
+    /// Sends `Content-Length: 0`.
+    #[default]
+    ContentLength,
+    /// Sends no `Content-Length`, but only the terminating chunk of chunked encoding.
+    Chunked,
+}
+
+#[derive(PartialEq, Debug, Clone, Copy, Default, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum Strategy {
+    #[default]
+    RoundRobin,
+    Random,
+}
+
+/// A request counter shared by the clones of a handler.
+#[derive(Debug, Clone, Default)]
+pub struct Counter(Arc<AtomicUsize>);
+
+impl Counter {
+    /// Returns the current count and increments it.
+    pub fn next(&self) -> usize {
+        self.0.fetch_add(1, Ordering::Relaxed)
+    }
+}
+
+// counters are runtime state, so handlers are equal regardless of how many requests they served
+impl PartialEq for Counter {
+    fn eq(&self, _: &Self) -> bool {
+        true
+    }
+}
+
+/// Request counters per key, shared by the clones of a handler.
+#[derive(Debug, Clone, Default)]
+pub struct KeyedCounter(Arc<Mutex<HashMap<(String, String), usize>>>);
+
+impl KeyedCounter {
+    /// Returns the current count of `key` and increments it.
+    pub fn next(&self, key: (String, String)) -> usize {
+        let mut counts = self.0.lock().unwrap();
+        let count = counts.entry(key).or_default();
+        *count += 1;
+        *count - 1
+    }
+}
+
+impl PartialEq for KeyedCounter {
+    fn eq(&self, _: &Self) -> bool {
+        true
+    }
+}
+
+/// Responses cached by key, such as method, path and query, shared by the clones of a handler.
+/// At most `capacity` entries are kept, and the one closest to expiry is evicted beyond it.
+#[derive(Debug, Clone)]
+pub struct ResponseCache {
+    entries: Arc<Mutex<HashMap<String, CachedResponse>>>,
+    capacity: usize,
+}
+
+impl Default for ResponseCache {
+    fn default() -> Self {
+        Self::with_capacity(10_000)
+    }
+}
+
+#[derive(Debug)]
+struct CachedResponse {
+    status: StatusCode,
+    headers: HeaderMap,
+    body: Bytes,
+    expires_at: Instant,
+}
+
+impl PartialEq for ResponseCache {
+    fn eq(&self, _: &Self) -> bool {
+        true
+    }
+}
+
+/// Values kept per session and key, shared by the clones of a handler.
+#[derive(Debug)]
+pub struct SessionStore<T>(Arc<Mutex<HashMap<(String, String), T>>>);
+
+impl<T> SessionStore<T> {
+    fn remove_session(&self, session: &str) {
+        // a poisoned store is cleared all the same, as the values of the session are dropped
+        let mut values = self.0.lock().unwrap_or_else(PoisonError::into_inner);
+        values.retain(|(owner, _), _| owner != session);
+    }
+}
+
+impl<T> Clone for SessionStore<T> {
+    fn clone(&self) -> Self {
+        Self(self.0.clone())
+    }
+}
+
+impl<T> Default for SessionStore<T> {
+    fn default() -> Self {
+        Self(Default::default())
+    }
+}
+
+impl<T> PartialEq for SessionStore<T> {
+    fn eq(&self, _: &Self) -> bool {
+        true
+    }
+}
+
+/// The stores of responses keeping data per session, such as `merge_patch` documents.
+///
+/// The handlers of an app are linked to a single set, so that the endpoints of every method on a
+/// path share the data, and so that it is dropped when the session is deleted.
+#[derive(Debug, Clone, Default)]
+pub struct SessionStores {
+    documents: SessionStore<Value>,
+    collections: SessionStore<ResourceCollection>,
+}
+
+impl SessionStores {
+    /// Makes `handler` and the responses nested in it keep their data in these stores.
+    pub fn link(&self, handler: &mut ResponseHandler) {
+        match handler {
+            ResponseHandler::MergePatch { documents, .. } => *documents = self.documents.clone(),
+            ResponseHandler::Resource { collections, .. } => {
+                *collections = self.collections.clone()
+            }
+            _ => {}
+        }
+        for child in handler.children_mut() {
+            self.link(child);
+        }
+    }
+
+    pub fn remove_session(&self, session: &str) {
+        self.documents.remove_session(session);
+        self.collections.remove_session(session);
+    }
+}
+
+pub static UPSTREAM_HEADER: HeaderName = HeaderName::from_static("x-serverify-upstream");
+
+/// Items of a `resource` collection, along with the id given to the last created one.
+#[derive(Debug, Default)]
+pub struct ResourceCollection {
+    last_id: usize,
+    items: IndexMap<String, Value>,
+}
+
+pub(crate) static HTTP_CLIENT: Lazy<reqwest::Client> = Lazy::new(reqwest::Client::new);
+
+/// A response of mock endpoints. It is serialized in the config schema, with runtime state such
+/// as counters and caches left out.
+#[derive(PartialEq, Debug, Clone, Serialize)]
+#[serde(tag = "type", rename_all = "snake_case")]
+pub enum ResponseHandler {
+    Static(StaticResponse),
+    File {
+        path: String,
+    },
+    Dir {
+        root: String,
+    },
+    /// Waits for `delay_ms` and then closes the connection without sending any response.
+    ///
+    /// The connection is aborted by failing the response body, so hyper drops it before the
+    /// response head is flushed. How the client observes this (EOF or ECONNRESET) depends on
+    /// the platform and on whether unread request data remains in the socket. HTTP/2 clients
+    /// see a reset stream instead of a closed connection.
+    Hangup {
+        delay_ms: u64,
+    },
+    /// Declares `content_length` in `Content-Length` but sends only `body`, then ends the response.
+    Truncated {
+        status: u16,
+        content_length: usize,
+        body: String,
+    },
+    /// Responds with the status given by the `status_param` query parameter, clamped to 100-599.
+    /// Values found in `statuses`, such as `missing`, are mapped to their status instead.
+    StatusReflect {
+        status_param: String,
+        default: u16,
+        statuses: IndexMap<String, u16>,
+    },
+    /// Streams every value of the `param` query parameter back as a JSON array, in request order.
+    QueryEcho {
+        param: String,
+    },
+    /// Responds with one of `variants`, chosen by hashing the value of the `header` request header,
+    /// so that the same client always gets the same variant.
+    Sticky {
+        header: String,
+        variants: Vec<ResponseHandler>,
+    },
+    /// Forwards the request to one of `targets` and relays its response.
+    ///
+    /// The chosen target is returned in `X-Serverify-Upstream`.
+    ///
+    /// Responses are cached for `cache_ttl_ms` when given, except for server errors and ones with
+    /// `Cache-Control: no-store`.
+    ProxyPool {
+        targets: Vec<String>,
+        strategy: Strategy,
+        #[serde(skip)]
+        cursor: Counter,
+        cache_ttl_ms: Option<u64>,
+        #[serde(skip)]
+        cache: ResponseCache,
+    },
+    /// Responds with `before` to the first `threshold` requests and with `after` to the rest.
+    CountGated {
+        threshold: usize,
+        before: Box<ResponseHandler>,
+        after: Box<ResponseHandler>,
+        #[serde(skip)]
+        count: Counter,
+    },
+    /// Sends `body` verbatim. Unlike static responses, no `Content-Type` is ever added.
+    Raw {
+        status: u16,
+        headers: IndexMap<String, String>,
+        body: String,
+    },
+    /// Sends `message` in a gRPC-web data frame followed by a trailer frame.
+    GrpcWeb {
+        #[serde(serialize_with = "serialize_base64")]
+        message: Vec<u8>,
+        grpc_status: u32,
+        grpc_message: Option<String>,
+    },
+    /// Responds with `size` bytes generated on each request.
+    ///
+    /// Random bytes are reproducible across requests when `seed` is given.
+    RandomBytes {
+        size: usize,
+        fill: Fill,
+        content_type: String,
+        seed: Option<u64>,
+    },
+    /// Responds with `pending_status` until the `success_after`-th attempt with the same session
+    /// and value of the `header` request header, and with `response` from then on.
+    RetryUntilSuccess {
+        header: String,
+        success_after: usize,
+        pending_status: u16,
+        response: Box<ResponseHandler>,
+        #[serde(skip)]
+        attempts: KeyedCounter,
+    },
+    /// Keeps a JSON document per session and path. `PATCH` applies the request body to it as a JSON
+    /// Merge Patch (RFC 7386) and other methods leave it as is; both respond with the document.
+    MergePatch {
+        initial: Value,
+        #[serde(skip)]
+        documents: SessionStore<Value>,
+    },
+    /// An in-memory CRUD resource, served at the endpoint path for listing and creating items
+    /// and at `{path}/:id` for reading, replacing and deleting one.
+    ///
+    /// Items are kept per session and `collection`. Created items get a sequential `id`.
+    Resource {
+        collection: String,
+        #[serde(skip)]
+        collections: SessionStore<ResourceCollection>,
+    },
+    /// Responds with the response of the first window containing the current time, or with
+    /// `default` outside of every window.
+    TimeWindows {
+        #[serde(serialize_with = "serialize_conditional")]
+        windows: Vec<(TimeWindow, ResponseHandler)>,
+        default: Box<ResponseHandler>,
+        #[serde(skip)]
+        clock: Clock,
+    },
+    /// Responds with the override for the session of the request, or with `default` for sessions
+    /// without one.
+    PerSession {
+        #[serde(rename = "per_session_overrides")]
+        overrides: IndexMap<String, ResponseHandler>,
+        default: Box<ResponseHandler>,
+    },
+    /// Sends `body`, or the part of it asked by a single `Range: bytes=...` request header with
+    /// `206 Partial Content`. Unsatisfiable ranges get `416 Range Not Satisfiable`.
+    PartialContent {
+        body: String,
+        content_type: Option<String>,
+    },
+    /// Responds with `first` to the first request and with `then` to the rest. The first request
+    /// is counted per session when `per_session` is set, and across sessions otherwise.
+    Once {
+        first: Box<ResponseHandler>,
+        then: Box<ResponseHandler>,
+        per_session: bool,
+        #[serde(skip)]
+        served: KeyedCounter,
+    },
+    /// Responds with the response for the value of the `cookie` cookie of the request, or with
+    /// `default` when the cookie is missing or has another value.
+    ByCookie {
+        cookie: String,
+        responses: IndexMap<String, ResponseHandler>,
+        default: Box<ResponseHandler>,
+    },
+    /// Responds with `response` and replays it for `ttl_ms` to later requests with the same session
+    /// and value of the `header` request header. Requests without the header are never replayed,
+    /// and server errors are not kept, as on `proxy_pool`. Beyond 10,000 kept responses, the one
+    /// closest to expiry is dropped.
+    Idempotent {
+        header: String,
+        ttl_ms: u64,
+        response: Box<ResponseHandler>,
+        #[serde(skip)]
+        cache: ResponseCache,
+    },
+    /// Responds with the response of the first range containing the size of the request body,
+    /// or with `default` when none does.
+    ByBodySize {
+        #[serde(serialize_with = "serialize_conditional")]
+        sizes: Vec<(BodySize, ResponseHandler)>,
+        default: Box<ResponseHandler>,
+    },
+    /// Responds with `message` in the error envelope of serverify itself,
+    /// `{"serverify_error":{"message":...}}`.
+    Error {
+        status: u16,
+        message: String,
+    },
+}
+
+// the first cookie of the name wins, as browsers send the most specific one first
+fn find_cookie(headers: &HeaderMap, name: &str) -> Option<String> {
+    headers
+        .get_all(header::COOKIE)
+        .iter()
+        .filter_map(|value| value.to_str().ok())
+        .flat_map(|value| value.split(';'))
+        .filter_map(|pair| pair.trim().split_once('='))
+        .find(|(key, _)| *key == name)
+        .map(|(_, value)| value.trim_matches('"').to_string())
+}
+
+fn serialize_base64<S: Serializer>(bytes: &[u8], serializer: S) -> Result<S::Ok, S::Error> {
+    serializer.serialize_str(&BASE64_STANDARD.encode(bytes))
+}
+
+// written as in the config, where the fields of the condition sit next to `response`
+fn serialize_conditional<T: Serialize, S: Serializer>(
+    entries: &[(T, ResponseHandler)],
+    serializer: S,
+) -> Result<S::Ok, S::Error> {
+    #[derive(Serialize)]
+    struct Entry<'a, T> {
+        #[serde(flatten)]
+        condition: &'a T,
+        response: &'a ResponseHandler,
+    }
+
+    serializer.collect_seq(entries.iter().map(|(condition, response)| Entry {
+        condition,
+        response,
+    }))
+}
+
+impl ResponseHandler {
+    /// The `type` of the response in the config file.
+    pub fn type_name(&self) -> &'static str {
+        match self {
+            ResponseHandler::Static(_) => "static",
+            ResponseHandler::File { .. } => "file",
+            ResponseHandler::Dir { .. } => "dir",
+            ResponseHandler::Hangup { .. } => "hangup",
+            ResponseHandler::Truncated { .. } => "truncated",
+            ResponseHandler::StatusReflect { .. } => "status_reflect",
+            ResponseHandler::QueryEcho { .. } => "query_echo",
+            ResponseHandler::Sticky { .. } => "sticky",
+            ResponseHandler::ProxyPool { .. } => "proxy_pool",
+            ResponseHandler::CountGated { .. } => "count_gated",
+            ResponseHandler::Raw { .. } => "raw",
+            ResponseHandler::GrpcWeb { .. } => "grpc_web",
+            ResponseHandler::RandomBytes { .. } => "random_bytes",
+            ResponseHandler::RetryUntilSuccess { .. } => "retry_until_success",
+            ResponseHandler::MergePatch { .. } => "merge_patch",
+            ResponseHandler::Resource { .. } => "resource",
+            ResponseHandler::TimeWindows { .. } => "time_windows",
+            ResponseHandler::PerSession { .. } => "per_session",
+            ResponseHandler::Once { .. } => "once",
+            ResponseHandler::PartialContent { .. } => "partial_content",
+            ResponseHandler::ByCookie { .. } => "by_cookie",
+            ResponseHandler::Idempotent { .. } => "idempotent",
+            ResponseHandler::ByBodySize { .. } => "by_body_size",
+            ResponseHandler::Error { .. } => "error",
+        }
+    }
+
+    // the responses nested in this one, such as the variants of `sticky`
+    fn children_mut(&mut self) -> Vec<&mut ResponseHandler> {
+        match self {
+            ResponseHandler::Sticky { variants, .. } => variants.iter_mut().collect(),
+            ResponseHandler::CountGated { before, after, .. } => {
+                vec![before.as_mut(), after.as_mut()]
+            }
+            ResponseHandler::Once { first, then, .. } => vec![first.as_mut(), then.as_mut()],
+            ResponseHandler::RetryUntilSuccess { response, .. }
+            | ResponseHandler::Idempotent { response, .. } => vec![response.as_mut()],
+            ResponseHandler::TimeWindows {
+                windows, default, ..
+            } => windows
+                .iter_mut()
+                .map(|(_, response)| response)
+                .chain(std::iter::once(default.as_mut()))
+                .collect(),
+            ResponseHandler::ByBodySize { sizes, default } => sizes
+                .iter_mut()
+                .map(|(_, response)| response)
+                .chain(std::iter::once(default.as_mut()))
+                .collect(),
+            ResponseHandler::PerSession {
+                overrides: responses,
+                default,
+            }
+            | ResponseHandler::ByCookie {
+                responses, default, ..
+            } => responses
+                .values_mut()
+                .chain(std::iter::once(default.as_mut()))
+                .collect(),
+            _ => vec![],
+        }
+    }
+
+    pub async fn respond(&self, ctx: &RequestContext) -> Response {
+        match self {
+            ResponseHandler::Static(response) => {
+                if let Some(delay_ms) = response.delay_ms {
+                    tokio::time::sleep(Duration::from_millis(delay_ms)).await;
+                }
+                if let Some(latency) = &response.latency {
+                    let delay_ms = latency.sample(&mut rand::thread_rng());
+                    tokio::time::sleep(Duration::from_millis(delay_ms)).await;
+                }
+                response.respond(ctx)
+            }
+            ResponseHandler::File { path } => serve_file(Path::new(path)).await,
+            ResponseHandler::Dir { root } => {
+                let rest = ctx
+                    .path_params
+                    .get("serverify_path")
+                    .map(String::as_str)
+                    .unwrap_or_default();
+                match resolve_in_dir(Path::new(root), rest) {
+                    Some(path) => serve_file(&path).await,
+                    None => StatusCode::NOT_FOUND.into_response(),
+                }
+            }
+            ResponseHandler::Hangup { delay_ms } => {
+                tokio::time::sleep(Duration::from_millis(*delay_ms)).await;
+                let body = Body::from_stream(futures::stream::once(async {
+                    Err::<Bytes, _>(io::Error::new(io::ErrorKind::ConnectionAborted, "hangup"))
+                }));
+                // logged as nginx's "444 No Response" since no status is sent to the client
+                Response::builder()
+                    .status(StatusCode::from_u16(444).unwrap())
+                    .body(body)
+                    .unwrap()
+            }
+            ResponseHandler::Truncated {
+                status,
+                content_length,
+                body,
+            } => {
+                // streamed so that the body does not override the declared length.
+                // hyper drops buffered data when the body ends too early, so the stream yields
+                // once more after the data to let it be flushed before the connection is closed.
+                let data = futures::stream::iter([Ok::<_, io::Error>(Bytes::from(body.clone()))]);
+                let flush =
+                    futures::stream::once(tokio::task::yield_now()).filter_map(|_| async { None });
+                Response::builder()
+                    .status(*status)
+                    .header(header::CONTENT_LENGTH, *content_length)
+                    .body(Body::from_stream(data.chain(flush)))
+                    .unwrap_or_else(build_error)
+            }
+            ResponseHandler::StatusReflect {
+                status_param,
+                default,
+                statuses,
+            } => {
+                let value = Query::<IndexMap<String, String>>::try_from_uri(&ctx.parts.uri)
+                    .ok()
+                    .and_then(|Query(mut query)| query.swap_remove(status_param));
+                // mapped statuses are not clamped, so that a mistake in the config is noticed
+                if let Some(&status) = value.as_ref().and_then(|value| statuses.get(value)) {
+                    return match StatusCode::from_u16(status) {
+                        Ok(status) if (100..600).contains(&status.as_u16()) => {
+                            status.into_response()
+                        }
+                        _ => (
+                            StatusCode::INTERNAL_SERVER_ERROR,
+                            format!("invalid status: {}", status),
+                        )
+                            .into_response(),
+                    };
+                }
+                let status = value
+                    .and_then(|value| value.parse::<i64>().ok())
+                    .map(|status| status.clamp(100, 599) as u16)
+                    .unwrap_or(*default);
+                StatusCode::from_u16(status).unwrap().into_response()
+            }
+            ResponseHandler::QueryEcho { param } => {
+                let query = ctx.parts.uri.query().unwrap_or_default().to_string();
+                let param = param.clone();
+                // the pairs are parsed one at a time as the body is polled, and each element is
+                // sent as its own chunk, so that the array is never buffered
+                let mut rest = 0;
+                let values = std::iter::from_fn(move || {
+                    while rest < query.len() {
+                        let end = query[rest..].find('&').map_or(query.len(), |i| rest + i);
+                        let pair = form_urlencoded::parse(&query.as_bytes()[rest..end]).next();
+                        rest = end + 1;
+                        if let Some((_, value)) = pair.filter(|(key, _)| *key == param) {
+                            return Some(serde_json::to_string(&value).unwrap());
+                        }
+                    }
+                    None
+                });
+                let elements = futures::stream::iter(values)
+                    .enumerate()
+                    .map(|(i, element)| {
+                        if i == 0 {
+                            element
+                        } else {
+                            format!(",{}", element)
+                        }
+                    });
+                let chunks = futures::stream::once(async { "[".to_string() })
+                    .chain(elements)
+                    .chain(futures::stream::once(async { "]".to_string() }))
+                    .map(|chunk| Ok::<_, io::Error>(Bytes::from(chunk)));
+                (
+                    [(header::CONTENT_TYPE, "application/json")],
+                    Body::from_stream(chunks),
+                )
+                    .into_response()
+            }
+            ResponseHandler::Sticky { header, variants } => {
+                // a missing header is hashed as an empty value
+                let value = ctx
+                    .parts
+                    .headers
+                    .get(header)
+                    .map(|value| value.as_bytes())
+                    .unwrap_or_default();
+                match variants.get(fnv1a(value) as usize % variants.len().max(1)) {
+                    Some(variant) => Box::pin(variant.respond(ctx)).await,
+                    None => StatusCode::INTERNAL_SERVER_ERROR.into_response(),
+                }
+            }
+            ResponseHandler::ProxyPool {
+                targets,
+                strategy,
+                cursor,
+                cache_ttl_ms,
+                cache,
+            } => {
+                let key = format!(
+                    "{} {}",
+                    ctx.parts.method,
+                    ctx.parts
+                        .uri
+                        .path_and_query()
+                        .map(|path_and_query| path_and_query.as_str())
+                        .unwrap_or("/")
+                );
+                if cache_ttl_ms.is_some() {
+                    if let Some(cached) = cache.get(&key) {
+                        return cached;
+                    }
+                }
+
+                let index = match strategy {
+                    Strategy::RoundRobin => cursor.next(),
+                    Strategy::Random => rand::random(),
+                } % targets.len().max(1);
+                let response = match targets.get(index) {
+                    Some(target) => forward(target, ctx).await,
+                    None => StatusCode::BAD_GATEWAY.into_response(),
+                };
+                match cache_ttl_ms {
+                    Some(ttl_ms) => {
+                        cache
+                            .insert(key, response, Duration::from_millis(*ttl_ms))
+                            .await
+                    }
+                    None => response,
+                }
+            }
+            ResponseHandler::CountGated {
+                threshold,
+                before,
+                after,
+                count,
+            } => {
+                if count.next() < *threshold {
+                    Box::pin(before.respond(ctx)).await
+                } else {
+                    Box::pin(after.respond(ctx)).await
+                }
+            }
+            ResponseHandler::Raw {
+                status,
+                headers,
+                body,
+            } => headers
+                .iter()
+                .fold(Response::builder(), |builder, (key, value)| {
+                    builder.header(key, value)
+                })
+                .status(*status)
+                .body(Body::from(body.clone()))
+                .unwrap_or_else(build_error),
+            ResponseHandler::GrpcWeb {
+                message,
+                grpc_status,
+                grpc_message,
+            } => {
+                let mut trailers = format!("grpc-status:{}\r\n", grpc_status);
+                if let Some(grpc_message) = grpc_message {
+                    trailers.push_str(&format!("grpc-message:{}\r\n", grpc_message));
+                }
+                let mut body = grpc_web_frame(GRPC_WEB_DATA_FLAG, message);
+                body.extend(grpc_web_frame(GRPC_WEB_TRAILER_FLAG, trailers.as_bytes()));
+                ([(header::CONTENT_TYPE, "application/grpc-web+proto")], body).into_response()
+            }
+            ResponseHandler::RandomBytes {
+                size,
+                fill,
+                content_type,
+                seed,
+            } => {
+                let mut body = vec![0; *size];
+                if *fill == Fill::Random {
+                    let mut rng = match seed {
+                        Some(seed) => StdRng::seed_from_u64(*seed),
+                        None => StdRng::from_entropy(),
+                    };
+                    rng.fill_bytes(&mut body);
+                }
+                ([(header::CONTENT_TYPE, content_type.clone())], body).into_response()
+            }
+            ResponseHandler::RetryUntilSuccess {
+                header,
+                success_after,
+                pending_status,
+                response,
+                attempts,
+            } => {
+                // a missing header is counted as an empty key
+                let key = ctx
+                    .parts
+                    .headers
+                    .get(header)
+                    .map(|value| String::from_utf8_lossy(value.as_bytes()).to_string())
+                    .unwrap_or_default();
+                if attempts.next((ctx.session.clone(), key)) + 1 < *success_after {
+                    Response::builder()
+                        .status(*pending_status)
+                        .body(Body::empty())
+                        .unwrap_or_else(build_error)
+                } else {
+                    Box::pin(response.respond(ctx)).await
+                }
+            }
+            ResponseHandler::MergePatch { initial, documents } => {
+                let patch = if ctx.parts.method == axum::http::Method::PATCH {
+                    match serde_json::from_slice::<Value>(&ctx.body) {
+                        Ok(patch) => Some(patch),
+                        Err(err) => {
+                            return (
+                                StatusCode::BAD_REQUEST,
+                                format!("serverify: invalid merge patch: {}", err),
+                            )
+                                .into_response()
+                        }
+                    }
+                } else {
+                    None
+                };
+
+                let Ok(mut documents) = documents.0.lock() else {
+                    return poisoned_store();
+                };
+                let document = documents
+                    .entry((ctx.session.clone(), ctx.parts.uri.path().to_string()))
+                    .or_insert_with(|| initial.clone());
+                if let Some(patch) = patch {
+                    merge_patch(document, &patch);
+                }
+                Json(document.clone()).into_response()
+            }
+            ResponseHandler::Resource {
+                collection,
+                collections,
+            } => {
+                let method = &ctx.parts.method;
+                let item = if [axum::http::Method::POST, axum::http::Method::PUT].contains(method) {
+                    match serde_json::from_slice::<Value>(&ctx.body) {
+                        Ok(item) => item,
+                        Err(err) => {
+                            return (
+                                StatusCode::BAD_REQUEST,
+                                format!("serverify: invalid resource: {}", err),
+                            )
+                                .into_response()
+                        }
+                    }
+                } else {
+                    Value::Null
+                };
+
+                let Ok(mut collections) = collections.0.lock() else {
+                    return poisoned_store();
+                };
+                let resource = collections
+                    .entry((ctx.session.clone(), collection.clone()))
+                    .or_default();
+                match (method, ctx.path_params.get("serverify_id")) {
+                    (&axum::http::Method::GET, None) => {
+                        Json(resource.items.values().collect::<Vec<_>>()).into_response()
+                    }
+                    (&axum::http::Method::POST, None) => {
+                        resource.last_id += 1;
+                        let id = resource.last_id.to_string();
+                        let item = with_resource_id(item, &id);
+                        resource.items.insert(id, item.clone());
+                        (StatusCode::CREATED, Json(item)).into_response()
+                    }
+                    (_, Some(id)) if !resource.items.contains_key(id) => {
+                        StatusCode::NOT_FOUND.into_response()
+                    }
+                    (&axum::http::Method::GET, Some(id)) => {
+                        Json(&resource.items[id]).into_response()
+                    }
+                    (&axum::http::Method::PUT, Some(id)) => {
+                        let item = with_resource_id(item, id);
+                        resource.items.insert(id.clone(), item.clone());
+                        Json(item).into_response()
+                    }
+                    (&axum::http::Method::DELETE, Some(id)) => {
+                        resource.items.shift_remove(id);
+                        StatusCode::NO_CONTENT.into_response()
+                    }
+                    _ => StatusCode::METHOD_NOT_ALLOWED.into_response(),
+                }
+            }
+            ResponseHandler::TimeWindows {
+                windows,
+                default,
+                clock,
+            } => {
+                let now = clock.now();
+                let response = windows
+                    .iter()
+                    .find(|(window, _)| window.contains(now))
+                    .map_or(default.as_ref(), |(_, response)| response);
+                Box::pin(response.respond(ctx)).await
+            }
+            ResponseHandler::PerSession { overrides, default } => {
+                let response = overrides.get(&ctx.session).unwrap_or(default);
+                Box::pin(response.respond(ctx)).await
+            }
+            ResponseHandler::ByCookie {
+                cookie,
+                responses,
+                default,
+            } => {
+                let response = find_cookie(&ctx.parts.headers, cookie)
+                    .and_then(|value| responses.get(&value))
+                    .unwrap_or(default);
+                Box::pin(response.respond(ctx)).await
+            }
+            ResponseHandler::ByBodySize { sizes, default } => {
+                let response = sizes
+                    .iter()
+                    .find(|(size, _)| size.contains(ctx.body.len()))
+                    .map_or(default.as_ref(), |(_, response)| response);
+                Box::pin(response.respond(ctx)).await
+            }
+            ResponseHandler::Idempotent {
+                header,
+                ttl_ms,
+                response,
+                cache,
+            } => {
+                let Some(value) = ctx.parts.headers.get(header) else {
+                    return Box::pin(response.respond(ctx)).await;
+                };
+                // the session is separated by a character which cannot be in its name
+                let key = format!(
+                    "{}\n{}",
+                    ctx.session,
+                    String::from_utf8_lossy(value.as_bytes())
+                );
+                if let Some(cached) = cache.get(&key) {
+                    return cached;
+                }
+                let res = Box::pin(response.respond(ctx)).await;
+                cache.insert(key, res, Duration::from_millis(*ttl_ms)).await
+            }
+            ResponseHandler::Error { status, message } => error_response::<()>(
+                StatusCode::from_u16(*status).unwrap_or(StatusCode::INTERNAL_SERVER_ERROR),
+                message,
+            )
+            .into_response(),
+            ResponseHandler::PartialContent { body, content_type } => {
+                let range = ctx
+                    .parts
+                    .headers
+                    .get(header::RANGE)
+                    .and_then(|value| value.to_str().ok())
+                    .and_then(|value| parse_byte_range(value, body.len()));
+                let builder = Response::builder().header(header::ACCEPT_RANGES, "bytes");
+                let builder = match content_type {
+                    Some(content_type) => builder.header(header::CONTENT_TYPE, content_type),
+                    None => builder,
+                };
+                match range {
+                    None => builder
+                        .status(StatusCode::OK)
+                        .body(Body::from(body.clone())),
+                    Some(ByteRange::Satisfiable(start, end)) => builder
+                        .status(StatusCode::PARTIAL_CONTENT)
+                        .header(
+                            header::CONTENT_RANGE,
+                            format!("bytes {}-{}/{}", start, end, body.len()),
+                        )
+                        .body(Body::from(body.as_bytes()[start..=end].to_vec())),
+                    Some(ByteRange::Unsatisfiable) => builder
+                        .status(StatusCode::RANGE_NOT_SATISFIABLE)
+                        .header(header::CONTENT_RANGE, format!("bytes */{}", body.len()))
+                        .body(Body::empty()),
+                }
+                .unwrap_or_else(build_error)
+            }
+            ResponseHandler::Once {
+                first,
+                then,
+                per_session,
+                served,
+            } => {
+                let session = if *per_session {
+                    ctx.session.clone()
+                } else {
+                    String::new()
+                };
+                if served.next((session, String::new())) == 0 {
+                    Box::pin(first.respond(ctx)).await
+                } else {
+                    Box::pin(then.respond(ctx)).await
+                }
+            }
+        }
+    }
+}
+
+#[derive(PartialEq, Debug)]
+enum ByteRange {
+    /// The first and the last byte positions, both inclusive.
+    Satisfiable(usize, usize),
+    Unsatisfiable,
+}
+
+// malformed headers and multiple ranges are ignored, so that the whole body is sent
+fn parse_byte_range(value: &str, len: usize) -> Option<ByteRange> {
+    let spec = value.trim().strip_prefix("bytes=")?;
+    if spec.contains(',') {
+        return None;
+    }
+    let (start, end) = spec.trim().split_once('-')?;
+    let range = match (start, end) {
+        ("", suffix) => {
+            let suffix = suffix.parse::<usize>().ok()?;
+            if suffix == 0 || len == 0 {
+                ByteRange::Unsatisfiable
+            } else {
+                ByteRange::Satisfiable(len.saturating_sub(suffix), len - 1)
+            }
+        }
+        (start, end) => {
+            let start = start.parse::<usize>().ok()?;
+            let end = match end {
+                "" => usize::MAX,
+                end => end.parse::<usize>().ok()?,
+            };
+            if end < start {
+                return None;
+            }
+            if start >= len {
+                ByteRange::Unsatisfiable
+            } else {
+                ByteRange::Satisfiable(start, end.min(len - 1))
+            }
+        }
+    };
+    Some(range)
+}
+
+impl StaticResponse {
+    fn respond(&self, ctx: &RequestContext) -> Response {
+        let last_modified = self
+            .last_modified
+            .map(|last_modified| last_modified.format(HTTP_DATE_FORMAT).to_string());
+
+        if let Some(last_modified) = &last_modified {
+            if self.is_not_modified(ctx) {
+                return (
+                    StatusCode::NOT_MODIFIED,
+                    [(header::LAST_MODIFIED, last_modified.as_str())],
+                )
+                    .into_response();
+            }
+        }
+
+        let (status, body) = self.forced_status(ctx).unwrap_or((self.status, &self.body));
+
+        let builder = self
+            .headers
+            .iter()
+            .fold(Response::builder(), |builder, (key, value)| {
+                builder.header(key, value)
+            });
+        let builder = self.cookies.iter().fold(builder, |builder, cookie| {
+            builder.header(header::SET_COOKIE, cookie.to_header_value())
+        });
+        let builder = match &last_modified {
+            Some(last_modified) => builder.header(header::LAST_MODIFIED, last_modified),
+            None => builder,
+        };
+        let builder = if self.connection_close {
+            builder.header(header::CONNECTION, "close")
+        } else {
+            builder
+        };
+
+        let body = match (self.chunk_size, self.reset_after) {
+            (chunk_size, Some(reset_after)) => {
+                let prefix = body.slice(..reset_after.min(body.len()));
+                let chunks = prefix
+                    .chunks(chunk_size.unwrap_or(prefix.len()).max(1))
+                    .map(|chunk| Ok::<_, io::Error>(Bytes::copy_from_slice(chunk)))
+                    .collect::<Vec<_>>();
+                // yields once to let hyper flush the prefix, as on truncated responses, and then
+                // fails the body, which makes hyper abort the connection
+                let flush =
+                    futures::stream::once(tokio::task::yield_now()).filter_map(|_| async { None });
+                let reset = futures::stream::once(async {
+                    Err(io::Error::new(io::ErrorKind::ConnectionReset, "reset"))
+                });
+                Body::from_stream(futures::stream::iter(chunks).chain(flush).chain(reset))
+            }
+            // a streamed body has no known length, so hyper falls back to chunked encoding
+            (Some(chunk_size), None) => {
+                let chunks = body
+                    .chunks(chunk_size.max(1))
+                    .map(|chunk| Ok::<_, io::Error>(Bytes::copy_from_slice(chunk)))
+                    .collect::<Vec<_>>();
+                Body::from_stream(futures::stream::iter(chunks))
+            }
+            (None, None) if body.is_empty() && self.empty_body == EmptyBody::Chunked => {
+                Body::from_stream(futures::stream::empty::<Result<Bytes, io::Error>>())
+            }
+            (None, None) => Body::from(body.clone()),
+        };
+
+        builder
+            .status(status)
+            .body(body)
+            .unwrap_or_else(build_error)
+    }
+
+    // the parameter is ignored when it asks for a status without a body of its own
+    fn forced_status(&self, ctx: &RequestContext) -> Option<(u16, &Bytes)> {
+        let Query(query) = Query::<IndexMap<String, String>>::try_from_uri(&ctx.parts.uri).ok()?;
+        let status = query.get("force_status")?.parse::<u16>().ok()?;
+        self.status_bodies
+            .get_key_value(&status)
+            .map(|(status, body)| (*status, body))
+    }
+
+    fn is_not_modified(&self, ctx: &RequestContext) -> bool {
+        if ctx.parts.method != axum::http::Method::GET
+            && ctx.parts.method != axum::http::Method::HEAD
+        {
+            return false;
+        }
+
+        let Some(last_modified) = self.last_modified else {
+            return false;
+        };
+
+        ctx.parts
+            .headers
+            .get(header::IF_MODIFIED_SINCE)
+            .and_then(|value| value.to_str().ok())
+            .and_then(parse_http_date)
+            // HTTP dates have only second precision
+            .is_some_and(|since| since.timestamp() >= last_modified.timestamp())
+    }
+}
+
+// configured statuses and headers are not validated, so building a response can fail at request
+// time. The fallback is made of constants, so that it can never fail itself.
+fn build_error(err: axum::http::Error) -> Response {
+    (
+        StatusCode::INTERNAL_SERVER_ERROR,
+        format!("serverify: failed to build the response: {}", err),
+    )
+        .into_response()
+}
+
+// a handler panicked while holding the store, so the data kept in it may be broken
+fn poisoned_store() -> Response {
+    (
+        StatusCode::INTERNAL_SERVER_ERROR,
+        "serverify: the data of the response is unavailable after a panic",
+    )
+        .into_response()
+}
+
+// hop-by-hop headers are meaningful only for a single connection, so they are not relayed
+const HOP_BY_HOP_HEADERS: [HeaderName; 5] = [
+    header::CONNECTION,
+    header::HOST,
+    header::TRANSFER_ENCODING,
+    header::UPGRADE,
+    header::CONTENT_LENGTH,
+];
+
+impl ResponseCache {
+    pub fn with_capacity(capacity: usize) -> Self {
+        Self {
+            entries: Default::default(),
+            capacity,
+        }
+    }
+
+    fn get(&self, key: &str) -> Option<Response> {
+        let entries = self.entries.lock().unwrap();
+        let cached = entries
+            .get(key)
+            .filter(|cached| cached.expires_at > Instant::now())?;
+        let mut response = (cached.status, cached.body.clone()).into_response();
+        *response.headers_mut() = cached.headers.clone();
+        Some(response)
+    }
+
+    // the body is buffered to be kept, and the response is rebuilt from it
+    async fn insert(&self, key: String, response: Response, ttl: Duration) -> Response {
+        let (parts, body) = response.into_parts();
+        let body = match axum::body::to_bytes(body, usize::MAX).await {
+            Ok(body) => body,
+            Err(err) => return (StatusCode::BAD_GATEWAY, err.to_string()).into_response(),
+        };
+        let no_store = parts
+            .headers
+            .get_all(header::CACHE_CONTROL)
+            .iter()
+            .filter_map(|value| value.to_str().ok())
+            .flat_map(|value| value.split(','))
+            .any(|directive| directive.trim().eq_ignore_ascii_case("no-store"));
+        if !no_store && !parts.status.is_server_error() {
+            let now = Instant::now();
+            let mut entries = self.entries.lock().unwrap();
+            // expired entries are dropped here, as every distinct key would stay forever otherwise
+            entries.retain(|_, cached| cached.expires_at > now);
+            if entries.len() >= self.capacity && !entries.contains_key(&key) {
+                let oldest = entries
+                    .iter()
+                    .min_by_key(|(_, cached)| cached.expires_at)
+                    .map(|(key, _)| key.clone());
+                if let Some(oldest) = oldest {
+                    entries.remove(&oldest);
+                }
+            }
+            entries.insert(
+                key,
+                CachedResponse {
+                    status: parts.status,
+                    headers: parts.headers.clone(),
+                    body: body.clone(),
+                    expires_at: now + ttl,
+                },
+            );
+        }
+        Response::from_parts(parts, Body::from(body))
+    }
+}
+
+/// Sends the request to `base_url` joined with its path and query, and relays the response.
+pub async fn forward(base_url: &str, ctx: &RequestContext) -> Response {
+    let path_and_query = ctx
+        .parts
+        .uri
+        .path_and_query()
+        .map(|path_and_query| path_and_query.as_str())
+        .unwrap_or("/");
+    let url = format!("{}{}", base_url.trim_end_matches('/'), path_and_query);
+    let headers = ctx
+        .parts
+        .headers
+        .iter()
+        .filter(|(name, _)| !HOP_BY_HOP_HEADERS.contains(name))
+        .map(|(name, value)| (name.clone(), value.clone()))
+        .collect();
+
+    let upstream = HTTP_CLIENT
+        .request(ctx.parts.method.clone(), &url)
+        .headers(headers)
+        .body(ctx.body.clone())
+        .send()
+        .await;
+    let upstream = match upstream {
+        Ok(upstream) => upstream,
+        Err(err) => {
+            return (
+                StatusCode::BAD_GATEWAY,
+                [(UPSTREAM_HEADER.clone(), base_url.to_string())],
+                err.to_string(),
+            )
+                .into_response()
+        }
+    };
+
+    let builder = upstream
+        .headers()
+        .iter()
+        .filter(|(name, _)| !HOP_BY_HOP_HEADERS.contains(name))
+        .fold(Response::builder(), |builder, (name, value)| {
+            builder.header(name, value)
+        })
+        .status(upstream.status())
+        .header(&UPSTREAM_HEADER, base_url);
+    match upstream.bytes().await {
+        Ok(body) => builder.body(Body::from(body)).unwrap_or_else(build_error),
+        Err(err) => (StatusCode::BAD_GATEWAY, err.to_string()).into_response(),
+    }
+}
+
+const GRPC_WEB_DATA_FLAG: u8 = 0x00;
+const GRPC_WEB_TRAILER_FLAG: u8 = 0x80;
+
+// a frame is a 1-byte flag and a 4-byte big-endian length followed by the payload
+fn grpc_web_frame(flag: u8, payload: &[u8]) -> Vec<u8> {
+    let mut frame = Vec::with_capacity(5 + payload.len());
+    frame.push(flag);
+    frame.extend((payload.len() as u32).to_be_bytes());
+    frame.extend(payload);
+    frame
+}
+
+// FNV-1a is used since the choice must be stable across processes, unlike `DefaultHasher`
+fn fnv1a(bytes: &[u8]) -> u64 {
+    bytes.iter().fold(0xcbf29ce484222325, |hash, byte| {
+        (hash ^ u64::from(*byte)).wrapping_mul(0x100000001b3)
+    })
+}
+
+const HTTP_DATE_FORMAT: &str = "%a, %d %b %Y %H:%M:%S GMT";
+
+// accepts IMF-fixdate and the obsolete RFC 850 and asctime formats (RFC 9110 section 5.6.7)
+fn parse_http_date(value: &str) -> Option<DateTime<Utc>> {
+    [
+        "%a, %d %b %Y %H:%M:%S GMT",
+        "%A, %d-%b-%y %H:%M:%S GMT",
+        "%a %b %e %H:%M:%S %Y",
+    ]
+    .iter()
+    .find_map(|format| NaiveDateTime::parse_from_str(value.trim(), format).ok())
+    .map(|naive| naive.and_utc())
+}
+
+// items which are not objects are kept as they are
+fn with_resource_id(item: Value, id: &str) -> Value {
+    match item {
+        Value::Object(mut item) => {
+            item.insert("id".to_string(), Value::String(id.to_string()));
+            Value::Object(item)
+        }
+        item => item,
+    }
+}
+
+// applies `patch` to `target` as described in RFC 7386
+fn merge_patch(target: &mut Value, patch: &Value) {
+    let Value::Object(patch) = patch else {
+        *target = patch.clone();
+        return;
+    };
+    if !target.is_object() {
+        *target = Value::Object(Default::default());
+    }
+    let Value::Object(target) = target else {
+        unreachable!()
+    };
+    for (key, value) in patch {
+        if value.is_null() {
+            target.shift_remove(key);
+        } else {
+            merge_patch(target.entry(key).or_insert(Value::Null), value);
+        }
+    }
+}
+
+// the file is opened per request so that it can be replaced while serving
+async fn serve_file(path: &Path) -> Response {
+    let Ok(file) = tokio::fs::File::open(path).await else {
+        return StatusCode::NOT_FOUND.into_response();
+    };
+
+    let content_type = mime_guess::from_path(path).first_or_octet_stream();
+    (
+        [(header::CONTENT_TYPE, content_type.to_string())],
+        Body::from_stream(ReaderStream::new(file)),
+    )
+        .into_response()
+}
+
+fn resolve_in_dir(root: &Path, rest: &str) -> Option<PathBuf> {
+    let rest = Path::new(rest.trim_start_matches('/'));
+    // reject anything which could escape from the root, such as `..` or absolute paths
+    if !rest
+        .components()
+        .all(|component| matches!(component, Component::Normal(_)))
+    {
+        return None;
+    }
+
+    let path = root.join(rest);
+    if path.is_dir() {
+        Some(path.join("index.html"))
+    } else {
+        Some(path)
+    }
+}
+
+#[cfg(test)]
+pub mod testutil {
+    use super::*;
+    use axum::http::Request;
+
+    pub fn new_context(session: &str, uri: &str) -> RequestContext {
+        let (parts, _) = Request::get(uri).body(()).unwrap().into_parts();
+        RequestContext {
+            session: session.to_string(),
+            parts,
+            path_params: IndexMap::new(),
+            body: Bytes::new(),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::testutil::new_context;
+    use super::*;
+    use axum::body::to_bytes;
+    use pretty_assertions::assert_eq;
+    use std::io::Write;
+
+    mod file {
+        use super::*;
+        use pretty_assertions::assert_eq;
+
+        #[tokio::test]
+        async fn when_file_exists() {
+            let mut file = tempfile::Builder::new().suffix(".txt").tempfile().unwrap();
+            write!(file, "Hello, file!").unwrap();
+
+            let handler = ResponseHandler::File {
+                path: file.path().to_str().unwrap().to_string(),
+            };
+            let response = handler.respond(&new_context("default", "/")).await;
+
+            assert_eq!(
+                (StatusCode::OK, Some("text/plain")),
+                (
+                    response.status(),
+                    response
+                        .headers()
+                        .get(header::CONTENT_TYPE)
+                        .and_then(|v| v.to_str().ok())
+                )
+            );
+            assert_eq!(
+                "Hello, file!",
+                to_bytes(response.into_body(), usize::MAX).await.unwrap()
+            );
+        }
+
+        #[tokio::test]
+        async fn when_file_is_missing() {
+            let dir = tempfile::tempdir().unwrap();
+            let handler = ResponseHandler::File {
+                path: dir.path().join("missing.txt").to_str().unwrap().to_string(),
+            };
+
+            assert_eq!(
+                StatusCode::NOT_FOUND,
+                handler.respond(&new_context("default", "/")).await.status()
+            );
+        }
+    }
+
+    mod dir {
+        use super::*;
+        use indexmap::indexmap;
+        use pretty_assertions::assert_eq;
+
+        fn new_dir_context(rest: &str) -> RequestContext {
+            let mut ctx = new_context("default", "/");
+            ctx.path_params = indexmap! { "serverify_path".to_string() => rest.to_string() };
+            ctx
+        }
+
+        fn new_root() -> tempfile::TempDir {
+            let root = tempfile::tempdir().unwrap();
+            std::fs::create_dir_all(root.path().join("css/nested")).unwrap();
+            std::fs::write(root.path().join("css/nested/site.css"), "body {}").unwrap();
+            std::fs::write(root.path().join("index.html"), "<h1>index</h1>").unwrap();
+            root
+        }
+
+        #[tokio::test]
+        async fn when_nested_file_is_requested() {
+            let root = new_root();
+            let handler = ResponseHandler::Dir {
+                root: root.path().to_str().unwrap().to_string(),
+            };
+            let response = handler
+                .respond(&new_dir_context("css/nested/site.css"))
+                .await;
+
+            assert_eq!(
+                (StatusCode::OK, Some("text/css")),
+                (
+                    response.status(),
+                    response
+                        .headers()
+                        .get(header::CONTENT_TYPE)
+                        .and_then(|v| v.to_str().ok())
+                )
+            );
+            assert_eq!(
+                "body {}",
+                to_bytes(response.into_body(), usize::MAX).await.unwrap()
+            );
+        }
+
+        #[tokio::test]
+        async fn when_directory_is_requested() {
+            let root = new_root();
+            let handler = ResponseHandler::Dir {
+                root: root.path().to_str().unwrap().to_string(),
+            };
+            let response = handler.respond(&new_dir_context("")).await;
+
+            assert_eq!(StatusCode::OK, response.status());
+            assert_eq!(
+                "<h1>index</h1>",
+                to_bytes(response.into_body(), usize::MAX).await.unwrap()
+            );
+        }
+
+        #[tokio::test]
+        async fn when_traversal_is_attempted() {
+            let root = new_root();
+            let handler = ResponseHandler::Dir {
+                root: root.path().join("css").to_str().unwrap().to_string(),
+            };
+            let response = handler.respond(&new_dir_context("../index.html")).await;
+
+            assert_eq!(StatusCode::NOT_FOUND, response.status());
+        }
+    }
+
+    mod status_reflect {
+        use super::*;
+        use pretty_assertions::assert_eq;
+        use rstest::rstest;
+
+        #[rstest]
+        #[case("/", StatusCode::OK)]
+        #[case("/?status=418", StatusCode::IM_A_TEAPOT)]
+        #[case("/?status=teapot", StatusCode::OK)]
+        #[case("/?status=", StatusCode::OK)]
+        #[case("/?status=42", StatusCode::CONTINUE)]
+        #[case("/?status=1000", StatusCode::from_u16(599).unwrap())]
+        #[tokio::test]
+        async fn respond(#[case] uri: &str, #[case] expected: StatusCode) {
+            let handler = ResponseHandler::StatusReflect {
+                status_param: "status".to_string(),
+                default: 200,
+                statuses: IndexMap::new(),
+            };
+            let response = handler.respond(&new_context("default", uri)).await;
+
+            assert_eq!(
+                (expected, Bytes::new()),
+                (
+                    response.status(),
+                    to_bytes(response.into_body(), usize::MAX).await.unwrap()
+                )
+            );
+        }
+    }
+
+    mod status_reflect_with_statuses {
+        use super::*;
+        use pretty_assertions::assert_eq;
+        use rstest::rstest;
+
+        #[rstest]
+        #[case("/?id=42", StatusCode::OK)]
+        #[case("/?id=missing", StatusCode::NOT_FOUND)]
+        #[case("/?id=broken", StatusCode::INTERNAL_SERVER_ERROR)]
+        #[case("/?id=418", StatusCode::IM_A_TEAPOT)]
+        #[tokio::test]
+        async fn respond(#[case] uri: &str, #[case] expected: StatusCode) {
+            let handler = ResponseHandler::StatusReflect {
+                status_param: "id".to_string(),
+                default: 200,
+                statuses: indexmap::indexmap! {
+                    "42".to_string() => 200,
+                    "missing".to_string() => 404,
+                    "broken".to_string() => 1000,
+                },
+            };
+            let response = handler.respond(&new_context("default", uri)).await;
+
+            assert_eq!(expected, response.status());
+        }
+    }
+
+    mod status_bodies {
+        use super::*;
+        use pretty_assertions::assert_eq;
+        use rstest::rstest;
+
+        #[rstest]
+        #[case("/", StatusCode::OK, "found")]
+        #[case("/?force_status=404", StatusCode::NOT_FOUND, "not found")]
+        #[case("/?force_status=500", StatusCode::OK, "found")]
+        #[case("/?force_status=teapot", StatusCode::OK, "found")]
+        #[tokio::test]
+        async fn respond(
+            #[case] uri: &str,
+            #[case] expected_status: StatusCode,
+            #[case] expected_body: &str,
+        ) {
+            let handler = ResponseHandler::Static(StaticResponse {
+                status: 200,
+                body: "found".into(),
+                status_bodies: indexmap::indexmap! { 404 => "not found".into() },
+                ..Default::default()
+            });
+            let response = handler.respond(&new_context("default", uri)).await;
+
+            assert_eq!(
+                (expected_status, expected_body.as_bytes()),
+                (
+                    response.status(),
+                    to_bytes(response.into_body(), usize::MAX)
+                        .await
+                        .unwrap()
+                        .as_ref()
+                )
+            );
+        }
+    }
+
+    mod chunked {
+        use super::*;
+        use axum::body::HttpBody;
+        use http_body_util::BodyExt;
+        use pretty_assertions::assert_eq;
+
+        #[tokio::test]
+        async fn body_is_streamed_in_chunks() {
+            let handler = ResponseHandler::Static(StaticResponse {
+                status: 200,
+                body: "Hello, chunked world!".into(),
+                chunk_size: Some(4),
+                ..Default::default()
+            });
+            let response = handler.respond(&new_context("default", "/")).await;
+            let mut body = response.into_body();
+
+            assert_eq!(None, body.size_hint().exact());
+            let mut chunks = vec![];
+            while let Some(frame) = body.frame().await {
+                chunks.push(frame.unwrap().into_data().unwrap());
+            }
+            assert_eq!(
+                vec!["Hell", "o, c", "hunk", "ed w", "orld", "!"],
+                chunks
+                    .iter()
+                    .map(|chunk| std::str::from_utf8(chunk).unwrap())
+                    .collect::<Vec<_>>()
+            );
+        }
+    }
+
+    mod build_error {
+        use super::*;
+        use indexmap::indexmap;
+        use pretty_assertions::assert_eq;
+        use rstest::rstest;
+
+        #[rstest]
+        #[case(
+            "invalid header name",
+            StaticResponse {
+                status: 200,
+                headers: indexmap! { "bad header".to_string() => "value".to_string() },
+                ..Default::default()
+            },
+            "serverify: failed to build the response: invalid HTTP header name"
+        )]
+        #[case(
+            "invalid status",
+            StaticResponse {
+                status: 1000,
+                ..Default::default()
+            },
+            "serverify: failed to build the response: invalid status code"
+        )]
+        #[tokio::test]
+        async fn falls_back_to_500(
+            #[case] title: &str,
+            #[case] response: StaticResponse,
+            #[case] expected_body: &str,
+        ) {
+            let response = ResponseHandler::Static(response)
+                .respond(&new_context("default", "/"))
+                .await;
+
+            assert_eq!(
+                StatusCode::INTERNAL_SERVER_ERROR,
+                response.status(),
+                "{}",
+                title
+            );
+            assert_eq!(
+                expected_body,
+                to_bytes(response.into_body(), usize::MAX).await.unwrap(),
+                "{}",
+                title
+            );
+        }
+    }
+
+    mod internal_redirect {
+        use super::*;
+        use indexmap::indexmap;
+        use pretty_assertions::assert_eq;
+
+        #[tokio::test]
+        async fn header_is_emitted_with_empty_body() {
+            let handler = ResponseHandler::Static(StaticResponse {
+                status: 200,
+                headers: indexmap! {
+                    "X-Accel-Redirect".to_string() => "/protected/file.bin".to_string(),
+                },
+                ..Default::default()
+            });
+            let response = handler.respond(&new_context("default", "/")).await;
+
+            assert_eq!(
+                (StatusCode::OK, Some("/protected/file.bin")),
+                (
+                    response.status(),
+                    response
+                        .headers()
+                        .get("x-accel-redirect")
+                        .and_then(|v| v.to_str().ok())
+                )
+            );
+            assert_eq!(
+                Bytes::new(),
+                to_bytes(response.into_body(), usize::MAX).await.unwrap()
+            );
+        }
+    }
+
+    mod query_echo {
+        use super::*;
+        use pretty_assertions::assert_eq;
+        use rstest::rstest;
+
+        #[rstest]
+        #[case("/", "[]")]
+        #[case("/?other=1", "[]")]
+        #[case("/?tag=a", r#"["a"]"#)]
+        #[case("/?tag=b&other=1&tag=a&tag=%22q%22", r#"["b","a","\"q\""]"#)]
+        #[tokio::test]
+        async fn respond(#[case] uri: &str, #[case] expected: &str) {
+            let handler = ResponseHandler::QueryEcho {
+                param: "tag".to_string(),
+            };
+            let response = handler.respond(&new_context("default", uri)).await;
+
+            assert_eq!(
+                expected,
+                to_bytes(response.into_body(), usize::MAX).await.unwrap()
+            );
+        }
+    }
+
+    mod sticky {
+        use super::*;
+        use axum::http::Request;
+        use pretty_assertions::assert_eq;
+
+        fn handler() -> ResponseHandler {
+            ResponseHandler::Sticky {
+                header: "x-client-id".to_string(),
+                variants: ["a", "b", "c"]
+                    .iter()
+                    .map(|body| {
+                        ResponseHandler::Static(StaticResponse {
+                            status: 200,
+                            body: body.to_string().into(),
+                            ..Default::default()
+                        })
+                    })
+                    .collect(),
+            }
+        }
+
+        async fn respond_to(client_id: &str) -> Bytes {
+            let (parts, _) = Request::get("/")
+                .header("x-client-id", client_id)
+                .body(())
+                .unwrap()
+                .into_parts();
+            let ctx = RequestContext {
+                parts,
+                ..new_context("default", "/")
+            };
+            let response = handler().respond(&ctx).await;
+            to_bytes(response.into_body(), usize::MAX).await.unwrap()
+        }
+
+        #[tokio::test]
+        async fn same_client_gets_same_variant() {
+            let alice = respond_to("alice").await;
+            let bob = respond_to("bob").await;
+
+            assert_eq!("c", alice);
+            assert_eq!("a", bob);
+            assert_eq!(alice, respond_to("alice").await);
+            assert_eq!(bob, respond_to("bob").await);
+        }
+    }
+
+    mod proxy_pool {
+        use super::*;
+        use pretty_assertions::assert_eq;
+
+        async fn spawn_upstream(name: &'static str) -> String {
+            let app = axum::Router::new().fallback(move || async move { name });
+            let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+            let addr = listener.local_addr().unwrap();
+            tokio::spawn(async move { axum::serve(listener, app).await });
+            format!("http://{}", addr)
+        }
+
+        #[tokio::test]
+        async fn round_robin() {
+            let targets = vec![spawn_upstream("a").await, spawn_upstream("b").await];
+            let handler = ResponseHandler::ProxyPool {
+                targets: targets.clone(),
+                strategy: Strategy::RoundRobin,
+                cursor: Counter::default(),
+                cache_ttl_ms: None,
+                cache: ResponseCache::default(),
+            };
+
+            let mut received = vec![];
+            for _ in 0..4 {
+                let response = handler.respond(&new_context("default", "/hello")).await;
+                let upstream = response.headers()[&UPSTREAM_HEADER]
+                    .to_str()
+                    .unwrap()
+                    .to_string();
+                let body = to_bytes(response.into_body(), usize::MAX).await.unwrap();
+                received.push((upstream, String::from_utf8(body.to_vec()).unwrap()));
+            }
+
+            assert_eq!(
+                vec![
+                    (targets[0].clone(), "a".to_string()),
+                    (targets[1].clone(), "b".to_string()),
+                    (targets[0].clone(), "a".to_string()),
+                    (targets[1].clone(), "b".to_string()),
+                ],
+                received
+            );
+        }
+
+        #[tokio::test]
+        async fn when_upstream_is_down() {
+            let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+            let target = format!("http://{}", listener.local_addr().unwrap());
+            drop(listener);
+            let handler = ResponseHandler::ProxyPool {
+                targets: vec![target],
+                strategy: Strategy::Random,
+                cursor: Counter::default(),
+                cache_ttl_ms: None,
+                cache: ResponseCache::default(),
+            };
+
+            let response = handler.respond(&new_context("default", "/")).await;
+
+            assert_eq!(StatusCode::BAD_GATEWAY, response.status());
+        }
+    }
+
+    mod proxy_cache {
+        use super::*;
+        use pretty_assertions::assert_eq;
+        use rstest::rstest;
+
+        async fn spawn_counting_upstream(cache_control: &'static str) -> (String, Counter) {
+            let calls = Counter::default();
+            let app = axum::Router::new().fallback({
+                let calls = calls.clone();
+                move || async move {
+                    let n = calls.next();
+                    (
+                        [(header::CACHE_CONTROL, cache_control)],
+                        format!("call {}", n),
+                    )
+                }
+            });
+            let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+            let addr = listener.local_addr().unwrap();
+            tokio::spawn(async move { axum::serve(listener, app).await });
+            (format!("http://{}", addr), calls)
+        }
+
+        #[rstest]
+        #[case("max-age=60", Some(60_000), vec!["call 0", "call 0"])]
+        #[case("max-age=60", None, vec!["call 0", "call 1"])]
+        #[case("private, no-store", Some(60_000), vec!["call 0", "call 1"])]
+        #[tokio::test]
+        async fn second_request_within_ttl_is_cached(
+            #[case] cache_control: &'static str,
+            #[case] cache_ttl_ms: Option<u64>,
+            #[case] expected: Vec<&str>,
+        ) {
+            let (target, _) = spawn_counting_upstream(cache_control).await;
+            let handler = ResponseHandler::ProxyPool {
+                targets: vec![target],
+                strategy: Strategy::RoundRobin,
+                cursor: Counter::default(),
+                cache_ttl_ms,
+                cache: ResponseCache::default(),
+            };
+
+            let mut bodies = vec![];
+            for _ in 0..2 {
+                let response = handler.respond(&new_context("default", "/hello?a=1")).await;
+                let body = to_bytes(response.into_body(), usize::MAX).await.unwrap();
+                bodies.push(String::from_utf8(body.to_vec()).unwrap());
+            }
+
+            assert_eq!(expected, bodies);
+        }
+
+        #[tokio::test]
+        async fn cache_expires_after_ttl() {
+            let (target, calls) = spawn_counting_upstream("max-age=60").await;
+            let handler = ResponseHandler::ProxyPool {
+                targets: vec![target],
+                strategy: Strategy::RoundRobin,
+                cursor: Counter::default(),
+                cache_ttl_ms: Some(50),
+                cache: ResponseCache::default(),
+            };
+
+            handler.respond(&new_context("default", "/hello")).await;
+            tokio::time::sleep(Duration::from_millis(100)).await;
+            handler.respond(&new_context("default", "/hello")).await;
+
+            assert_eq!(2, calls.next());
+        }
+
+        #[tokio::test]
+        async fn expired_entries_are_removed_on_insert() {
+            let (target, _) = spawn_counting_upstream("max-age=60").await;
+            let cache = ResponseCache::default();
+            let handler = ResponseHandler::ProxyPool {
+                targets: vec![target],
+                strategy: Strategy::RoundRobin,
+                cursor: Counter::default(),
+                cache_ttl_ms: Some(50),
+                cache: cache.clone(),
+            };
+
+            handler.respond(&new_context("default", "/old")).await;
+            tokio::time::sleep(Duration::from_millis(100)).await;
+            handler.respond(&new_context("default", "/new")).await;
+
+            let entries = cache.entries.lock().unwrap();
+            assert_eq!(
+                vec!["GET /new"],
+                entries.keys().map(String::as_str).collect::<Vec<_>>()
+            );
+        }
+    }
+
+    mod count_gated {
+        use super::*;
+        use pretty_assertions::assert_eq;
+
+        #[tokio::test]
+        async fn switches_after_threshold() {
+            let handler = ResponseHandler::CountGated {
+                threshold: 2,
+                before: Box::new(ResponseHandler::Static(StaticResponse {
+                    status: 503,
+                    ..Default::default()
+                })),
+                after: Box::new(ResponseHandler::Static(StaticResponse {
+                    status: 200,
+                    ..Default::default()
+                })),
+                count: Counter::default(),
+            };
+            // clones share the count, as the router clones the handler for each route
+            let cloned = handler.clone();
+
+            let mut statuses = vec![];
+            for handler in [&handler, &cloned, &handler, &cloned] {
+                statuses.push(handler.respond(&new_context("default", "/")).await.status());
+            }
+
+            assert_eq!(
+                vec![
+                    StatusCode::SERVICE_UNAVAILABLE,
+                    StatusCode::SERVICE_UNAVAILABLE,
+                    StatusCode::OK,
+                    StatusCode::OK,
+                ],
+                statuses
+            );
+        }
+    }
+
+    mod grpc_web {
+        use super::*;
+        use pretty_assertions::assert_eq;
+
+        #[tokio::test]
+        async fn respond() {
+            let handler = ResponseHandler::GrpcWeb {
+                message: vec![0x08, 0x96, 0x01],
+                grpc_status: 5,
+                grpc_message: Some("not found".to_string()),
+            };
+            let response = handler.respond(&new_context("default", "/")).await;
+
+            assert_eq!(
+                (StatusCode::OK, Some("application/grpc-web+proto")),
+                (
+                    response.status(),
+                    response
+                        .headers()
+                        .get(header::CONTENT_TYPE)
+                        .and_then(|v| v.to_str().ok())
+                )
+            );
+            let trailers = b"grpc-status:5\r\ngrpc-message:not found\r\n";
+            let mut expected = vec![0x00, 0, 0, 0, 3, 0x08, 0x96, 0x01];
+            expected.extend([0x80, 0, 0, 0, trailers.len() as u8]);
+            expected.extend(trailers);
+            assert_eq!(
+                expected,
+                to_bytes(response.into_body(), usize::MAX)
+                    .await
+                    .unwrap()
+                    .to_vec()
+            );
+        }
+    }
+
+    mod random_bytes {
+        use super::*;
+        use pretty_assertions::assert_eq;
+        use rstest::rstest;
+
+        fn handler(size: usize, fill: Fill, seed: Option<u64>) -> ResponseHandler {
+            ResponseHandler::RandomBytes {
+                size,
+                fill,
+                content_type: "application/octet-stream".to_string(),
+                seed,
+            }
+        }
+
+        async fn respond(handler: &ResponseHandler) -> (Option<String>, Bytes) {
+            let response = handler.respond(&new_context("default", "/")).await;
+            (
+                response
+                    .headers()
+                    .get(header::CONTENT_TYPE)
+                    .map(|v| v.to_str().unwrap().to_string()),
+                to_bytes(response.into_body(), usize::MAX).await.unwrap(),
+            )
+        }
+
+        #[rstest]
+        #[case(Fill::Zero, 0)]
+        #[case(Fill::Zero, 1024)]
+        #[case(Fill::Random, 1024)]
+        #[tokio::test]
+        async fn length_equals_size(#[case] fill: Fill, #[case] size: usize) {
+            let (content_type, body) = respond(&handler(size, fill, None)).await;
+
+            assert_eq!(
+                (Some("application/octet-stream".to_string()), size),
+                (content_type, body.len())
+            );
+        }
+
+        #[tokio::test]
+        async fn zero_fill() {
+            let (_, body) = respond(&handler(16, Fill::Zero, None)).await;
+
+            assert_eq!(vec![0; 16], body.to_vec());
+        }
+
+        #[tokio::test]
+        async fn seeded_random_fill_is_reproducible() {
+            let (_, first) = respond(&handler(64, Fill::Random, Some(42))).await;
+            let (_, second) = respond(&handler(64, Fill::Random, Some(42))).await;
+            let (_, other) = respond(&handler(64, Fill::Random, Some(43))).await;
+
+            assert_eq!(first, second);
+            assert_ne!(first, other);
+        }
+    }
+
+    mod retry_until_success {
+        use super::*;
+        use axum::http::Request;
+        use pretty_assertions::assert_eq;
+
+        fn context(session: &str, key: &str) -> RequestContext {
+            let (parts, _) = Request::get("/")
+                .header("idempotency-key", key)
+                .body(())
+                .unwrap()
+                .into_parts();
+            RequestContext {
+                session: session.to_string(),
+                parts,
+                path_params: IndexMap::new(),
+                body: Bytes::new(),
+            }
+        }
+
+        #[tokio::test]
+        async fn succeeds_at_nth_attempt_per_key() {
+            let handler = ResponseHandler::RetryUntilSuccess {
+                header: "idempotency-key".to_string(),
+                success_after: 3,
+                pending_status: 409,
+                response: Box::new(ResponseHandler::Static(StaticResponse {
+                    status: 200,
+                    ..Default::default()
+                })),
+                attempts: KeyedCounter::default(),
+            };
+
+            let mut statuses = vec![];
+            for (session, key) in [
+                ("s1", "k1"),
+                ("s1", "k1"),
+                ("s1", "k2"),
+                ("s2", "k1"),
+                ("s1", "k1"),
+                ("s1", "k1"),
+            ] {
+                let response = handler.respond(&context(session, key)).await;
+                statuses.push(response.status().as_u16());
+            }
+
+            assert_eq!(vec![409, 409, 409, 409, 200, 200], statuses);
+        }
+    }
+
+    mod merge_patch {
+        use super::*;
+        use axum::http::Request;
+        use pretty_assertions::assert_eq;
+        use serde_json::json;
+
+        async fn send(handler: &ResponseHandler, method: &str, body: Value) -> Value {
+            let (parts, _) = Request::builder()
+                .method(method)
+                .uri("/users/1")
+                .body(())
+                .unwrap()
+                .into_parts();
+            let ctx = RequestContext {
+                session: "merge_patch".to_string(),
+                parts,
+                path_params: IndexMap::new(),
+                body: Bytes::from(body.to_string()),
+            };
+            let response = handler.respond(&ctx).await;
+            serde_json::from_slice(&to_bytes(response.into_body(), usize::MAX).await.unwrap())
+                .unwrap()
+        }
+
+        #[tokio::test]
+        async fn patches_are_merged() {
+            let handler = ResponseHandler::MergePatch {
+                initial: json!({ "name": "alice", "tags": ["a"] }),
+                documents: SessionStore::default(),
+            };
+
+            send(&handler, "PATCH", json!({ "age": 20, "tags": null })).await;
+            send(
+                &handler,
+                "PATCH",
+                json!({ "name": "bob", "address": { "city": "tokyo" } }),
+            )
+            .await;
+            let document = send(&handler, "GET", Value::Null).await;
+
+            assert_eq!(
+                json!({ "name": "bob", "age": 20, "address": { "city": "tokyo" } }),
+                document
+            );
+        }
+
+        #[tokio::test]
+        async fn removed_session_starts_over() {
+            let stores = SessionStores::default();
+            let mut handler = ResponseHandler::MergePatch {
+                initial: json!({ "name": "alice" }),
+                documents: SessionStore::default(),
+            };
+            stores.link(&mut handler);
+
+            send(&handler, "PATCH", json!({ "name": "bob" })).await;
+            stores.remove_session("merge_patch");
+
+            assert_eq!(
+                json!({ "name": "alice" }),
+                send(&handler, "GET", Value::Null).await
+            );
+        }
+
+        #[test]
+        fn merge_patch_follows_rfc7386() {
+            let mut target = json!({ "a": "b", "c": { "d": "e", "f": "g" } });
+
+            merge_patch(&mut target, &json!({ "a": "z", "c": { "f": null } }));
+
+            assert_eq!(json!({ "a": "z", "c": { "d": "e" } }), target);
+        }
+    }
+
+    mod time_windows {
+        use super::*;
+        use chrono::TimeZone;
+        use pretty_assertions::assert_eq;
+        use rstest::rstest;
+
+        fn status(status: u16) -> ResponseHandler {
+            ResponseHandler::Static(StaticResponse {
+                status,
+                ..Default::default()
+            })
+        }
+
+        #[rstest]
+        #[case(1, 59, 200)]
+        #[case(2, 0, 503)]
+        #[case(3, 59, 503)]
+        #[case(4, 0, 200)]
+        #[case(23, 30, 418)]
+        #[case(0, 30, 418)]
+        #[tokio::test]
+        async fn responds_by_window(#[case] hour: u32, #[case] minute: u32, #[case] expected: u16) {
+            let window = |start: &str, end: &str| TimeWindow {
+                start: start.parse().unwrap(),
+                end: end.parse().unwrap(),
+            };
+            let handler = ResponseHandler::TimeWindows {
+                windows: vec![
+                    (window("02:00:00", "04:00:00"), status(503)),
+                    (window("23:00:00", "01:00:00"), status(418)),
+                ],
+                default: Box::new(status(200)),
+                clock: Clock::Fixed(Utc.with_ymd_and_hms(2024, 1, 2, hour, minute, 0).unwrap()),
+            };
+
+            let response = handler.respond(&new_context("default", "/")).await;
+
+            assert_eq!(expected, response.status().as_u16());
+        }
+    }
+
+    mod per_session {
+        use super::*;
+        use indexmap::indexmap;
+        use pretty_assertions::assert_eq;
+        use rstest::rstest;
+
+        fn static_response(body: &str) -> ResponseHandler {
+            ResponseHandler::Static(StaticResponse {
+                status: 200,
+                body: body.to_string().into(),
+                ..Default::default()
+            })
+        }
+
+        #[rstest]
+        #[case("alice", "for alice")]
+        #[case("bob", "for bob")]
+        #[case("carol", "default")]
+        #[tokio::test]
+        async fn responds_by_session(#[case] session: &str, #[case] expected: &str) {
+            let handler = ResponseHandler::PerSession {
+                overrides: indexmap! {
+                    "alice".to_string() => static_response("for alice"),
+                    "bob".to_string() => static_response("for bob"),
+                },
+                default: Box::new(static_response("default")),
+            };
+
+            let response = handler.respond(&new_context(session, "/")).await;
+
+            assert_eq!(
+                expected.as_bytes(),
+                to_bytes(response.into_body(), usize::MAX).await.unwrap()
+            );
+        }
+    }
+
+    mod idempotent {
+        use super::*;
+        use pretty_assertions::assert_eq;
+
+        async fn post(handler: &ResponseHandler, session: &str, key: Option<&str>) -> Bytes {
+            let mut ctx = new_context(session, "/payments");
+            ctx.parts.method = axum::http::Method::POST;
+            if let Some(key) = key {
+                ctx.parts
+                    .headers
+                    .insert("idempotency-key", key.parse().unwrap());
+            }
+            let response = handler.respond(&ctx).await;
+            to_bytes(response.into_body(), usize::MAX).await.unwrap()
+        }
+
+        #[tokio::test]
+        async fn replays_response_for_same_key() {
+            // random bodies stand for generated ids, which differ on every run of the response
+            let handler = ResponseHandler::Idempotent {
+                header: "idempotency-key".to_string(),
+                ttl_ms: 60_000,
+                response: Box::new(ResponseHandler::RandomBytes {
+                    size: 16,
+                    fill: Fill::Random,
+                    content_type: "application/octet-stream".to_string(),
+                    seed: None,
+                }),
+                cache: ResponseCache::default(),
+            };
+
+            let first = post(&handler, "alice", Some("key-1")).await;
+
+            assert_eq!(first, post(&handler, "alice", Some("key-1")).await);
+            assert_ne!(first, post(&handler, "alice", Some("key-2")).await);
+            assert_ne!(first, post(&handler, "bob", Some("key-1")).await);
+            assert_ne!(
+                post(&handler, "alice", None).await,
+                post(&handler, "alice", None).await
+            );
+        }
+
+        #[tokio::test]
+        async fn oldest_key_is_evicted_beyond_capacity() {
+            let cache = ResponseCache::with_capacity(2);
+            let handler = ResponseHandler::Idempotent {
+                header: "idempotency-key".to_string(),
+                ttl_ms: 60_000,
+                response: Box::new(ResponseHandler::RandomBytes {
+                    size: 16,
+                    fill: Fill::Random,
+                    content_type: "application/octet-stream".to_string(),
+                    seed: None,
+                }),
+                cache: cache.clone(),
+            };
+
+            let first = post(&handler, "alice", Some("key-1")).await;
+            let second = post(&handler, "alice", Some("key-2")).await;
+            post(&handler, "alice", Some("key-3")).await;
+
+            assert_eq!(2, cache.entries.lock().unwrap().len());
+            assert_eq!(second, post(&handler, "alice", Some("key-2")).await);
+            assert_ne!(first, post(&handler, "alice", Some("key-1")).await);
+        }
+    }
+
+    mod error {
+        use super::*;
+        use pretty_assertions::assert_eq;
+
+        #[tokio::test]
+        async fn responds_with_error_envelope() {
+            let handler = ResponseHandler::Error {
+                status: 422,
+                message: "name is required".to_string(),
+            };
+
+            let response = handler.respond(&new_context("default", "/")).await;
+
+            assert_eq!(
+                (
+                    StatusCode::UNPROCESSABLE_ENTITY,
+                    serde_json::json!({ "serverify_error": { "message": "name is required" } })
+                ),
+                (
+                    response.status(),
+                    serde_json::from_slice(
+                        &to_bytes(response.into_body(), usize::MAX).await.unwrap()
+                    )
+                    .unwrap()
+                )
+            );
+        }
+    }
+
+    mod by_body_size {
+        use super::*;
+        use pretty_assertions::assert_eq;
+        use rstest::rstest;
+
+        #[rstest]
+        #[case(0, "small")]
+        #[case(1024, "small")]
+        #[case(1025, "large")]
+        #[case(1024 * 1024, "large")]
+        #[case(1024 * 1024 + 1, "too large")]
+        #[tokio::test]
+        async fn responds_by_body_size(#[case] size: usize, #[case] expected: &str) {
+            let handler = ResponseHandler::ByBodySize {
+                sizes: vec![
+                    (
+                        BodySize {
+                            min: None,
+                            max: Some(1024),
+                        },
+                        ResponseHandler::Static(StaticResponse {
+                            status: 200,
+                            body: "small".into(),
+                            ..Default::default()
+                        }),
+                    ),
+                    (
+                        BodySize {
+                            min: Some(1025),
+                            max: Some(1024 * 1024),
+                        },
+                        ResponseHandler::Static(StaticResponse {
+                            status: 200,
+                            body: "large".into(),
+                            ..Default::default()
+                        }),
+                    ),
+                ],
+                default: Box::new(ResponseHandler::Static(StaticResponse {
+                    status: 413,
+                    body: "too large".into(),
+                    ..Default::default()
+                })),
+            };
+            let mut ctx = new_context("default", "/uploads");
+            ctx.body = Bytes::from(vec![b'a'; size]);
+
+            let response = handler.respond(&ctx).await;
+
+            assert_eq!(
+                expected.as_bytes(),
+                to_bytes(response.into_body(), usize::MAX).await.unwrap()
+            );
+        }
+    }
+
+    mod by_cookie {
+        use super::*;
+        use indexmap::indexmap;
+        use pretty_assertions::assert_eq;
+        use rstest::rstest;
+
+        #[rstest]
+        #[case(Some("plan=pro"), "pro dashboard")]
+        #[case(Some("theme=dark; plan=\"pro\""), "pro dashboard")]
+        #[case(Some("plan=free"), "free dashboard")]
+        #[case(Some("myplan=pro"), "free dashboard")]
+        #[case(None, "free dashboard")]
+        #[tokio::test]
+        async fn responds_by_cookie(#[case] cookie: Option<&str>, #[case] expected: &str) {
+            let handler = ResponseHandler::ByCookie {
+                cookie: "plan".to_string(),
+                responses: indexmap! {
+                    "pro".to_string() => ResponseHandler::Static(StaticResponse {
+                        status: 200,
+                        body: "pro dashboard".into(),
+                        ..Default::default()
+                    }),
+                },
+                default: Box::new(ResponseHandler::Static(StaticResponse {
+                    status: 200,
+                    body: "free dashboard".into(),
+                    ..Default::default()
+                })),
+            };
+            let mut ctx = new_context("default", "/");
+            if let Some(cookie) = cookie {
+                ctx.parts
+                    .headers
+                    .insert(header::COOKIE, cookie.parse().unwrap());
+            }
+
+            let response = handler.respond(&ctx).await;
+
+            assert_eq!(
+                expected.as_bytes(),
+                to_bytes(response.into_body(), usize::MAX).await.unwrap()
+            );
+        }
+
+        // branches take any response, so a delayed static slows down only the requests it matches
+        #[rstest]
+        #[case("plan=free", true)]
+        #[case("plan=pro", false)]
+        #[tokio::test]
+        async fn delays_only_matching_branch(#[case] cookie: &str, #[case] delayed: bool) {
+            let handler = ResponseHandler::ByCookie {
+                cookie: "plan".to_string(),
+                responses: indexmap! {
+                    "free".to_string() => ResponseHandler::Static(StaticResponse {
+                        status: 200,
+                        delay_ms: Some(200),
+                        ..Default::default()
+                    }),
+                },
+                default: Box::new(ResponseHandler::Static(StaticResponse {
+                    status: 200,
+                    ..Default::default()
+                })),
+            };
+            let mut ctx = new_context("default", "/");
+            ctx.parts
+                .headers
+                .insert(header::COOKIE, cookie.parse().unwrap());
+
+            let started = std::time::Instant::now();
+            handler.respond(&ctx).await;
+
+            assert_eq!(delayed, started.elapsed() >= Duration::from_millis(200));
+        }
+    }
+
+    mod partial_content {
+        use super::*;
+        use axum::http::Request;
+        use pretty_assertions::assert_eq;
+        use rstest::rstest;
+
+        #[rstest]
+        #[case(None, 200, None, "0123456789")]
+        #[case(Some("bytes=2-5"), 206, Some("bytes 2-5/10"), "2345")]
+        #[case(Some("bytes=7-"), 206, Some("bytes 7-9/10"), "789")]
+        #[case(Some("bytes=-3"), 206, Some("bytes 7-9/10"), "789")]
+        #[case(Some("bytes=8-20"), 206, Some("bytes 8-9/10"), "89")]
+        #[case(Some("bytes=10-12"), 416, Some("bytes */10"), "")]
+        #[case(Some("bytes=0-1,4-5"), 200, None, "0123456789")]
+        #[case(Some("items=0-1"), 200, None, "0123456789")]
+        #[tokio::test]
+        async fn respond(
+            #[case] range: Option<&str>,
+            #[case] expected_status: u16,
+            #[case] expected_content_range: Option<&str>,
+            #[case] expected_body: &str,
+        ) {
+            let handler = ResponseHandler::PartialContent {
+                body: "0123456789".to_string(),
+                content_type: Some("text/plain".to_string()),
+            };
+            let request = match range {
+                Some(range) => Request::get("/").header(header::RANGE, range),
+                None => Request::get("/"),
+            };
+            let (parts, _) = request.body(()).unwrap().into_parts();
+            let ctx = RequestContext {
+                session: "default".to_string(),
+                parts,
+                path_params: IndexMap::new(),
+                body: Bytes::new(),
+            };
+
+            let response = handler.respond(&ctx).await;
+
+            assert_eq!(
+                (
+                    expected_status,
+                    Some("bytes"),
+                    Some("text/plain"),
+                    expected_content_range
+                ),
+                (
+                    response.status().as_u16(),
+                    response
+                        .headers()
+                        .get(header::ACCEPT_RANGES)
+                        .map(|value| value.to_str().unwrap()),
+                    response
+                        .headers()
+                        .get(header::CONTENT_TYPE)
+                        .map(|value| value.to_str().unwrap()),
+                    response
+                        .headers()
+                        .get(header::CONTENT_RANGE)
+                        .map(|value| value.to_str().unwrap()),
+                )
+            );
+            assert_eq!(
+                expected_body.as_bytes(),
+                to_bytes(response.into_body(), usize::MAX).await.unwrap()
+            );
+        }
+
+        #[tokio::test]
+        async fn invalid_content_type_falls_back_to_500() {
+            let handler = ResponseHandler::PartialContent {
+                body: "0123456789".to_string(),
+                content_type: Some("text/plain\n".to_string()),
+            };
+
+            let response = handler.respond(&new_context("default", "/")).await;
+
+            assert_eq!(StatusCode::INTERNAL_SERVER_ERROR, response.status());
+        }
+    }
+
+    mod once {
+        use super::*;
+        use pretty_assertions::assert_eq;
+        use rstest::rstest;
+
+        #[rstest]
+        #[case(false, vec!["first", "then", "then", "then"])]
+        #[case(true, vec!["first", "then", "first", "then"])]
+        #[tokio::test]
+        async fn responds_with_first_only_once(
+            #[case] per_session: bool,
+            #[case] expected: Vec<&str>,
+        ) {
+            let handler = ResponseHandler::Once {
+                first: Box::new(ResponseHandler::Static(StaticResponse {
+                    status: 201,
+                    body: "first".into(),
+                    ..Default::default()
+                })),
+                then: Box::new(ResponseHandler::Static(StaticResponse {
+                    status: 200,
+                    body: "then".into(),
+                    ..Default::default()
+                })),
+                per_session,
+                served: Default::default(),
+            };
+
+            let mut bodies = vec![];
+            for session in ["alice", "alice", "bob", "bob"] {
+                let response = handler.respond(&new_context(session, "/")).await;
+                bodies.push(
+                    String::from_utf8(
+                        to_bytes(response.into_body(), usize::MAX)
+                            .await
+                            .unwrap()
+                            .to_vec(),
+                    )
+                    .unwrap(),
+                );
+            }
+
+            assert_eq!(expected, bodies);
+        }
+    }
+
+    mod latency {
+        use super::*;
+        use pretty_assertions::assert_eq;
+
+        #[test]
+        fn samples_fall_within_percentiles() {
+            let latency = LatencyDistribution {
+                p50: 100,
+                p95: 300,
+                p99: 1000,
+            };
+            let mut rng = StdRng::seed_from_u64(42);
+
+            let mut samples = (0..10000)
+                .map(|_| latency.sample(&mut rng))
+                .collect::<Vec<_>>();
+            samples.sort();
+
+            assert!(samples.iter().all(|sample| *sample <= 1000));
+            // the empirical percentiles are close to the configured ones
+            assert_eq!(
+                (true, true, true),
+                (
+                    (90..=110).contains(&samples[5000]),
+                    (280..=320).contains(&samples[9500]),
+                    (900..=1000).contains(&samples[9900]),
+                )
+            );
+        }
+
+        #[test]
+        fn constant_distribution() {
+            let latency = LatencyDistribution {
+                p50: 0,
+                p95: 0,
+                p99: 0,
+            };
+            let mut rng = StdRng::seed_from_u64(42);
+
+            assert!((0..100).all(|_| latency.sample(&mut rng) == 0));
+        }
+    }
+
+    mod last_modified {
+        use super::*;
+        use chrono::TimeZone;
+        use pretty_assertions::assert_eq;
+        use rstest::rstest;
+
+        fn handler() -> ResponseHandler {
+            ResponseHandler::Static(StaticResponse {
+                status: 200,
+                body: "fresh".into(),
+                last_modified: Some(Utc.with_ymd_and_hms(2024, 1, 2, 3, 4, 5).unwrap()),
+                ..Default::default()
+            })
+        }
+
+        #[rstest]
+        #[case("without condition", None, StatusCode::OK, "fresh")]
+        #[case(
+            "modified since",
+            Some("Tue, 02 Jan 2024 03:04:04 GMT"),
+            StatusCode::OK,
+            "fresh"
+        )]
+        #[case(
+            "not modified since (IMF-fixdate)",
+            Some("Tue, 02 Jan 2024 03:04:05 GMT"),
+            StatusCode::NOT_MODIFIED,
+            ""
+        )]
+        #[case(
+            "not modified since (RFC 850)",
+            Some("Wednesday, 03-Jan-24 00:00:00 GMT"),
+            StatusCode::NOT_MODIFIED,
+            ""
+        )]
+        #[case(
+            "not modified since (asctime)",
+            Some("Tue Jan  2 03:04:05 2024"),
+            StatusCode::NOT_MODIFIED,
+            ""
+        )]
+        #[case("invalid date", Some("yesterday"), StatusCode::OK, "fresh")]
+        #[tokio::test]
+        async fn respond(
+            #[case] title: &str,
+            #[case] if_modified_since: Option<&str>,
+            #[case] expected_status: StatusCode,
+            #[case] expected_body: &str,
+        ) {
+            let mut ctx = new_context("default", "/");
+            if let Some(value) = if_modified_since {
+                ctx.parts
+                    .headers
+                    .insert(header::IF_MODIFIED_SINCE, value.parse().unwrap());
+            }
+            let response = handler().respond(&ctx).await;
+
+            assert_eq!(
+                (
+                    expected_status,
+                    Some("Tue, 02 Jan 2024 03:04:05 GMT"),
+                    Bytes::from(expected_body.to_string())
+                ),
+                (
+                    response.status(),
+                    response
+                        .headers()
+                        .get(header::LAST_MODIFIED)
+                        .and_then(|v| v.to_str().ok())
+                        .map(str::to_string)
+                        .as_deref(),
+                    to_bytes(response.into_body(), usize::MAX).await.unwrap()
+                ),
+                "{}",
+                title
+            );
+        }
+    }
+
+    #[tokio::test]
+    async fn static_response() {
+        let handler = ResponseHandler::Static(StaticResponse {
+            status: 201,
+            headers: indexmap::indexmap! { "answer".to_string() => "42".to_string() },
+            body: "created".into(),
+            ..Default::default()
+        });
+        let response = handler.respond(&new_context("default", "/")).await;
+
+        assert_eq!(
+            (StatusCode::CREATED, Some("42")),
+            (
+                response.status(),
+                response
+                    .headers()
+                    .get("answer")
+                    .and_then(|v| v.to_str().ok())
+            )
+        );
+        assert_eq!(
+            "created",
+            to_bytes(response.into_body(), usize::MAX).await.unwrap()
+        );
+    }
+}
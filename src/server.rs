@@ -0,0 +1,1400 @@
+#[cfg(unix)]
+use std::path::PathBuf;
+use std::{future::Future, net::SocketAddr, time::Duration};
+
+use axum::{
+    error_handling::HandleErrorLayer,
+    extract::{Request, State},
+    http::{header, HeaderValue, StatusCode},
+    middleware::{self, Next},
+    response::{Html, IntoResponse},
+    routing::get,
+    Json, Router,
+};
+use socket2::{Domain, Protocol, Socket, Type};
+#[cfg(unix)]
+use tokio::net::UnixListener;
+use tokio::{net::TcpListener, task::JoinHandle};
+use tokio_util::sync::CancellationToken;
+use tower::{
+    limit::ConcurrencyLimitLayer, load_shed::LoadShedLayer, BoxError, ServiceBuilder, ServiceExt,
+};
+use tower_http::cors::{AllowHeaders, AllowMethods, Any, CorsLayer};
+
+use crate::{
+    admin_endpoint::route_admin_to,
+    config_endpoint::route_config_to,
+    method_override::override_method,
+    mock_endpoint::{forward_unmatched, route_endpoints_to, route_options_to, MockEndpoint},
+    request_id::assign_request_id,
+    request_logger::LoggerError,
+    response::error_response,
+    session_endpoint::route_session_to,
+    state::AppState,
+};
+
+#[derive(Default, Clone)]
+pub struct ServerOptions {
+    pub cors_allow_all: bool,
+    /// Allows cross-origin requests, echoing the requested headers and method on preflight.
+    pub cors_reflect: bool,
+    /// Seconds for which browsers may cache preflight results, sent in `Access-Control-Max-Age`.
+    pub preflight_max_age: Option<u64>,
+    /// Prefix for every route, such as `/serverify`. Routes are served at the root when empty.
+    pub base_path: String,
+    /// Body of the landing page served at `/`. Nothing is served there when `None`.
+    pub index_body: Option<String>,
+    /// Body of the 404 response for unmatched routes other than mock ones.
+    pub not_found_body: Option<String>,
+    /// Takes the method of POST requests from `X-HTTP-Method-Override` when set.
+    pub method_override: bool,
+    /// Base URL that requests to unmatched mock routes are forwarded to.
+    pub fallback_upstream: Option<String>,
+    /// Leaves out the session API, such as `/session` and `/history`.
+    pub disable_session_api: bool,
+    /// Number of requests handled at once. Further requests wait for a slot.
+    pub max_concurrent_requests: Option<usize>,
+    /// Answers requests beyond `max_concurrent_requests` with 503 instead of queueing them.
+    pub load_shed: bool,
+}
+
+pub fn build_app(
+    mut endpoints: Vec<MockEndpoint>,
+    state: AppState,
+    options: &ServerOptions,
+) -> Router {
+    for endpoint in &mut endpoints {
+        state.session_stores.link(&mut endpoint.response);
+    }
+    let app = Router::new()
+        .route("/health", get(health))
+        .route("/health/ready", get(ready));
+    let app = route_options_to(app, &endpoints);
+    let app = route_config_to(app, &endpoints);
+    let app = route_admin_to(app);
+    let mocks = route_endpoints_to(app, endpoints);
+
+    let app = if options.disable_session_api {
+        mocks
+    } else {
+        route_session_to(mocks)
+    };
+    let app = match &options.index_body {
+        Some(body) => {
+            let body = body.clone();
+            app.route("/", get(move || async move { Html(body) }))
+        }
+        None => app,
+    };
+    let app = if options.not_found_body.is_some() || options.fallback_upstream.is_some() {
+        let body = options.not_found_body.clone();
+        let upstream = options.fallback_upstream.clone();
+        app.fallback(
+            move |State(state): State<AppState>, req: Request| async move {
+                // unmatched mock routes keep the bare 404, as they stand for the mocked server
+                if !req.uri().path().starts_with("/mock/") {
+                    (StatusCode::NOT_FOUND, body.unwrap_or_default()).into_response()
+                } else if let Some(upstream) = upstream {
+                    forward_unmatched(&upstream, state, req).await
+                } else {
+                    StatusCode::NOT_FOUND.into_response()
+                }
+            },
+        )
+    } else {
+        app
+    };
+    let app = app.with_state(state);
+    let base_path = options.base_path.trim_matches('/');
+    let app = if base_path.is_empty() {
+        app
+    } else {
+        Router::new().nest(&format!("/{}", base_path), app)
+    };
+
+    // the method has to be replaced before routing, so the app is wrapped as a fallback
+    let app = if options.method_override {
+        Router::new()
+            .fallback_service(app)
+            .layer(middleware::from_fn(override_method))
+    } else {
+        app
+    };
+
+    let cors = if options.cors_reflect {
+        Some(
+            CorsLayer::new()
+                .allow_origin(Any)
+                .allow_methods(AllowMethods::mirror_request())
+                .allow_headers(AllowHeaders::mirror_request()),
+        )
+    } else if options.cors_allow_all {
+        Some(CorsLayer::permissive())
+    } else {
+        None
+    };
+    let app = match (cors, options.preflight_max_age) {
+        (Some(cors), Some(max_age)) => with_cors(app, cors.max_age(Duration::from_secs(max_age))),
+        (Some(cors), None) => with_cors(app, cors),
+        (None, _) => app,
+    };
+
+    // the app is wrapped as a fallback so that a single limit is shared by every route
+    let app = match options.max_concurrent_requests {
+        Some(limit) if options.load_shed => Router::new().fallback_service(app).layer(
+            ServiceBuilder::new()
+                .layer(HandleErrorLayer::new(|_: BoxError| async {
+                    error_response::<()>(StatusCode::SERVICE_UNAVAILABLE, "too many requests")
+                }))
+                .layer(LoadShedLayer::new())
+                .layer(ConcurrencyLimitLayer::new(limit)),
+        ),
+        Some(limit) => Router::new()
+            .fallback_service(app)
+            .layer(ConcurrencyLimitLayer::new(limit)),
+        None => app,
+    };
+
+    app.layer(middleware::from_fn(assign_request_id))
+}
+
+// preflights are answered by the CORS layer, while plain OPTIONS requests are passed to the
+// auto-generated OPTIONS routes so that they keep their `Allow` header
+fn with_cors(app: Router, cors: CorsLayer) -> Router {
+    let plain = app.clone();
+    app.layer(cors)
+        .layer(middleware::from_fn(move |req: Request, next: Next| {
+            let plain = plain.clone();
+            async move {
+                let is_preflight = req
+                    .headers()
+                    .contains_key(header::ACCESS_CONTROL_REQUEST_METHOD);
+                if req.method() != axum::http::Method::OPTIONS || is_preflight {
+                    return next.run(req).await;
+                }
+
+                let mut res = plain.oneshot(req).await.unwrap_or_else(|err| match err {});
+                res.headers_mut().insert(
+                    header::ACCESS_CONTROL_ALLOW_ORIGIN,
+                    HeaderValue::from_static("*"),
+                );
+                res
+            }
+        }))
+}
+
+pub enum Listener {
+    Tcp(TcpListener),
+    /// Serves HTTP/2 with prior knowledge (h2c) in addition to HTTP/1.1.
+    H2c(TcpListener),
+    #[cfg(unix)]
+    Unix(UnixListener, PathBuf),
+}
+
+/// Options of the listening TCP sockets. The defaults are those of `TcpListener::bind`.
+#[derive(Clone, Copy)]
+pub struct SocketOptions {
+    /// Maximum number of pending connections, 1024 by default.
+    pub backlog: u32,
+    /// Sets `TCP_NODELAY`, which accepted connections inherit on Linux. Off by default.
+    pub nodelay: bool,
+    /// Sets `SO_REUSEADDR`, which lets the port be bound again while old connections linger in
+    /// `TIME_WAIT`. On by default except on Windows, where it allows stealing a bound port.
+    pub reuse_address: bool,
+}
+
+impl Default for SocketOptions {
+    fn default() -> Self {
+        Self {
+            backlog: 1024,
+            nodelay: false,
+            reuse_address: !cfg!(windows),
+        }
+    }
+}
+
+impl Listener {
+    pub fn bind_tcp(port: u16, options: &SocketOptions) -> std::io::Result<Self> {
+        bind_tcp_listener(port, options).map(Listener::Tcp)
+    }
+
+    pub fn bind_h2c(port: u16, options: &SocketOptions) -> std::io::Result<Self> {
+        bind_tcp_listener(port, options).map(Listener::H2c)
+    }
+
+    #[cfg(unix)]
+    pub fn bind_unix(path: impl Into<PathBuf>) -> std::io::Result<Self> {
+        let path = path.into();
+        UnixListener::bind(&path).map(|listener| Listener::Unix(listener, path))
+    }
+}
+
+// the socket is configured before binding, as `SO_REUSEADDR` and the backlog cannot be changed on
+// a bound `TcpListener`
+fn bind_tcp_listener(port: u16, options: &SocketOptions) -> std::io::Result<TcpListener> {
+    let addr = SocketAddr::from(([0, 0, 0, 0], port));
+    let socket = Socket::new(Domain::IPV4, Type::STREAM, Some(Protocol::TCP))?;
+    socket.set_reuse_address(options.reuse_address)?;
+    socket.set_nodelay(options.nodelay)?;
+    socket.set_nonblocking(true)?;
+    socket.bind(&addr.into())?;
+    socket.listen(options.backlog.min(i32::MAX as u32) as i32)?;
+    TcpListener::from_std(socket.into())
+}
+
+pub async fn serve(
+    listener: Listener,
+    app: Router,
+    shutdown: impl Future<Output = ()> + Send + 'static,
+) -> std::io::Result<()> {
+    match listener {
+        Listener::Tcp(listener) => {
+            axum::serve(
+                listener,
+                app.into_make_service_with_connect_info::<SocketAddr>(),
+            )
+            .with_graceful_shutdown(shutdown)
+            .await
+        }
+        Listener::H2c(listener) => serve_h2c(&listener, app, shutdown).await,
+        #[cfg(unix)]
+        Listener::Unix(listener, path) => {
+            let result = serve_unix(&listener, app, shutdown).await;
+            std::fs::remove_file(&path)?;
+            result
+        }
+    }
+}
+
+pub struct ServerHandle {
+    token: CancellationToken,
+    tasks: Vec<JoinHandle<std::io::Result<()>>>,
+}
+
+impl ServerHandle {
+    pub async fn shutdown(self) -> std::io::Result<()> {
+        self.token.cancel();
+        for task in self.tasks {
+            task.await.map_err(std::io::Error::other)??;
+        }
+        Ok(())
+    }
+}
+
+pub fn serve_many(listeners: Vec<Listener>, app: Router) -> ServerHandle {
+    let token = CancellationToken::new();
+    let tasks = listeners
+        .into_iter()
+        .map(|listener| {
+            tokio::spawn(serve(
+                listener,
+                app.clone(),
+                token.clone().cancelled_owned(),
+            ))
+        })
+        .collect();
+
+    ServerHandle { token, tasks }
+}
+
+#[cfg(unix)]
+async fn serve_unix(
+    listener: &UnixListener,
+    app: Router,
+    shutdown: impl Future<Output = ()>,
+) -> std::io::Result<()> {
+    use hyper_util::{
+        rt::{TokioExecutor, TokioIo},
+        server::conn::auto::Builder,
+        service::TowerToHyperService,
+    };
+
+    tokio::pin!(shutdown);
+    loop {
+        let (stream, _) = tokio::select! {
+            accepted = listener.accept() => accepted?,
+            _ = &mut shutdown => return Ok(()),
+        };
+
+        let service = TowerToHyperService::new(app.clone());
+        tokio::spawn(async move {
+            // connection errors only affect the peer, so they are not propagated
+            let _ = Builder::new(TokioExecutor::new())
+                .serve_connection_with_upgrades(TokioIo::new(stream), service)
+                .await;
+        });
+    }
+}
+
+// axum::serve only speaks HTTP/1.1, so connections are served by hyper's auto builder, which also
+// accepts the HTTP/2 connection preface
+async fn serve_h2c(
+    listener: &TcpListener,
+    app: Router,
+    shutdown: impl Future<Output = ()>,
+) -> std::io::Result<()> {
+    use axum::{extract::ConnectInfo, Extension};
+    use hyper_util::{
+        rt::{TokioExecutor, TokioIo},
+        server::conn::auto::Builder,
+        service::TowerToHyperService,
+    };
+
+    tokio::pin!(shutdown);
+    loop {
+        let (stream, addr) = tokio::select! {
+            accepted = listener.accept() => accepted?,
+            _ = &mut shutdown => return Ok(()),
+        };
+
+        let service = TowerToHyperService::new(app.clone().layer(Extension(ConnectInfo(addr))));
+        tokio::spawn(async move {
+            // connection errors only affect the peer, so they are not propagated
+            let _ = Builder::new(TokioExecutor::new())
+                .serve_connection_with_upgrades(TokioIo::new(stream), service)
+                .await;
+        });
+    }
+}
+
+async fn health() -> (StatusCode, Json<serde_json::Value>) {
+    (StatusCode::OK, Json(serde_json::json!({ "status": "ok" })))
+}
+
+// unlike `/health`, which tells that the process is alive, this tells whether requests can be
+// logged
+async fn ready(State(state): State<AppState>) -> (StatusCode, Json<serde_json::Value>) {
+    match state.logger.ping().await {
+        Ok(()) => (StatusCode::OK, Json(serde_json::json!({ "status": "ok" }))),
+        Err(LoggerError::InvalidSession(message) | LoggerError::InternalError(message)) => (
+            StatusCode::SERVICE_UNAVAILABLE,
+            Json(serde_json::json!({ "status": "unavailable", "message": message })),
+        ),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{
+        method::Method,
+        mock_endpoint::testutil::endpoint,
+        request_logger::testutil::new_logger,
+        response_handler::{ResponseHandler, StaticResponse},
+    };
+    use axum::http::{HeaderName, HeaderValue};
+    use axum_test::TestServer;
+    use indexmap::indexmap;
+
+    async fn new_test_server(options: ServerOptions) -> TestServer {
+        let endpoints = vec![endpoint(
+            Method::Get,
+            "/hello",
+            ResponseHandler::Static(StaticResponse {
+                status: 200,
+                headers: indexmap! {},
+                body: "Hello, world!".into(),
+                ..Default::default()
+            }),
+        )];
+        let state = AppState::new(new_logger().await);
+
+        TestServer::new(build_app(endpoints, state, &options)).unwrap()
+    }
+
+    async fn send_get<S>(stream: S, path: &str) -> (StatusCode, String)
+    where
+        S: tokio::io::AsyncRead + tokio::io::AsyncWrite + Unpin + Send + 'static,
+    {
+        use http_body_util::{BodyExt, Empty};
+        use hyper::body::Bytes;
+        use hyper_util::rt::TokioIo;
+
+        let (mut sender, conn) = hyper::client::conn::http1::handshake(TokioIo::new(stream))
+            .await
+            .unwrap();
+        tokio::spawn(conn);
+
+        let request = axum::http::Request::get(path)
+            .header("host", "localhost")
+            .body(Empty::<Bytes>::new())
+            .unwrap();
+        let response = sender.send_request(request).await.unwrap();
+        let status = response.status();
+        let body = response.into_body().collect().await.unwrap().to_bytes();
+        (status, String::from_utf8_lossy(&body).to_string())
+    }
+
+    mod serve_many {
+        use super::*;
+        use pretty_assertions::assert_eq;
+        use tokio::net::TcpStream;
+
+        #[tokio::test]
+        async fn serves_on_every_listener() {
+            let state = AppState::new(new_logger().await);
+            let endpoints = vec![endpoint(
+                Method::Get,
+                "/hello",
+                ResponseHandler::Static(StaticResponse {
+                    status: 200,
+                    headers: indexmap! {},
+                    body: "Hello, world!".into(),
+                    ..Default::default()
+                }),
+            )];
+            let app = build_app(endpoints, state, &ServerOptions::default());
+
+            let mut listeners = vec![];
+            let mut addrs = vec![];
+            for _ in 0..2 {
+                let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+                addrs.push(listener.local_addr().unwrap());
+                listeners.push(Listener::Tcp(listener));
+            }
+            let handle = serve_many(listeners, app);
+
+            for addr in addrs.iter() {
+                let stream = TcpStream::connect(addr).await.unwrap();
+                assert_eq!(
+                    (StatusCode::OK, "Hello, world!".to_string()),
+                    send_get(stream, "/mock/default/hello").await,
+                    "{}",
+                    addr
+                );
+            }
+
+            handle.shutdown().await.unwrap();
+            for addr in addrs.iter() {
+                assert!(TcpStream::connect(addr).await.is_err(), "{}", addr);
+            }
+        }
+
+        #[tokio::test]
+        async fn remote_addr_is_logged() {
+            let logger = new_logger().await;
+            logger.create_session("mysession").await.unwrap();
+            let state = AppState::new(logger);
+            let app = build_app(
+                vec![endpoint(
+                    Method::Get,
+                    "/hello",
+                    ResponseHandler::Static(StaticResponse {
+                        status: 200,
+                        ..Default::default()
+                    }),
+                )],
+                state.clone(),
+                &ServerOptions::default(),
+            );
+
+            let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+            let addr = listener.local_addr().unwrap();
+            let handle = serve_many(vec![Listener::Tcp(listener)], app);
+
+            let stream = TcpStream::connect(addr).await.unwrap();
+            send_get(stream, "/mock/mysession/hello").await;
+            handle.shutdown().await.unwrap();
+
+            let logs = state.logger.get_session_history("mysession").await.unwrap();
+            assert_eq!(Some("127.0.0.1".to_string()), logs[0].remote_addr);
+        }
+
+        #[tokio::test]
+        async fn serves_with_socket_options() {
+            let state = AppState::new(new_logger().await);
+            let endpoints = vec![endpoint(
+                Method::Get,
+                "/hello",
+                ResponseHandler::Static(StaticResponse {
+                    status: 200,
+                    body: "Hello, world!".into(),
+                    ..Default::default()
+                }),
+            )];
+            let app = build_app(endpoints, state, &ServerOptions::default());
+
+            let listener = Listener::bind_tcp(
+                0,
+                &SocketOptions {
+                    backlog: 16,
+                    nodelay: true,
+                    reuse_address: true,
+                },
+            )
+            .unwrap();
+            let Listener::Tcp(tcp_listener) = &listener else {
+                unreachable!()
+            };
+            let socket = socket2::SockRef::from(tcp_listener);
+            assert_eq!(
+                (true, true),
+                (socket.nodelay().unwrap(), socket.reuse_address().unwrap())
+            );
+            let port = tcp_listener.local_addr().unwrap().port();
+            let handle = serve_many(vec![listener], app);
+
+            let stream = TcpStream::connect(("127.0.0.1", port)).await.unwrap();
+            assert_eq!(
+                (StatusCode::OK, "Hello, world!".to_string()),
+                send_get(stream, "/mock/default/hello").await
+            );
+            handle.shutdown().await.unwrap();
+        }
+
+        // names are lowercased by `http::HeaderName` whatever casing is sent on the wire, so the
+        // history is consistent without any option
+        #[tokio::test]
+        async fn header_names_are_logged_in_lowercase() {
+            use tokio::io::{AsyncReadExt, AsyncWriteExt};
+
+            let logger = new_logger().await;
+            logger.create_session("mysession").await.unwrap();
+            let state = AppState::new(logger);
+            let app = build_app(
+                vec![endpoint(
+                    Method::Get,
+                    "/hello",
+                    ResponseHandler::Static(StaticResponse {
+                        status: 200,
+                        ..Default::default()
+                    }),
+                )],
+                state.clone(),
+                &ServerOptions::default(),
+            );
+
+            let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+            let addr = listener.local_addr().unwrap();
+            let handle = serve_many(vec![Listener::Tcp(listener)], app);
+
+            for name in ["X-Custom", "x-custom"] {
+                let mut stream = TcpStream::connect(addr).await.unwrap();
+                let request = format!(
+                    "GET /mock/mysession/hello HTTP/1.1\r\nHost: localhost\r\n{}: {}\r\nConnection: close\r\n\r\n",
+                    name, name
+                );
+                stream.write_all(request.as_bytes()).await.unwrap();
+                stream.read_to_end(&mut vec![]).await.unwrap();
+            }
+            handle.shutdown().await.unwrap();
+
+            let logs = state.logger.get_session_history("mysession").await.unwrap();
+            assert_eq!(
+                vec![Some("X-Custom"), Some("x-custom")],
+                logs.iter()
+                    .map(|log| log.headers.get("x-custom").map(String::as_str))
+                    .collect::<Vec<_>>()
+            );
+            assert!(logs
+                .iter()
+                .flat_map(|log| log.headers.keys())
+                .all(|name| *name == name.to_ascii_lowercase()));
+        }
+    }
+
+    mod serve_h2c {
+        use super::*;
+        use http_body_util::{BodyExt, Empty};
+        use hyper::body::Bytes;
+        use hyper_util::rt::{TokioExecutor, TokioIo};
+        use pretty_assertions::assert_eq;
+        use tokio::net::TcpStream;
+
+        #[tokio::test]
+        async fn serves_http2_with_prior_knowledge() {
+            let logger = new_logger().await;
+            logger.create_session("mysession").await.unwrap();
+            let state = AppState::new(logger);
+            let app = build_app(
+                vec![endpoint(
+                    Method::Get,
+                    "/hello",
+                    ResponseHandler::Static(StaticResponse {
+                        status: 200,
+                        body: "Hello, world!".into(),
+                        ..Default::default()
+                    }),
+                )],
+                state.clone(),
+                &ServerOptions::default(),
+            );
+            let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+            let addr = listener.local_addr().unwrap();
+            let handle = serve_many(vec![Listener::H2c(listener)], app);
+
+            let stream = TcpStream::connect(addr).await.unwrap();
+            let (mut sender, conn) =
+                hyper::client::conn::http2::handshake(TokioExecutor::new(), TokioIo::new(stream))
+                    .await
+                    .unwrap();
+            tokio::spawn(conn);
+            let request = axum::http::Request::get(format!("http://{}/mock/mysession/hello", addr))
+                .body(Empty::<Bytes>::new())
+                .unwrap();
+            let response = sender.send_request(request).await.unwrap();
+            let version = response.version();
+            let status = response.status();
+            let body = response.into_body().collect().await.unwrap().to_bytes();
+
+            assert_eq!(
+                (
+                    axum::http::Version::HTTP_2,
+                    StatusCode::OK,
+                    "Hello, world!".as_bytes()
+                ),
+                (version, status, body.as_ref())
+            );
+            let logs = state.logger.get_session_history("mysession").await.unwrap();
+            assert_eq!(Some("127.0.0.1".to_string()), logs[0].remote_addr);
+
+            handle.shutdown().await.unwrap();
+        }
+    }
+
+    mod request_id {
+        use super::*;
+        use pretty_assertions::assert_eq;
+
+        async fn new_test_server_with_session() -> (TestServer, AppState) {
+            let endpoints = vec![endpoint(
+                Method::Get,
+                "/hello",
+                ResponseHandler::Static(StaticResponse {
+                    status: 200,
+                    body: "Hello, world!".into(),
+                    ..Default::default()
+                }),
+            )];
+            let logger = new_logger().await;
+            logger.create_session("mysession").await.unwrap();
+            let state = AppState::new(logger);
+            let server = TestServer::new(build_app(
+                endpoints,
+                state.clone(),
+                &ServerOptions::default(),
+            ))
+            .unwrap();
+            (server, state)
+        }
+
+        #[tokio::test]
+        async fn generated_id_is_echoed_and_logged() {
+            let (server, state) = new_test_server_with_session().await;
+
+            let response = server.get("/mock/mysession/hello").await;
+            let request_id = response
+                .header("x-request-id")
+                .to_str()
+                .unwrap()
+                .to_string();
+            assert!(uuid::Uuid::parse_str(&request_id).is_ok(), "{}", request_id);
+
+            let logs = state.logger.get_session_history("mysession").await.unwrap();
+            assert_eq!(Some(request_id), logs[0].request_id);
+        }
+
+        #[tokio::test]
+        async fn incoming_id_is_preserved() {
+            let (server, state) = new_test_server_with_session().await;
+
+            let response = server
+                .get("/mock/mysession/hello")
+                .add_header(
+                    HeaderName::from_static("x-request-id"),
+                    HeaderValue::from_static("client-id-1"),
+                )
+                .await;
+            assert_eq!("client-id-1", response.header("x-request-id"));
+
+            let logs = state.logger.get_session_history("mysession").await.unwrap();
+            assert_eq!(Some("client-id-1".to_string()), logs[0].request_id);
+        }
+
+        #[tokio::test]
+        async fn admin_routes_get_an_id_too() {
+            let (server, _) = new_test_server_with_session().await;
+
+            let response = server.get("/health").await;
+            assert!(response.maybe_header("x-request-id").is_some());
+        }
+    }
+
+    #[cfg(unix)]
+    mod serve_unix {
+        use super::*;
+        use pretty_assertions::assert_eq;
+        use tokio::{net::UnixStream, sync::oneshot};
+
+        #[tokio::test]
+        async fn serves_over_unix_socket_and_removes_it_on_shutdown() {
+            let path = std::env::temp_dir().join(format!("serverify-{}.sock", std::process::id()));
+            let _ = std::fs::remove_file(&path);
+
+            let state = AppState::new(new_logger().await);
+            let app = build_app(vec![], state, &ServerOptions::default());
+            let listener = Listener::bind_unix(&path).unwrap();
+            let (tx, rx) = oneshot::channel::<()>();
+            let handle = tokio::spawn(serve(listener, app, async move {
+                let _ = rx.await;
+            }));
+
+            let stream = UnixStream::connect(&path).await.unwrap();
+            let (status, body) = send_get(stream, "/health").await;
+
+            assert_eq!(
+                (StatusCode::OK, serde_json::json!({ "status": "ok" })),
+                (status, serde_json::from_str(&body).unwrap())
+            );
+
+            tx.send(()).unwrap();
+            handle.await.unwrap().unwrap();
+            assert!(!path.exists());
+        }
+    }
+
+    mod cors_allow_all {
+        use super::*;
+        use pretty_assertions::assert_eq;
+
+        #[tokio::test]
+        async fn preflight_is_allowed_when_enabled() {
+            let server = new_test_server(ServerOptions {
+                cors_allow_all: true,
+                ..Default::default()
+            })
+            .await;
+
+            let response = server
+                .method(axum::http::Method::OPTIONS, "/mock/default/hello")
+                .add_header(
+                    HeaderName::from_static("origin"),
+                    HeaderValue::from_static("http://example.com"),
+                )
+                .add_header(
+                    HeaderName::from_static("access-control-request-method"),
+                    HeaderValue::from_static("GET"),
+                )
+                .add_header(
+                    HeaderName::from_static("access-control-request-headers"),
+                    HeaderValue::from_static("x-token"),
+                )
+                .await;
+
+            assert_eq!(StatusCode::OK, response.status_code());
+            assert_eq!(
+                ("*", "*", "*"),
+                (
+                    response
+                        .header("access-control-allow-origin")
+                        .to_str()
+                        .unwrap(),
+                    response
+                        .header("access-control-allow-methods")
+                        .to_str()
+                        .unwrap(),
+                    response
+                        .header("access-control-allow-headers")
+                        .to_str()
+                        .unwrap(),
+                )
+            );
+        }
+
+        #[tokio::test]
+        async fn auto_options_has_cors_headers_when_enabled() {
+            let server = new_test_server(ServerOptions {
+                cors_allow_all: true,
+                ..Default::default()
+            })
+            .await;
+
+            let response = server
+                .method(axum::http::Method::OPTIONS, "/mock/default/hello")
+                .add_header(
+                    HeaderName::from_static("origin"),
+                    HeaderValue::from_static("http://example.com"),
+                )
+                .await;
+
+            assert_eq!(
+                (StatusCode::NO_CONTENT, "GET", "*"),
+                (
+                    response.status_code(),
+                    response.header("allow").to_str().unwrap(),
+                    response
+                        .header("access-control-allow-origin")
+                        .to_str()
+                        .unwrap(),
+                )
+            );
+        }
+
+        #[tokio::test]
+        async fn cors_headers_are_absent_when_disabled() {
+            let server = new_test_server(ServerOptions::default()).await;
+
+            let response = server
+                .get("/mock/default/hello")
+                .add_header(
+                    HeaderName::from_static("origin"),
+                    HeaderValue::from_static("http://example.com"),
+                )
+                .await;
+
+            assert_eq!(StatusCode::OK, response.status_code());
+            assert_eq!(None, response.maybe_header("access-control-allow-origin"));
+        }
+    }
+
+    mod cors_reflect {
+        use super::*;
+        use pretty_assertions::assert_eq;
+
+        #[tokio::test]
+        async fn preflight_reflects_requested_headers_and_method() {
+            let server = new_test_server(ServerOptions {
+                cors_reflect: true,
+                ..Default::default()
+            })
+            .await;
+
+            let response = server
+                .method(axum::http::Method::OPTIONS, "/mock/default/hello")
+                .add_header(
+                    HeaderName::from_static("origin"),
+                    HeaderValue::from_static("http://example.com"),
+                )
+                .add_header(
+                    HeaderName::from_static("access-control-request-method"),
+                    HeaderValue::from_static("PUT"),
+                )
+                .add_header(
+                    HeaderName::from_static("access-control-request-headers"),
+                    HeaderValue::from_static("x-token,x-trace-id"),
+                )
+                .await;
+
+            assert_eq!(StatusCode::OK, response.status_code());
+            assert_eq!(
+                ("*", "PUT", "x-token,x-trace-id"),
+                (
+                    response
+                        .header("access-control-allow-origin")
+                        .to_str()
+                        .unwrap(),
+                    response
+                        .header("access-control-allow-methods")
+                        .to_str()
+                        .unwrap(),
+                    response
+                        .header("access-control-allow-headers")
+                        .to_str()
+                        .unwrap(),
+                )
+            );
+        }
+    }
+
+    mod preflight_max_age {
+        use super::*;
+        use pretty_assertions::assert_eq;
+        use rstest::rstest;
+
+        #[rstest]
+        #[case(ServerOptions { cors_allow_all: true, ..Default::default() })]
+        #[case(ServerOptions { cors_reflect: true, ..Default::default() })]
+        #[tokio::test]
+        async fn preflight_has_max_age(#[case] options: ServerOptions) {
+            let server = new_test_server(ServerOptions {
+                preflight_max_age: Some(600),
+                ..options
+            })
+            .await;
+
+            let response = server
+                .method(axum::http::Method::OPTIONS, "/mock/default/hello")
+                .add_header(
+                    HeaderName::from_static("origin"),
+                    HeaderValue::from_static("http://example.com"),
+                )
+                .add_header(
+                    HeaderName::from_static("access-control-request-method"),
+                    HeaderValue::from_static("GET"),
+                )
+                .await;
+
+            assert_eq!(
+                (StatusCode::OK, "600"),
+                (
+                    response.status_code(),
+                    response.header("access-control-max-age").to_str().unwrap()
+                )
+            );
+        }
+
+        #[tokio::test]
+        async fn preflight_has_no_max_age_by_default() {
+            let server = new_test_server(ServerOptions {
+                cors_allow_all: true,
+                ..Default::default()
+            })
+            .await;
+
+            let response = server
+                .method(axum::http::Method::OPTIONS, "/mock/default/hello")
+                .add_header(
+                    HeaderName::from_static("origin"),
+                    HeaderValue::from_static("http://example.com"),
+                )
+                .add_header(
+                    HeaderName::from_static("access-control-request-method"),
+                    HeaderValue::from_static("GET"),
+                )
+                .await;
+
+            assert_eq!(None, response.maybe_header("access-control-max-age"));
+        }
+    }
+
+    mod index_and_not_found {
+        use super::*;
+        use pretty_assertions::assert_eq;
+
+        #[tokio::test]
+        async fn index_body_is_served_at_root() {
+            let server = new_test_server(ServerOptions {
+                index_body: Some("<h1>serverify</h1>".to_string()),
+                ..Default::default()
+            })
+            .await;
+
+            let response = server.get("/").await;
+
+            assert_eq!(
+                (
+                    StatusCode::OK,
+                    "text/html; charset=utf-8",
+                    "<h1>serverify</h1>"
+                ),
+                (
+                    response.status_code(),
+                    response.header("content-type").to_str().unwrap(),
+                    response.text().as_str()
+                )
+            );
+        }
+
+        #[tokio::test]
+        async fn root_is_not_found_without_index_body() {
+            let server = new_test_server(ServerOptions::default()).await;
+
+            let response = server.get("/").await;
+
+            assert_eq!(
+                (StatusCode::NOT_FOUND, ""),
+                (response.status_code(), response.text().as_str())
+            );
+        }
+
+        #[tokio::test]
+        async fn not_found_body_is_served_for_unmatched_route() {
+            let server = new_test_server(ServerOptions {
+                not_found_body: Some("no such route".to_string()),
+                ..Default::default()
+            })
+            .await;
+
+            let admin = server.get("/unknown").await;
+            let mock = server.get("/mock/default/unknown").await;
+
+            assert_eq!(
+                (
+                    (StatusCode::NOT_FOUND, "no such route"),
+                    (StatusCode::NOT_FOUND, "")
+                ),
+                (
+                    (admin.status_code(), admin.text().as_str()),
+                    (mock.status_code(), mock.text().as_str())
+                )
+            );
+        }
+    }
+
+    mod method_override {
+        use super::*;
+        use pretty_assertions::assert_eq;
+        use rstest::rstest;
+
+        #[rstest]
+        #[case(true, "DELETE", StatusCode::OK, "deleted")]
+        #[case(true, "delete", StatusCode::OK, "deleted")]
+        #[case(false, "DELETE", StatusCode::METHOD_NOT_ALLOWED, "")]
+        #[case(
+            true,
+            "TRACE",
+            StatusCode::BAD_REQUEST,
+            r#"{"serverify_error":{"message":"unsupported method override \"TRACE\""}}"#
+        )]
+        #[tokio::test]
+        async fn post_is_overridden(
+            #[case] method_override: bool,
+            #[case] header: &'static str,
+            #[case] expected_status: StatusCode,
+            #[case] expected_body: &str,
+        ) {
+            let endpoints = vec![endpoint(
+                Method::Delete,
+                "/users",
+                ResponseHandler::Static(StaticResponse {
+                    status: 200,
+                    body: "deleted".into(),
+                    ..Default::default()
+                }),
+            )];
+            let state = AppState::new(new_logger().await);
+            let app = build_app(
+                endpoints,
+                state,
+                &ServerOptions {
+                    method_override,
+                    ..Default::default()
+                },
+            );
+            let server = TestServer::new(app).unwrap();
+
+            let response = server
+                .post("/mock/default/users")
+                .add_header(
+                    HeaderName::from_static("x-http-method-override"),
+                    HeaderValue::from_static(header),
+                )
+                .await;
+
+            assert_eq!(
+                (expected_status, expected_body),
+                (response.status_code(), response.text().as_str())
+            );
+        }
+    }
+
+    mod base_path {
+        use super::*;
+        use pretty_assertions::assert_eq;
+        use rstest::rstest;
+
+        #[rstest]
+        #[case("/sv")]
+        #[case("sv/")]
+        #[tokio::test]
+        async fn routes_are_nested_under_base_path(#[case] base_path: &str) {
+            let server = new_test_server(ServerOptions {
+                base_path: base_path.to_string(),
+                ..Default::default()
+            })
+            .await;
+
+            assert_eq!(
+                (StatusCode::OK, StatusCode::OK, StatusCode::CREATED),
+                (
+                    server.get("/sv/health").await.status_code(),
+                    server.get("/sv/mock/default/hello").await.status_code(),
+                    server
+                        .post("/sv/session")
+                        .json(&serde_json::json!({ "session": "s1" }))
+                        .await
+                        .status_code(),
+                )
+            );
+            assert_eq!(
+                StatusCode::NOT_FOUND,
+                server.get("/health").await.status_code()
+            );
+        }
+
+        #[tokio::test]
+        async fn routes_are_served_at_root_by_default() {
+            let server = new_test_server(ServerOptions::default()).await;
+
+            assert_eq!(StatusCode::OK, server.get("/health").await.status_code());
+        }
+    }
+
+    mod ready {
+        use super::*;
+        use crate::request_logger::testutil::close;
+        use pretty_assertions::assert_eq;
+
+        #[tokio::test]
+        async fn ok_on_healthy_pool() {
+            let server = new_test_server(ServerOptions::default()).await;
+
+            let response = server.get("/health/ready").await;
+
+            assert_eq!(
+                (StatusCode::OK, serde_json::json!({ "status": "ok" })),
+                (response.status_code(), response.json())
+            );
+        }
+
+        #[tokio::test]
+        async fn unavailable_on_closed_pool() {
+            let logger = new_logger().await;
+            close(&logger).await;
+            let server = TestServer::new(build_app(
+                vec![],
+                AppState::new(logger),
+                &ServerOptions::default(),
+            ))
+            .unwrap();
+
+            let response = server.get("/health/ready").await;
+
+            assert_eq!(
+                (
+                    StatusCode::SERVICE_UNAVAILABLE,
+                    serde_json::json!("unavailable")
+                ),
+                (
+                    response.status_code(),
+                    response.json::<serde_json::Value>()["status"].clone()
+                )
+            );
+        }
+    }
+
+    mod disable_session_api {
+        use super::*;
+        use pretty_assertions::assert_eq;
+
+        #[tokio::test]
+        async fn session_api_is_not_served() {
+            let server = new_test_server(ServerOptions {
+                disable_session_api: true,
+                ..Default::default()
+            })
+            .await;
+
+            assert_eq!(
+                (StatusCode::NOT_FOUND, StatusCode::OK),
+                (
+                    server
+                        .post("/session")
+                        .json(&serde_json::json!({ "session": "s1" }))
+                        .await
+                        .status_code(),
+                    server.get("/mock/default/hello").await.status_code(),
+                )
+            );
+        }
+    }
+
+    mod session_stores {
+        use super::*;
+        use pretty_assertions::assert_eq;
+        use serde_json::{json, Value};
+
+        #[tokio::test]
+        async fn recreated_session_starts_with_initial_document() {
+            let endpoints = [Method::Get, Method::Patch]
+                .into_iter()
+                .map(|method| {
+                    endpoint(
+                        method,
+                        "/profile",
+                        ResponseHandler::MergePatch {
+                            initial: json!({ "name": "alice" }),
+                            documents: Default::default(),
+                        },
+                    )
+                })
+                .collect();
+            let state = AppState::new(new_logger().await);
+            let server =
+                TestServer::new(build_app(endpoints, state, &ServerOptions::default())).unwrap();
+            let session = json!({ "session": "s1" });
+
+            server.post("/session").json(&session).await;
+            server
+                .patch("/mock/s1/profile")
+                .json(&json!({ "name": "bob" }))
+                .await;
+            assert_eq!(
+                json!({ "name": "bob" }),
+                server.get("/mock/s1/profile").await.json::<Value>()
+            );
+            server.delete("/session/s1").await;
+            server.post("/session").json(&session).await;
+
+            assert_eq!(
+                json!({ "name": "alice" }),
+                server.get("/mock/s1/profile").await.json::<Value>()
+            );
+        }
+
+        #[tokio::test]
+        async fn recreated_session_starts_with_empty_resource() {
+            let endpoints = vec![endpoint(
+                Method::Get,
+                "/users",
+                ResponseHandler::Resource {
+                    collection: "users".to_string(),
+                    collections: Default::default(),
+                },
+            )];
+            let state = AppState::new(new_logger().await);
+            let server =
+                TestServer::new(build_app(endpoints, state, &ServerOptions::default())).unwrap();
+            let session = json!({ "session": "s1" });
+
+            server.post("/session").json(&session).await;
+            server
+                .post("/mock/s1/users")
+                .json(&json!({ "name": "bob" }))
+                .await;
+            server.delete("/session/s1").await;
+            server.post("/session").json(&session).await;
+
+            assert_eq!(
+                json!([]),
+                server.get("/mock/s1/users").await.json::<Value>()
+            );
+        }
+    }
+
+    mod fallback_upstream {
+        use super::*;
+        use axum::http::Uri;
+        use pretty_assertions::assert_eq;
+
+        async fn spawn_upstream() -> String {
+            let app = Router::new().route(
+                "/users/:id",
+                get(|uri: Uri| async move {
+                    (
+                        StatusCode::ACCEPTED,
+                        [(header::CONTENT_TYPE, "text/plain")],
+                        format!("upstream {}", uri),
+                    )
+                }),
+            );
+            let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+            let addr = listener.local_addr().unwrap();
+            tokio::spawn(async move { axum::serve(listener, app).await });
+            format!("http://{}", addr)
+        }
+
+        #[tokio::test]
+        async fn unmatched_mock_route_is_forwarded_and_logged() {
+            let logger = new_logger().await;
+            logger.create_session("mysession").await.unwrap();
+            let state = AppState::new(logger);
+            let server = TestServer::new(build_app(
+                vec![],
+                state.clone(),
+                &ServerOptions {
+                    fallback_upstream: Some(spawn_upstream().await),
+                    ..Default::default()
+                },
+            ))
+            .unwrap();
+
+            let response = server
+                .get("/mock/mysession/users/1")
+                .add_query_param("q", "v")
+                .await;
+
+            assert_eq!(
+                (StatusCode::ACCEPTED, "upstream /users/1?q=v".to_string()),
+                (response.status_code(), response.text())
+            );
+            let history = state.logger.get_session_history("mysession").await.unwrap();
+            assert_eq!(
+                vec![(
+                    "/users/1".to_string(),
+                    202,
+                    Some("upstream /users/1?q=v".to_string()),
+                    Some("text/plain".to_string())
+                )],
+                history
+                    .into_iter()
+                    .map(|log| (
+                        log.path,
+                        log.status,
+                        log.response_body,
+                        log.response_content_type
+                    ))
+                    .collect::<Vec<_>>()
+            );
+        }
+
+        #[tokio::test]
+        async fn unmatched_route_is_not_forwarded_outside_mock() {
+            let server = new_test_server(ServerOptions {
+                fallback_upstream: Some("http://127.0.0.1:1".to_string()),
+                ..Default::default()
+            })
+            .await;
+
+            assert_eq!(
+                StatusCode::NOT_FOUND,
+                server.get("/users/1").await.status_code()
+            );
+        }
+    }
+
+    mod max_concurrent_requests {
+        use super::*;
+        use pretty_assertions::assert_eq;
+        use rstest::rstest;
+        use std::time::Instant;
+
+        #[rstest]
+        #[case::queued(false, vec![200, 200, 200], 600)]
+        #[case::shed(true, vec![200, 503, 503], 200)]
+        #[tokio::test]
+        async fn limits_slow_requests(
+            #[case] load_shed: bool,
+            #[case] expected_statuses: Vec<u16>,
+            #[case] expected_min_elapsed_ms: u128,
+        ) {
+            let endpoints = vec![endpoint(
+                Method::Get,
+                "/slow",
+                ResponseHandler::Static(StaticResponse {
+                    status: 200,
+                    delay_ms: Some(200),
+                    ..Default::default()
+                }),
+            )];
+            let logger = new_logger().await;
+            logger.create_session("123").await.unwrap();
+            let options = ServerOptions {
+                max_concurrent_requests: Some(1),
+                load_shed,
+                ..Default::default()
+            };
+            let app = build_app(endpoints, AppState::new(logger), &options);
+
+            let started = Instant::now();
+            let responses = futures::future::join_all((0..3).map(|_| {
+                app.clone().oneshot(
+                    Request::builder()
+                        .uri("/mock/123/slow")
+                        .body(axum::body::Body::empty())
+                        .unwrap(),
+                )
+            }))
+            .await;
+            let elapsed = started.elapsed().as_millis();
+
+            let mut statuses = responses
+                .iter()
+                .map(|response| response.as_ref().unwrap().status().as_u16())
+                .collect::<Vec<_>>();
+            statuses.sort();
+            assert_eq!(expected_statuses, statuses);
+            assert!(elapsed >= expected_min_elapsed_ms, "elapsed: {}ms", elapsed);
+        }
+    }
+}
@@ -1,16 +1,24 @@
+use std::borrow::Cow;
+
 use axum::{
-    extract::{Json, Path, State},
-    http::StatusCode,
-    response::IntoResponse,
+    extract::{Json, Path, Query, State},
+    http::{header, HeaderMap, StatusCode},
+    response::{Html, IntoResponse, Response},
     routing::{delete, get, post},
     Router,
 };
+use indexmap::IndexMap;
 use once_cell::sync::Lazy;
 use regex::Regex;
+use serde_json::Value;
 
 use crate::{
-    request_logger::{LoggerError, RequestLog},
-    response::{error_response, success_response, WithError},
+    config,
+    method::Method,
+    mock_endpoint::MockEndpoint,
+    request_logger::{DuplicateGroup, LoggerError, RequestLog, RequestLogger, SessionRequestLog},
+    response::{error_response, success_response},
+    response_handler::{ResponseHandler, StaticResponse, HTTP_CLIENT},
     state::AppState,
 };
 
@@ -18,6 +26,16 @@ pub fn route_session_to(app: Router<AppState>) -> Router<AppState> {
     app.route("/session", post(create_session))
         .route("/session/:session", get(get_session))
         .route("/session/:session", delete(delete_session))
+        .route("/session/:session/csv", get(get_session_csv))
+        .route(
+            "/session/:session/history/:id/assert-body",
+            post(assert_history_body),
+        )
+        .route("/session/:session/history/:id/replay", post(replay_history))
+        .route("/session/:session/assert-absent", get(assert_absent))
+        .route("/session/:session/duplicates", get(get_duplicates))
+        .route("/history", get(get_all_history))
+        .route("/export/config", get(export_config))
 }
 
 #[derive(serde::Deserialize)]
@@ -32,6 +50,32 @@ struct CreateResBody {
 
 static SESSION_NAME_REGEX: Lazy<Regex> = Lazy::new(|| Regex::new(r"^[-a-zA-Z0-9_]+$").unwrap());
 
+/// Creates the sessions declared in the config file before serving. Sessions which exist in the
+/// database, such as ones kept in a database file by an earlier run, are left as they are.
+pub async fn seed_sessions(logger: &RequestLogger, sessions: &[String]) -> Result<(), String> {
+    for (i, session) in sessions.iter().enumerate() {
+        if !SESSION_NAME_REGEX.is_match(session) {
+            return Err(format!(
+                "session \"{}\": session name should contains only alphanumeric, hyphen or underscore",
+                session
+            ));
+        }
+        if sessions[..i].contains(session) {
+            return Err(format!("session \"{}\" already exists", session));
+        }
+
+        logger
+            .ensure_session(session)
+            .await
+            .map_err(|err| match err {
+                LoggerError::InvalidSession(message) | LoggerError::InternalError(message) => {
+                    message
+                }
+            })?;
+    }
+    Ok(())
+}
+
 async fn create_session(
     State(state): State<AppState>,
     Json(CreateReqBody { session }): Json<CreateReqBody>,
@@ -57,12 +101,392 @@ struct GetResBody {
     histories: Vec<RequestLog>,
 }
 
+#[derive(serde::Deserialize)]
+struct GetQuery {
+    path_pattern: Option<String>,
+    correlation_id: Option<String>,
+    name: Option<String>,
+}
+
+impl GetQuery {
+    fn matches(&self, history: &RequestLog) -> bool {
+        self.path_pattern
+            .as_ref()
+            .is_none_or(|pattern| &history.path_pattern == pattern)
+            && self
+                .correlation_id
+                .as_ref()
+                .is_none_or(|id| history.correlation_id.as_ref() == Some(id))
+            && self
+                .name
+                .as_ref()
+                .is_none_or(|name| history.name.as_ref() == Some(name))
+    }
+}
+
 async fn get_session(
     State(state): State<AppState>,
     Path(session): Path<String>,
-) -> (StatusCode, Json<WithError<GetResBody>>) {
+    Query(query): Query<GetQuery>,
+    headers: HeaderMap,
+) -> Response {
+    let histories = state
+        .logger
+        .get_session_history(&session)
+        .await
+        .map(|histories| {
+            histories
+                .into_iter()
+                .filter(|history| query.matches(history))
+                .collect::<Vec<_>>()
+        });
+    match histories {
+        Ok(histories) if prefers_html(&headers) => {
+            Html(render_histories_html(&session, &histories)).into_response()
+        }
+        Ok(histories) => success_response(StatusCode::OK, GetResBody { histories }).into_response(),
+        Err(LoggerError::InvalidSession(message)) => {
+            error_response::<GetResBody>(StatusCode::NOT_FOUND, message).into_response()
+        }
+        Err(LoggerError::InternalError(message)) => {
+            error_response::<GetResBody>(StatusCode::INTERNAL_SERVER_ERROR, message).into_response()
+        }
+    }
+}
+
+#[derive(serde::Serialize)]
+struct GetAllResBody {
+    histories: Vec<SessionRequestLog>,
+}
+
+async fn get_all_history(State(state): State<AppState>, Query(query): Query<GetQuery>) -> Response {
+    match state.logger.get_all_history().await {
+        Ok(histories) => {
+            let histories = histories
+                .into_iter()
+                .filter(|history| query.matches(&history.log))
+                .collect();
+            success_response(StatusCode::OK, GetAllResBody { histories }).into_response()
+        }
+        Err(LoggerError::InvalidSession(message)) | Err(LoggerError::InternalError(message)) => {
+            error_response::<GetAllResBody>(StatusCode::INTERNAL_SERVER_ERROR, message)
+                .into_response()
+        }
+    }
+}
+
+#[derive(serde::Deserialize)]
+struct ExportQuery {
+    session: Option<String>,
+}
+
+// each method and path is pinned to the latest response observed for it. The body is only known
+// for requests forwarded to the fallback upstream, so mocked ones are left out, as they cannot be
+// reproduced. They are served by the config of this server anyway.
+async fn export_config(
+    State(state): State<AppState>,
+    Query(query): Query<ExportQuery>,
+) -> Response {
+    let histories = match &query.session {
+        Some(session) => state.logger.get_session_history(session).await,
+        None => state
+            .logger
+            .get_all_history()
+            .await
+            .map(|histories| histories.into_iter().map(|history| history.log).collect()),
+    };
+    let histories = match histories {
+        Ok(histories) => histories,
+        Err(LoggerError::InvalidSession(message)) => {
+            return error_response::<()>(StatusCode::NOT_FOUND, message).into_response()
+        }
+        Err(LoggerError::InternalError(message)) => {
+            return error_response::<()>(StatusCode::INTERNAL_SERVER_ERROR, message).into_response()
+        }
+    };
+
+    let mut endpoints = IndexMap::new();
+    for history in histories {
+        let Some(body) = history.response_body else {
+            continue;
+        };
+        let headers = history
+            .response_content_type
+            .map(|content_type| IndexMap::from([("Content-Type".to_string(), content_type)]))
+            .unwrap_or_default();
+        let response = ResponseHandler::Static(StaticResponse {
+            status: history.status,
+            headers,
+            body: body.into(),
+            ..Default::default()
+        });
+        endpoints.insert(
+            (history.method.clone(), history.path.clone()),
+            MockEndpoint {
+                method: history.method,
+                path: history.path,
+                host: None,
+                name: None,
+                response,
+            },
+        );
+    }
+
+    match config::export_config(&endpoints.into_values().collect::<Vec<_>>()) {
+        Ok(yaml) => (
+            StatusCode::OK,
+            [(header::CONTENT_TYPE, "application/yaml")],
+            yaml,
+        )
+            .into_response(),
+        Err(message) => {
+            error_response::<()>(StatusCode::INTERNAL_SERVER_ERROR, message).into_response()
+        }
+    }
+}
+
+// true when the Accept header gives text/html a higher quality than application/json
+fn prefers_html(headers: &HeaderMap) -> bool {
+    let media_ranges: Vec<(&str, f32)> = headers
+        .get_all(header::ACCEPT)
+        .iter()
+        .filter_map(|value| value.to_str().ok())
+        .flat_map(|value| value.split(','))
+        .map(|range| {
+            let mut params = range.split(';').map(str::trim);
+            let media_type = params.next().unwrap_or_default();
+            let quality = params
+                .find_map(|param| param.strip_prefix("q="))
+                .and_then(|q| q.parse().ok())
+                .unwrap_or(1.0);
+            (media_type, quality)
+        })
+        .collect();
+
+    let quality_of = |media_type: &str| {
+        let (main_type, _) = media_type.split_once('/').unwrap();
+        let wildcard = format!("{}/*", main_type);
+        [media_type, wildcard.as_str(), "*/*"]
+            .iter()
+            .find_map(|candidate| {
+                media_ranges
+                    .iter()
+                    .find(|(range, _)| range.eq_ignore_ascii_case(candidate))
+                    .map(|(_, quality)| *quality)
+            })
+            .unwrap_or(0.0)
+    };
+
+    quality_of("text/html") > quality_of("application/json")
+}
+
+fn render_histories_html(session: &str, histories: &[RequestLog]) -> String {
+    let rows = histories
+        .iter()
+        .map(|history| {
+            let pairs = |map: &IndexMap<String, String>| {
+                map.iter()
+                    .map(|(name, value)| format!("{}: {}", escape_html(name), escape_html(value)))
+                    .collect::<Vec<_>>()
+                    .join("<br>")
+            };
+            format!(
+                "<tr><td>{}</td><td>{}</td><td>{}</td><td>{}</td><td>{}</td><td>{}</td><td><pre>{}</pre></td></tr>\n",
+                escape_html(&history.requested_at.to_rfc3339()),
+                history.method,
+                escape_html(&history.path),
+                history.status,
+                pairs(&history.query),
+                pairs(&history.headers),
+                escape_html(&history.body),
+            )
+        })
+        .collect::<String>();
+
+    format!(
+        r#"<!DOCTYPE html>
+<html>
+<head><meta charset="utf-8"><title>serverify: {session}</title></head>
+<body>
+<h1>{session}</h1>
+<table border="1">
+<tr><th>requested_at</th><th>method</th><th>path</th><th>status</th><th>query</th><th>headers</th><th>body</th></tr>
+{rows}</table>
+</body>
+</html>
+"#,
+        session = escape_html(session),
+        rows = rows,
+    )
+}
+
+fn escape_html(value: &str) -> String {
+    value
+        .replace('&', "&amp;")
+        .replace('<', "&lt;")
+        .replace('>', "&gt;")
+        .replace('"', "&quot;")
+        .replace('\'', "&#39;")
+}
+
+async fn get_session_csv(State(state): State<AppState>, Path(session): Path<String>) -> Response {
     match state.logger.get_session_history(&session).await {
-        Ok(histories) => success_response(StatusCode::OK, GetResBody { histories }),
+        Ok(histories) => {
+            let rows = histories.iter().map(|history| {
+                [
+                    history.method.to_string(),
+                    history.path.clone(),
+                    history.status.to_string(),
+                    history.requested_at.to_rfc3339(),
+                    history.body_size.to_string(),
+                ]
+                .iter()
+                .map(|field| csv_field(field))
+                .collect::<Vec<_>>()
+                .join(",")
+            });
+            let csv = std::iter::once("method,path,status,requested_at,body_size".to_string())
+                .chain(rows)
+                .map(|line| line + "\r\n")
+                .collect::<String>();
+
+            (StatusCode::OK, [(header::CONTENT_TYPE, "text/csv")], csv).into_response()
+        }
+        Err(LoggerError::InvalidSession(message)) => {
+            error_response::<()>(StatusCode::NOT_FOUND, message).into_response()
+        }
+        Err(LoggerError::InternalError(message)) => {
+            error_response::<()>(StatusCode::INTERNAL_SERVER_ERROR, message).into_response()
+        }
+    }
+}
+
+fn csv_field(value: &str) -> Cow<'_, str> {
+    if value.contains([',', '"', '\r', '\n']) {
+        Cow::Owned(format!("\"{}\"", value.replace('"', "\"\"")))
+    } else {
+        Cow::Borrowed(value)
+    }
+}
+
+#[derive(serde::Serialize)]
+struct AssertBodyResBody {
+    matched: bool,
+    diff: Vec<BodyDiff>,
+}
+
+#[derive(serde::Serialize, PartialEq, Debug)]
+struct BodyDiff {
+    path: String,
+    expected: Option<Value>,
+    actual: Option<Value>,
+}
+
+// `id` is the request id assigned to the logged request, as echoed in X-Request-Id
+async fn find_history(
+    state: &AppState,
+    session: &str,
+    id: &str,
+) -> Result<RequestLog, (StatusCode, String)> {
+    let histories = match state.logger.get_session_history(session).await {
+        Ok(histories) => histories,
+        Err(LoggerError::InvalidSession(message)) => return Err((StatusCode::NOT_FOUND, message)),
+        Err(LoggerError::InternalError(message)) => {
+            return Err((StatusCode::INTERNAL_SERVER_ERROR, message))
+        }
+    };
+
+    histories
+        .into_iter()
+        .find(|history| history.request_id.as_deref() == Some(id))
+        .ok_or_else(|| {
+            (
+                StatusCode::NOT_FOUND,
+                format!("request \"{}\" is not found in session \"{}\"", id, session),
+            )
+        })
+}
+
+async fn assert_history_body(
+    State(state): State<AppState>,
+    Path((session, id)): Path<(String, String)>,
+    Json(expected): Json<Value>,
+) -> impl IntoResponse {
+    let history = match find_history(&state, &session, &id).await {
+        Ok(history) => history,
+        Err((status, message)) => return error_response(status, message),
+    };
+
+    // a body which is not JSON is compared as a string
+    let actual = serde_json::from_str::<Value>(&history.body)
+        .unwrap_or_else(|_| Value::String(history.body.clone()));
+    let mut diff = vec![];
+    diff_json("$", &expected, &actual, &mut diff);
+
+    success_response(
+        StatusCode::OK,
+        AssertBodyResBody {
+            matched: diff.is_empty(),
+            diff,
+        },
+    )
+}
+
+#[derive(serde::Deserialize)]
+struct AssertAbsentQuery {
+    method: Option<Method>,
+    path: Option<String>,
+}
+
+#[derive(serde::Serialize)]
+struct AssertAbsentResBody {
+    count: usize,
+}
+
+async fn assert_absent(
+    State(state): State<AppState>,
+    Path(session): Path<String>,
+    Query(AssertAbsentQuery { method, path }): Query<AssertAbsentQuery>,
+) -> impl IntoResponse {
+    let histories = match state.logger.get_session_history(&session).await {
+        Ok(histories) => histories,
+        Err(LoggerError::InvalidSession(message)) => {
+            return error_response(StatusCode::NOT_FOUND, message)
+        }
+        Err(LoggerError::InternalError(message)) => {
+            return error_response(StatusCode::INTERNAL_SERVER_ERROR, message)
+        }
+    };
+
+    let count = histories
+        .iter()
+        .filter(|history| {
+            method
+                .as_ref()
+                .is_none_or(|method| &history.method == method)
+        })
+        .filter(|history| path.as_ref().is_none_or(|path| &history.path == path))
+        .count();
+    let status = if count == 0 {
+        StatusCode::OK
+    } else {
+        StatusCode::CONFLICT
+    };
+
+    success_response(status, AssertAbsentResBody { count })
+}
+
+#[derive(serde::Serialize)]
+struct DuplicatesResBody {
+    duplicates: Vec<DuplicateGroup>,
+}
+
+async fn get_duplicates(
+    State(state): State<AppState>,
+    Path(session): Path<String>,
+) -> impl IntoResponse {
+    match state.logger.get_session_duplicates(&session).await {
+        Ok(duplicates) => success_response(StatusCode::OK, DuplicatesResBody { duplicates }),
         Err(LoggerError::InvalidSession(message)) => error_response(StatusCode::NOT_FOUND, message),
         Err(LoggerError::InternalError(message)) => {
             error_response(StatusCode::INTERNAL_SERVER_ERROR, message)
@@ -70,6 +494,107 @@ async fn get_session(
     }
 }
 
+#[derive(serde::Deserialize)]
+struct ReplayReqBody {
+    target_base_url: String,
+}
+
+#[derive(serde::Serialize)]
+struct ReplayResBody {
+    status: u16,
+    body: String,
+}
+
+async fn replay_history(
+    State(state): State<AppState>,
+    Path((session, id)): Path<(String, String)>,
+    Json(ReplayReqBody { target_base_url }): Json<ReplayReqBody>,
+) -> impl IntoResponse {
+    let history = match find_history(&state, &session, &id).await {
+        Ok(history) => history,
+        Err((status, message)) => return error_response(status, message),
+    };
+
+    let url = format!("{}{}", target_base_url.trim_end_matches('/'), history.path);
+    // the length is recomputed from the body, and other hop-by-hop headers are for the original
+    // connection only
+    let headers = history.headers.iter().filter(|(name, _)| {
+        !["host", "content-length", "connection", "transfer-encoding"]
+            .iter()
+            .any(|hop| hop.eq_ignore_ascii_case(name))
+    });
+    let request = headers.fold(
+        HTTP_CLIENT
+            .request(
+                history.method.to_string().to_uppercase().parse().unwrap(),
+                url,
+            )
+            .query(&history.query),
+        |request, (name, value)| request.header(name, value),
+    );
+
+    let result = match request.body(history.body).send().await {
+        Ok(response) => {
+            let status = response.status().as_u16();
+            response.text().await.map(|body| (status, body))
+        }
+        Err(err) => Err(err),
+    };
+    match result {
+        Ok((status, body)) => success_response(StatusCode::OK, ReplayResBody { status, body }),
+        Err(err) => error_response(StatusCode::BAD_GATEWAY, err.to_string()),
+    }
+}
+
+// object keys are compared regardless of their order, while array elements are compared by index
+fn diff_json(path: &str, expected: &Value, actual: &Value, diff: &mut Vec<BodyDiff>) {
+    match (expected, actual) {
+        (Value::Object(expected), Value::Object(actual)) => {
+            for (key, expected_value) in expected {
+                let child = format!("{}.{}", path, key);
+                match actual.get(key) {
+                    Some(actual_value) => diff_json(&child, expected_value, actual_value, diff),
+                    None => diff.push(BodyDiff {
+                        path: child,
+                        expected: Some(expected_value.clone()),
+                        actual: None,
+                    }),
+                }
+            }
+            for (key, actual_value) in actual {
+                if !expected.contains_key(key) {
+                    diff.push(BodyDiff {
+                        path: format!("{}.{}", path, key),
+                        expected: None,
+                        actual: Some(actual_value.clone()),
+                    });
+                }
+            }
+        }
+        (Value::Array(expected), Value::Array(actual)) => {
+            for i in 0..expected.len().max(actual.len()) {
+                let child = format!("{}[{}]", path, i);
+                match (expected.get(i), actual.get(i)) {
+                    (Some(expected_value), Some(actual_value)) => {
+                        diff_json(&child, expected_value, actual_value, diff)
+                    }
+                    (expected_value, actual_value) => diff.push(BodyDiff {
+                        path: child,
+                        expected: expected_value.cloned(),
+                        actual: actual_value.cloned(),
+                    }),
+                }
+            }
+        }
+        (expected, actual) if expected != actual => diff.push(BodyDiff {
+            path: path.to_string(),
+            expected: Some(expected.clone()),
+            actual: Some(actual.clone()),
+        }),
+        _ => {}
+    }
+}
+
 #[derive(serde::Serialize)]
 struct DeleteResBody {
     session: String,
@@ -80,7 +605,10 @@ async fn delete_session(
     Path(session): Path<String>,
 ) -> impl IntoResponse {
     match state.logger.delete_session(&session).await {
-        Ok(_) => success_response(StatusCode::OK, DeleteResBody { session }),
+        Ok(_) => {
+            state.session_stores.remove_session(&session);
+            success_response(StatusCode::OK, DeleteResBody { session })
+        }
         Err(LoggerError::InvalidSession(message)) => error_response(StatusCode::NOT_FOUND, message),
         Err(LoggerError::InternalError(message)) => {
             error_response(StatusCode::INTERNAL_SERVER_ERROR, message)
@@ -91,7 +619,7 @@ async fn delete_session(
 #[cfg(test)]
 mod tests {
 
-    use crate::{method::Method, request_logger::testutil::new_logger};
+    use crate::{mock_endpoint::testutil::endpoint, request_logger::testutil::new_logger};
 
     use super::*;
     use axum_test::TestServer;
@@ -127,14 +655,26 @@ mod tests {
                     query: indexmap! {
                         "answer".to_string() => "42".to_string(),
                     },
+                    repeated_query: IndexMap::new(),
                     body: r#"{"message":"hello"}"#.to_string(),
+                    content_type: Some("application/json".to_string()),
+                    body_size: 19,
+                    status: 201,
+                    request_id: Some("req-1".to_string()),
+                    correlation_id: None,
+                    response_body: None,
+                    response_content_type: None,
+                    response_headers: indexmap! {},
+                    name: None,
+                    remote_addr: Some("127.0.0.1".to_string()),
+                    path_pattern: "/greet".to_string(),
                     requested_at,
                 },
             )
             .await
             .unwrap();
 
-        let state = AppState { logger };
+        let state = AppState::new(logger);
         (
             TestServer::new(route_session_to(Router::new()).with_state(state.clone())).unwrap(),
             state,
@@ -224,7 +764,19 @@ mod tests {
                         "token": "abc"
                     },
                     "query": {"answer": "42" },
+                    "repeated_query": {},
                     "body": r#"{"message":"hello"}"#,
+                    "content_type": "application/json",
+                    "body_size": 19,
+                    "status": 201,
+                    "request_id": "req-1",
+                    "correlation_id": null,
+                    "response_body": null,
+                    "response_content_type": null,
+                    "response_headers": {},
+                    "name": null,
+                    "remote_addr": "127.0.0.1",
+                    "path_pattern": "/greet",
                     "requested_at": "2024-01-02T03:04:05+09:00"
                 }
             ]
@@ -256,6 +808,620 @@ mod tests {
         );
     }
 
+    #[tokio::test]
+    async fn get_session_with_path_pattern() {
+        let (server, state) = new_test_server_with_default_session().await;
+        for path in ["/users/1", "/users/2"] {
+            state
+                .logger
+                .log_request(
+                    EXIST_SESSION,
+                    &RequestLog {
+                        method: Method::Get,
+                        path: path.to_string(),
+                        headers: indexmap! {},
+                        query: indexmap! {},
+                        repeated_query: IndexMap::new(),
+                        body: "".to_string(),
+                        content_type: None,
+                        body_size: 0,
+                        status: 200,
+                        request_id: None,
+                        correlation_id: None,
+                        response_body: None,
+                        response_content_type: None,
+                        response_headers: indexmap! {},
+                        name: None,
+                        remote_addr: None,
+                        path_pattern: "/users/:id".to_string(),
+                        requested_at: Local::now(),
+                    },
+                )
+                .await
+                .unwrap();
+        }
+
+        let response = server
+            .get(&format!("/session/{}", EXIST_SESSION))
+            .add_query_param("path_pattern", "/users/:id")
+            .await;
+
+        assert_eq!(StatusCode::OK, response.status_code());
+        let paths: Vec<Value> = response.json::<Value>()["histories"]
+            .as_array()
+            .unwrap()
+            .iter()
+            .map(|history| history["path"].clone())
+            .collect();
+        assert_eq!(vec![json!("/users/1"), json!("/users/2")], paths);
+    }
+
+    #[tokio::test]
+    async fn get_all_history() {
+        let (server, state) = new_test_server_with_default_session().await;
+        state.logger.create_session("other_session").await.unwrap();
+        for (session, path, pattern) in [
+            ("other_session", "/users/1", "/users/:id"),
+            (EXIST_SESSION, "/users/2", "/users/:id"),
+        ] {
+            state
+                .logger
+                .log_request(
+                    session,
+                    &RequestLog {
+                        method: Method::Get,
+                        path: path.to_string(),
+                        headers: indexmap! {},
+                        query: indexmap! {},
+                        repeated_query: IndexMap::new(),
+                        body: "".to_string(),
+                        content_type: None,
+                        body_size: 0,
+                        status: 200,
+                        request_id: None,
+                        correlation_id: None,
+                        response_body: None,
+                        response_content_type: None,
+                        response_headers: indexmap! {},
+                        name: None,
+                        remote_addr: None,
+                        path_pattern: pattern.to_string(),
+                        requested_at: Local::now(),
+                    },
+                )
+                .await
+                .unwrap();
+        }
+
+        let response = server.get("/history").await;
+        assert_eq!(StatusCode::OK, response.status_code());
+        let tagged: Vec<(Value, Value)> = response.json::<Value>()["histories"]
+            .as_array()
+            .unwrap()
+            .iter()
+            .map(|history| (history["session"].clone(), history["path"].clone()))
+            .collect();
+        assert_eq!(
+            vec![
+                (json!(EXIST_SESSION), json!("/greet")),
+                (json!("other_session"), json!("/users/1")),
+                (json!(EXIST_SESSION), json!("/users/2")),
+            ],
+            tagged
+        );
+
+        let response = server
+            .get("/history")
+            .add_query_param("path_pattern", "/users/:id")
+            .await;
+        let paths: Vec<Value> = response.json::<Value>()["histories"]
+            .as_array()
+            .unwrap()
+            .iter()
+            .map(|history| history["path"].clone())
+            .collect();
+        assert_eq!(vec![json!("/users/1"), json!("/users/2")], paths);
+    }
+
+    mod assert_absent {
+        use super::*;
+        use pretty_assertions::assert_eq;
+
+        #[rstest]
+        #[case("no request to the path", "get", "/greet", StatusCode::OK, json!({ "count": 0 }))]
+        #[case("no request with the method", "post", "/bye", StatusCode::OK, json!({ "count": 0 }))]
+        #[case("requested", "post", "/greet", StatusCode::CONFLICT, json!({ "count": 1 }))]
+        #[tokio::test]
+        async fn assert_absent(
+            #[case] title: &str,
+            #[case] method: &str,
+            #[case] path: &str,
+            #[case] expected_status_code: StatusCode,
+            #[case] expected_res_body: Value,
+        ) {
+            let (server, _) = new_test_server_with_default_session().await;
+
+            let response = server
+                .get(&format!("/session/{}/assert-absent", EXIST_SESSION))
+                .add_query_param("method", method)
+                .add_query_param("path", path)
+                .await;
+
+            assert_eq!(
+                (expected_status_code, expected_res_body),
+                (response.status_code(), response.json()),
+                "{}: response",
+                title
+            );
+        }
+    }
+
+    mod get_duplicates {
+        use super::*;
+        use pretty_assertions::assert_eq;
+
+        #[tokio::test]
+        async fn success_case() {
+            let (server, state) = new_test_server_with_default_session().await;
+            for path in ["/users/1", "/users/1", "/users/2"] {
+                state
+                    .logger
+                    .log_request(
+                        EXIST_SESSION,
+                        &RequestLog {
+                            method: Method::Get,
+                            path: path.to_string(),
+                            headers: indexmap! {},
+                            query: indexmap! {},
+                            repeated_query: IndexMap::new(),
+                            body: "".to_string(),
+                            content_type: None,
+                            body_size: 0,
+                            status: 200,
+                            request_id: None,
+                            correlation_id: None,
+                            response_body: None,
+                            response_content_type: None,
+                            response_headers: indexmap! {},
+                            name: None,
+                            remote_addr: None,
+                            path_pattern: "/users/:id".to_string(),
+                            requested_at: Local::now(),
+                        },
+                    )
+                    .await
+                    .unwrap();
+            }
+
+            let response = server
+                .get(&format!("/session/{}/duplicates", EXIST_SESSION))
+                .await;
+
+            assert_eq!(StatusCode::OK, response.status_code());
+            let body = response.json::<Value>();
+            let duplicates = body["duplicates"].as_array().unwrap();
+            assert_eq!(1, duplicates.len());
+            assert_eq!(
+                (json!("get"), json!("/users/1"), json!(""), json!(2)),
+                (
+                    duplicates[0]["method"].clone(),
+                    duplicates[0]["path"].clone(),
+                    duplicates[0]["body"].clone(),
+                    duplicates[0]["count"].clone()
+                )
+            );
+            assert_eq!(2, duplicates[0]["histories"].as_array().unwrap().len());
+        }
+
+        #[tokio::test]
+        async fn when_session_does_not_exist() {
+            let (server, _) = new_test_server_with_default_session().await;
+
+            let response = server.get("/session/undefined_session/duplicates").await;
+
+            assert_eq!(
+                (
+                    StatusCode::NOT_FOUND,
+                    json!({ "serverify_error": { "message": "session \"undefined_session\" is not found" } })
+                ),
+                (response.status_code(), response.json())
+            );
+        }
+    }
+
+    mod export_config {
+        use super::*;
+        use pretty_assertions::assert_eq;
+
+        #[tokio::test]
+        async fn success_case() {
+            let (server, state) = new_test_server_with_default_session().await;
+            state
+                .logger
+                .log_request(
+                    EXIST_SESSION,
+                    &RequestLog {
+                        method: Method::Get,
+                        path: "/users/1".to_string(),
+                        headers: indexmap! {},
+                        query: indexmap! {},
+                        repeated_query: IndexMap::new(),
+                        body: "".to_string(),
+                        content_type: None,
+                        body_size: 0,
+                        status: 200,
+                        request_id: None,
+                        correlation_id: None,
+                        response_body: Some(r#"{"id":1}"#.to_string()),
+                        response_content_type: Some("application/json".to_string()),
+                        response_headers: indexmap! {},
+                        name: None,
+                        remote_addr: None,
+                        path_pattern: "".to_string(),
+                        requested_at: Local::now(),
+                    },
+                )
+                .await
+                .unwrap();
+
+            let response = server
+                .get("/export/config")
+                .add_query_param("session", EXIST_SESSION)
+                .await;
+
+            // `POST /greet` logged by `new_test_server_with_default_session` was mocked, so
+            // its body is not known
+            assert_eq!(StatusCode::OK, response.status_code());
+            assert_eq!(
+                vec![endpoint(
+                    Method::Get,
+                    "/users/1",
+                    ResponseHandler::Static(StaticResponse {
+                        status: 200,
+                        headers: indexmap! {
+                            "Content-Type".to_string() => "application/json".to_string(),
+                        },
+                        body: r#"{"id":1}"#.into(),
+                        ..Default::default()
+                    })
+                ),],
+                config::parse_config(&response.text()).unwrap().endpoints
+            );
+        }
+
+        #[tokio::test]
+        async fn when_session_does_not_exist() {
+            let (server, _) = new_test_server_with_default_session().await;
+
+            let response = server
+                .get("/export/config")
+                .add_query_param("session", "undefined_session")
+                .await;
+
+            assert_eq!(
+                (
+                    StatusCode::NOT_FOUND,
+                    json!({ "serverify_error": { "message": "session \"undefined_session\" is not found" } })
+                ),
+                (response.status_code(), response.json())
+            );
+        }
+    }
+
+    mod assert_history_body {
+        use super::*;
+        use pretty_assertions::assert_eq;
+
+        #[rstest]
+        #[case(
+            "exact match",
+            "req-1",
+            json!({ "message": "hello" }),
+            StatusCode::OK,
+            json!({ "matched": true, "diff": [] })
+        )]
+        #[case(
+            "mismatch",
+            "req-1",
+            json!({ "message": "goodbye", "count": 1 }),
+            StatusCode::OK,
+            json!({
+                "matched": false,
+                "diff": [
+                    { "path": "$.message", "expected": "goodbye", "actual": "hello" },
+                    { "path": "$.count", "expected": 1, "actual": null },
+                ]
+            })
+        )]
+        #[case(
+            "request is not found",
+            "req-2",
+            json!({}),
+            StatusCode::NOT_FOUND,
+            json!({ "serverify_error": { "message": "request \"req-2\" is not found in session \"exist_session\"" } })
+        )]
+        #[tokio::test]
+        async fn assert_body(
+            #[case] title: &str,
+            #[case] id: &str,
+            #[case] expected: Value,
+            #[case] expected_status_code: StatusCode,
+            #[case] expected_res_body: Value,
+        ) {
+            let (server, _) = new_test_server_with_default_session().await;
+
+            let response = server
+                .post(&format!(
+                    "/session/{}/history/{}/assert-body",
+                    EXIST_SESSION, id
+                ))
+                .json(&expected)
+                .await;
+
+            assert_eq!(
+                (expected_status_code, expected_res_body),
+                (response.status_code(), response.json()),
+                "{}",
+                title
+            );
+        }
+
+        #[test]
+        fn key_order_is_ignored() {
+            let mut diff = vec![];
+            diff_json(
+                "$",
+                &json!({ "a": 1, "b": [1, { "c": 2, "d": 3 }] }),
+                &json!({ "b": [1, { "d": 3, "c": 2 }], "a": 1 }),
+                &mut diff,
+            );
+
+            assert_eq!(Vec::<BodyDiff>::new(), diff);
+        }
+    }
+
+    mod replay_history {
+        use super::*;
+        use axum::{body::Bytes, http::Uri};
+        use pretty_assertions::assert_eq;
+
+        // echoes what it received so that the reconstructed request can be checked
+        async fn spawn_upstream() -> String {
+            let app = Router::new().fallback(
+                |method: axum::http::Method, uri: Uri, headers: HeaderMap, body: Bytes| async move {
+                    (
+                        StatusCode::ACCEPTED,
+                        format!(
+                            "{} {} token={} {}",
+                            method,
+                            uri,
+                            headers["token"].to_str().unwrap(),
+                            String::from_utf8_lossy(&body)
+                        ),
+                    )
+                },
+            );
+            let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+            let addr = listener.local_addr().unwrap();
+            tokio::spawn(async move { axum::serve(listener, app).await });
+            format!("http://{}", addr)
+        }
+
+        #[tokio::test]
+        async fn replays_logged_request() {
+            let (server, _) = new_test_server_with_default_session().await;
+            let target = spawn_upstream().await;
+
+            let response = server
+                .post(&format!("/session/{}/history/req-1/replay", EXIST_SESSION))
+                .json(&json!({ "target_base_url": target }))
+                .await;
+
+            assert_eq!(
+                (
+                    StatusCode::OK,
+                    json!({
+                        "status": 202,
+                        "body": r#"POST /greet?answer=42 token=abc {"message":"hello"}"#,
+                    })
+                ),
+                (response.status_code(), response.json())
+            );
+        }
+
+        #[tokio::test]
+        async fn when_request_is_not_found() {
+            let (server, _) = new_test_server_with_default_session().await;
+
+            let response = server
+                .post(&format!("/session/{}/history/req-2/replay", EXIST_SESSION))
+                .json(&json!({ "target_base_url": "http://localhost:1" }))
+                .await;
+
+            assert_eq!(StatusCode::NOT_FOUND, response.status_code());
+        }
+    }
+
+    mod seed_sessions {
+        use super::*;
+        use pretty_assertions::assert_eq;
+
+        #[tokio::test]
+        async fn seeded_session_is_queryable() {
+            let logger = new_logger().await;
+            seed_sessions(&logger, &["seeded".to_string()])
+                .await
+                .unwrap();
+            let server =
+                TestServer::new(route_session_to(Router::new()).with_state(AppState::new(logger)))
+                    .unwrap();
+
+            let response = server.get("/session/seeded").await;
+
+            assert_eq!(
+                (StatusCode::OK, json!({ "histories": [] })),
+                (response.status_code(), response.json())
+            );
+        }
+
+        #[tokio::test]
+        async fn existing_session_is_kept() {
+            let logger = new_logger().await;
+            logger.create_session("kept").await.unwrap();
+
+            assert_eq!(Ok(()), seed_sessions(&logger, &["kept".to_string()]).await);
+        }
+
+        #[rstest]
+        #[case(
+            vec!["dup", "dup"],
+            "session \"dup\" already exists"
+        )]
+        #[case(
+            vec!["in valid"],
+            "session \"in valid\": session name should contains only alphanumeric, hyphen or underscore"
+        )]
+        #[tokio::test]
+        async fn when_sessions_are_invalid(#[case] sessions: Vec<&str>, #[case] expected: &str) {
+            let logger = new_logger().await;
+            let sessions = sessions.into_iter().map(str::to_string).collect::<Vec<_>>();
+
+            assert_eq!(
+                Err(expected.to_string()),
+                seed_sessions(&logger, &sessions).await
+            );
+        }
+    }
+
+    mod get_session_html {
+        use super::*;
+        use pretty_assertions::assert_eq;
+
+        #[rstest]
+        #[case("text/html", true)]
+        #[case(
+            "text/html,application/xhtml+xml,application/xml;q=0.9,*/*;q=0.8",
+            true
+        )]
+        #[case("application/json", false)]
+        #[case("application/json, text/html;q=0.5", false)]
+        #[case("*/*", false)]
+        #[tokio::test]
+        async fn content_negotiation(#[case] accept: &str, #[case] expected_html: bool) {
+            let (server, _) = new_test_server_with_default_session().await;
+
+            let response = server
+                .get(&format!("/session/{}", EXIST_SESSION))
+                .add_header(
+                    header::ACCEPT,
+                    axum::http::HeaderValue::from_str(accept).unwrap(),
+                )
+                .await;
+
+            assert_eq!(StatusCode::OK, response.status_code());
+            let content_type = response.header("content-type");
+            let content_type = content_type.to_str().unwrap();
+            if expected_html {
+                assert_eq!("text/html; charset=utf-8", content_type);
+                let text = response.text();
+                assert!(
+                    text.contains("<td>post</td><td>/greet</td><td>201</td>"),
+                    "{}",
+                    text
+                );
+                assert!(
+                    text.contains("{&quot;message&quot;:&quot;hello&quot;}"),
+                    "{}",
+                    text
+                );
+            } else {
+                assert_eq!("application/json", content_type);
+                assert_eq!(
+                    1,
+                    response.json::<Value>()["histories"]
+                        .as_array()
+                        .unwrap()
+                        .len()
+                );
+            }
+        }
+    }
+
+    mod get_session_csv {
+        use super::*;
+        use pretty_assertions::assert_eq;
+
+        #[tokio::test]
+        async fn success_case() {
+            let (server, state) = new_test_server_with_default_session().await;
+            state
+                .logger
+                .log_request(
+                    EXIST_SESSION,
+                    &RequestLog {
+                        method: Method::Get,
+                        path: "/search,all".to_string(),
+                        headers: indexmap! {},
+                        query: indexmap! {},
+                        repeated_query: IndexMap::new(),
+                        body: "".to_string(),
+                        content_type: None,
+                        body_size: 0,
+                        status: 404,
+                        request_id: None,
+                        correlation_id: None,
+                        response_body: None,
+                        response_content_type: None,
+                        response_headers: indexmap! {},
+                        name: None,
+                        remote_addr: None,
+                        path_pattern: "/search,all".to_string(),
+                        requested_at: Local
+                            .from_local_datetime(
+                                &NaiveDate::from_ymd_opt(2024, 1, 2)
+                                    .unwrap()
+                                    .and_hms_opt(3, 4, 6)
+                                    .unwrap(),
+                            )
+                            .unwrap(),
+                    },
+                )
+                .await
+                .unwrap();
+
+            let response = server.get(&format!("/session/{}/csv", EXIST_SESSION)).await;
+
+            assert_eq!(
+                (StatusCode::OK, "text/csv"),
+                (
+                    response.status_code(),
+                    response.header("content-type").to_str().unwrap()
+                )
+            );
+            assert_eq!(
+                "method,path,status,requested_at,body_size\r\n\
+                 post,/greet,201,2024-01-02T03:04:05+09:00,19\r\n\
+                 get,\"/search,all\",404,2024-01-02T03:04:06+09:00,0\r\n",
+                response.text()
+            );
+        }
+
+        #[tokio::test]
+        async fn when_session_does_not_exist() {
+            let (server, _) = new_test_server_with_default_session().await;
+
+            let response = server.get("/session/undefined_session/csv").await;
+
+            assert_eq!(
+                (
+                    StatusCode::NOT_FOUND,
+                    json!({ "serverify_error": { "message": "session \"undefined_session\" is not found" } })
+                ),
+                (response.status_code(), response.json()),
+            );
+        }
+    }
+
     #[rstest]
     #[tokio::test]
     #[case(
@@ -1,6 +1,45 @@
-use crate::request_logger::RequestLogger;
+use std::sync::{atomic::AtomicBool, Arc};
+
+use crate::{request_logger::RequestLogger, response_handler::SessionStores};
 
 #[derive(Clone)]
 pub struct AppState {
     pub logger: RequestLogger,
+    pub options: AppOptions,
+    /// Stops recording requests while set, which is toggled via `/admin/logging`.
+    pub logging_paused: Arc<AtomicBool>,
+    /// Data kept per session by mock responses, which is dropped along with the session.
+    pub session_stores: SessionStores,
+}
+
+#[derive(Clone, Default)]
+pub struct AppOptions {
+    pub parse_multipart: bool,
+    /// `Content-Type` of static responses which do not set one.
+    pub default_content_type: Option<String>,
+    /// Records the client in `X-Forwarded-For` instead of the peer address when set.
+    pub trust_forwarded_for: bool,
+    /// Rejects queries with broken percent-encoding or invalid UTF-8 with 400.
+    pub strict_query_parse: bool,
+    /// Records requests to the `default` session, which are skipped otherwise.
+    pub log_default_session: bool,
+    /// Request or response header logged as the correlation id.
+    pub correlation_header: Option<String>,
+    /// Records the headers of responses, which are left out to keep the database small otherwise.
+    pub store_response_headers: bool,
+}
+
+impl AppState {
+    pub fn new(logger: RequestLogger) -> Self {
+        Self {
+            logger,
+            options: AppOptions::default(),
+            logging_paused: Default::default(),
+            session_stores: Default::default(),
+        }
+    }
+
+    pub fn with_options(self, options: AppOptions) -> Self {
+        Self { options, ..self }
+    }
 }
@@ -0,0 +1,160 @@
+use std::{
+    io::Write,
+    net::TcpListener,
+    process::{Child, Command, Output, Stdio},
+    time::Duration,
+};
+
+const BIN: &str = env!("CARGO_BIN_EXE_serverify");
+
+// the server is killed even when an assertion fails
+struct Server(Child);
+
+impl Drop for Server {
+    fn drop(&mut self) {
+        let _ = self.0.kill();
+        let _ = self.0.wait();
+    }
+}
+
+fn free_port() -> u16 {
+    TcpListener::bind("127.0.0.1:0")
+        .unwrap()
+        .local_addr()
+        .unwrap()
+        .port()
+}
+
+fn spawn_with_stdin(args: &[&str], stdin: &str) -> Child {
+    let mut child = Command::new(BIN)
+        .args(args)
+        .stdin(Stdio::piped())
+        .stdout(Stdio::piped())
+        .stderr(Stdio::piped())
+        .spawn()
+        .unwrap();
+    child
+        .stdin
+        .take()
+        .unwrap()
+        .write_all(stdin.as_bytes())
+        .unwrap();
+    child
+}
+
+mod config_from_stdin {
+    use super::*;
+    use pretty_assertions::assert_eq;
+
+    #[tokio::test]
+    async fn serves_endpoints_of_piped_config() {
+        let port = free_port();
+        let config = r#"
+paths:
+  /hello:
+    get:
+      response:
+        status: 200
+        body: "Hello from stdin!"
+"#;
+        let _server = Server(spawn_with_stdin(
+            &["--port", &port.to_string(), "-"],
+            config,
+        ));
+
+        let url = format!("http://127.0.0.1:{}/mock/default/hello", port);
+        let mut response = None;
+        for _ in 0..100 {
+            match reqwest::get(&url).await {
+                Ok(res) => {
+                    response = Some(res);
+                    break;
+                }
+                Err(_) => tokio::time::sleep(Duration::from_millis(100)).await,
+            }
+        }
+        let response = response.expect("server did not start");
+
+        assert_eq!(200, response.status().as_u16());
+        assert_eq!("Hello from stdin!", response.text().await.unwrap());
+    }
+
+    #[test]
+    fn reports_errors_with_stdin_label() {
+        let port = free_port();
+        let child = spawn_with_stdin(&["--port", &port.to_string(), "-"], "paths: [");
+        let Output { status, stderr, .. } = child.wait_with_output().unwrap();
+        let stderr = String::from_utf8(stderr).unwrap();
+
+        assert!(!status.success());
+        assert!(stderr.contains("<stdin>: "), "{}", stderr);
+    }
+}
+
+mod init {
+    use super::*;
+    use pretty_assertions::assert_eq;
+    use serverify::config;
+
+    fn run_init(args: &[&str]) -> Output {
+        let output = Command::new(BIN).arg("init").args(args).output().unwrap();
+        assert!(output.status.success(), "{:?}", output);
+        output
+    }
+
+    #[test]
+    fn writes_config_which_can_be_read() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("serverify.yaml");
+
+        let Output { stdout, .. } = run_init(&[path.to_str().unwrap()]);
+
+        assert_eq!("", String::from_utf8(stdout).unwrap());
+        let config = config::read_config_strict(path.to_str().unwrap(), std::io::empty()).unwrap();
+        assert_eq!(2, config.endpoints.len());
+        assert_eq!(vec!["example".to_string()], config.sessions);
+    }
+
+    #[test]
+    fn refuses_to_overwrite_existing_file() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("serverify.yaml");
+        std::fs::write(&path, "paths: {}\n").unwrap();
+
+        let Output { status, stderr, .. } =
+            Command::new(BIN).arg("init").arg(&path).output().unwrap();
+
+        assert!(!status.success());
+        assert_eq!(
+            format!(
+                "{} already exists; pass --force to overwrite it\n",
+                path.display()
+            ),
+            String::from_utf8(stderr).unwrap()
+        );
+        assert_eq!("paths: {}\n", std::fs::read_to_string(&path).unwrap());
+    }
+
+    #[test]
+    fn overwrites_existing_file_with_force() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("serverify.yaml");
+        std::fs::write(&path, "paths: [").unwrap();
+
+        run_init(&["--force", path.to_str().unwrap()]);
+
+        assert_eq!(
+            config::SAMPLE_CONFIG,
+            std::fs::read_to_string(&path).unwrap()
+        );
+    }
+
+    #[test]
+    fn prints_config_without_path() {
+        let Output { stdout, .. } = run_init(&[]);
+
+        let stdout = String::from_utf8(stdout).unwrap();
+        assert_eq!(config::SAMPLE_CONFIG, stdout);
+        assert!(config::parse_config(&stdout).is_ok());
+    }
+}